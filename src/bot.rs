@@ -1,20 +1,74 @@
 use std::{
+    collections::HashMap,
     convert::Infallible,
+    fmt::Display,
     future::{ready, Future},
     sync::{Arc, LazyLock},
+    time::{Duration, Instant},
 };
 
 use color_eyre::{
-    eyre::{bail, ensure, eyre, ContextCompat},
+    eyre::{bail, ensure, eyre, Context, ContextCompat},
     Result,
 };
+use humantime_serde::re::humantime;
 use sled::Db;
 use teloxide::{
-    dispatching::update_listeners, prelude::*, types::User, utils::command::BotCommands,
+    dispatching::{update_listeners, UpdateHandler},
+    prelude::*,
+    types::{
+        AllowedUpdate, BotCommand, ChatId, ChatMemberKind, ChatMemberUpdated, ChatType,
+        InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputMessageContent,
+        InputMessageContentText, User, UserId,
+    },
+    utils::{
+        command::{BotCommands, ParseError},
+        html,
+    },
+    RequestError,
 };
-use tracing::info;
+use tokio::time::sleep;
+use tracing::{info, info_span, warn, Instrument, Span};
 
-use crate::{catch, send_debug, BotType, Config, Ctx, BOT_INFO};
+use crate::{
+    add_cap_notice, cancel_clear, cancel_revert, catch, check_availability, count_command,
+    dump_raw_keys, emit_event, format_privileges, handle_external_demotion, load_snapshot,
+    missing_privileges, parse_username, record_command, render_diagnosis, render_titles,
+    required_privileges, save_snapshot, schedule_clear, schedule_revert, send_debug,
+    send_debug_event, set_protected, unix_now, BotType, ChatSettings, Config, Cooldown, Ctx,
+    Debouncer, DebugEvent, DemotionPolicy, Outcome, Privilege, TitleDiff, TitleQuery, TitleRecord,
+    TitleStyle, TtlCache, WebhookEvent, BOT_INFO,
+};
+
+/// Timeout of long polling for updates.
+const POLLING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry of startup requests, doubled on each retry.
+const STARTUP_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a command failed due to a transient error can be retried with
+/// `/retry`.
+const RETRY_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Last retryable command failed due to a transient error, of each user in
+/// each chat.
+static FAILED_COMMANDS: LazyLock<TtlCache<(ChatId, UserId), Command>> =
+    LazyLock::new(|| TtlCache::new(RETRY_WINDOW));
+
+/// How often the onboarding reply is sent to the same private chat.
+const ONBOARDING_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// Reply to plain messages sent to the bot in private.
+const ONBOARDING_TEXT: &str = "Hi! I manage custom titles of admins in groups. Add me to your \
+                               group and promote me to admin, then send /title there to set your \
+                               title. Send /help to see all commands.";
+
+/// Private chats recently sent the onboarding reply.
+static ONBOARDED: LazyLock<Cooldown<ChatId>> = LazyLock::new(|| Cooldown::new(ONBOARDING_COOLDOWN));
+
+/// Pending `/title` of each user in each chat, see `title_debounce`.
+static TITLE_EDITS: LazyLock<Debouncer<(ChatId, UserId), (Message, Command)>> =
+    LazyLock::new(Debouncer::default);
 
 #[derive(BotCommands, Debug, Clone)]
 #[command(rename = "lowercase", description = "These commands are supported:")]
@@ -22,21 +76,389 @@ pub enum Command {
     #[command(description = "Display this text.")]
     Help,
     #[command(description = "Display this text.")]
-    Start,
+    Start { payload: String },
     #[command(description = "Change my title.")]
     Title { title: String },
-    #[command(description = "Remove specific title")]
+    #[command(
+        description = "Rename my title: /retitle <old> | <new>",
+        parse_with = "parse_retitle"
+    )]
+    Retitle { old: String, new: String },
+    #[command(description = "Check whether a title is available, without claiming it")]
+    Available { title: String },
+    #[command(description = "Show the rules titles of this chat must follow")]
+    Limits,
+    #[command(description = "Remove specific title, only the owner can remove protected ones")]
     RemoveTitle { title: String },
-    #[command(description = "Get all titles being used")]
-    Titles,
+    #[command(description = "Restore a removed title")]
+    Restore { title: String },
+    #[command(description = "Protect a title so only the owner can remove it")]
+    Protect { title: String },
+    #[command(description = "Stop protecting a title")]
+    Unprotect { title: String },
+    #[command(
+        description = "Get all titles being used: /titles [sort=title|user] [filter=text] \
+                       [show=status]"
+    )]
+    Titles { args: String },
     #[command(description = "Demote me and remove my title")]
     Demote { username: String },
-    #[command(description = "Demote everyone and remove all titles in chat")]
-    Nuke,
+    #[command(description = "Demote everyone and remove all titles in chat: /nuke [reason]")]
+    Nuke { reason: String },
     #[command(description = "Make me anonymous")]
     Anonymous,
+    #[command(description = "Set my title and make me anonymous: /anontitle <title>")]
+    AnonTitle { title: String },
+    #[command(description = "Make me anonymous for a while: /tempanon <duration>, e.g. 1h")]
+    TempAnon { duration: String },
     #[command(description = "Make me un-anonymous")]
     DeAnonymous,
+    #[command(description = "Make all anonymous admins un-anonymous, owner only")]
+    DeAnonAll,
+    #[command(
+        description = "Set how my title is shown in external UIs: /style color=#ff8800 \
+                       badge=gold, empty to unset"
+    )]
+    Style { args: String },
+    #[command(description = "Retry my last title change that failed due to a network error")]
+    Retry,
+    #[command(
+        description = "Show how Telegram sees someone, for debugging: /member @someone or user id"
+    )]
+    Member { username: String },
+    #[command(description = "Export all data stored about me")]
+    MyData,
+    #[command(description = "Remove my title and all data stored about me")]
+    ForgetMe,
+    #[command(description = "Send feedback to the operator of the bot: /feedback <text>")]
+    Feedback { text: String },
+    #[command(description = "List chats the bot is active in (superuser only)")]
+    Chats,
+    #[command(description = "Show or change chat settings: /set [key] [value]")]
+    Set { args: String },
+    #[command(description = "Keep replies for a while: /verbose <duration>, 0s to stop")]
+    Verbose { duration: String },
+    #[command(description = "Check whether my title can be set, without changing it")]
+    TestTitle,
+    #[command(description = "Fix my title if it differs from the record: /resync [@someone]")]
+    Resync { username: String },
+    #[command(description = "Re-apply title prefix and suffix to all existing titles")]
+    ReapplyPrefix,
+    #[command(description = "Save or compare titles: /snapshot save|diff <name>")]
+    Snapshot { args: String },
+    #[command(
+        description = "Clear all titles on an interval: /schedule clear <interval>, e.g. 7days, \
+                       or /schedule cancel (owner only)"
+    )]
+    Schedule { args: String },
+    #[command(description = "Reserve a title for someone: /reserve @username title")]
+    Reserve { args: String },
+    #[command(
+        description = "Reserve many titles, one `@username title` per line, starting right after \
+                       /bulkreserve or in the replied message (owner only)"
+    )]
+    BulkReserve { args: String },
+    #[command(
+        description = "Give a title to everyone listed in the replied message, numbered if \
+                       shared: /bulktitle <title> (owner only)"
+    )]
+    BulkTitle { title: String },
+    #[command(description = "Show or set the title quota: /quota [n|off] (owner only)")]
+    Quota { quota: String },
+    #[command(description = "List reserved titles (owner only)")]
+    Reservations,
+    #[command(description = "Export all titles in this chat as a JSON file (owner only)")]
+    ExportTitles,
+    #[command(
+        description = "List words not allowed in titles of this chat, or manage them: /blocked \
+                       add|remove <word> (owner only)"
+    )]
+    Blocked { args: String },
+    #[command(description = "List who held a title, oldest first (owner only)")]
+    TitleHistory { title: String },
+    #[command(description = "Revoke the reservation of a title (owner only)")]
+    Unreserve { title: String },
+    #[command(description = "Run a self-check of the bot in this chat")]
+    Diagnose,
+    #[command(
+        description = "Verify database integrity: /verify [repair] (superuser only), or check the \
+                       title of someone against the roster: /verify @username"
+    )]
+    Verify { args: String },
+    #[command(description = "Dump raw keys of a chat: /rawkeys <chat_id> [page] (superuser only)")]
+    RawKeys { args: String },
+    #[command(description = "Check and fix the keys of a title in this chat (superuser only)")]
+    Repair { title: String },
+}
+
+impl Command {
+    /// Whether the command needs the real identity of the sender. Commands that
+    /// don't can still be used by anonymous admins that are not tracked.
+    #[must_use]
+    pub const fn requires_identity(&self) -> bool {
+        !matches!(
+            self,
+            Self::Help
+                | Self::Start { .. }
+                | Self::Titles { .. }
+                | Self::Available { .. }
+                | Self::Limits
+                | Self::Diagnose
+                | Self::Feedback { .. }
+                | Self::Chats
+                | Self::Verify { .. }
+                | Self::RawKeys { .. }
+                | Self::Repair { .. }
+                | Self::Retry
+        )
+    }
+
+    /// Whether the command can be safely run again by `/retry`, i.e. it's
+    /// idempotent.
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Title { .. } | Self::AnonTitle { .. } | Self::Anonymous | Self::DeAnonymous
+        )
+    }
+
+    /// Name of the command as typed, e.g. `removetitle`.
+    #[must_use]
+    pub fn name(&self) -> String {
+        format!("{self:?}")
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    }
+
+    /// Whether the command message is deleted after handling, unless the
+    /// command is listed in `keep`.
+    #[must_use]
+    pub fn deletes_message(&self, keep: &[String]) -> bool {
+        !keep.contains(&self.name())
+    }
+
+    /// Names of the arguments taken by the command with given name, following
+    /// the fields of the variant. All arguments are free-form strings.
+    #[must_use]
+    pub fn arguments_of(name: &str) -> &'static [&'static str] {
+        match name.trim_start_matches('/') {
+            "start" => &["payload"],
+            "title" | "available" | "removetitle" | "restore" | "protect" | "unprotect"
+            | "unreserve" | "anontitle" | "bulktitle" | "titlehistory" | "repair" => &["title"],
+            "retitle" => &["old", "new"],
+            "demote" | "resync" | "member" => &["username"],
+            "quota" => &["quota"],
+            "nuke" => &["reason"],
+            "feedback" => &["text"],
+            "verbose" | "tempanon" => &["duration"],
+            "titles" | "set" | "snapshot" | "schedule" | "reserve" | "bulkreserve" | "verify"
+            | "rawkeys" | "style" | "blocked" => &["args"],
+            _ => &[],
+        }
+    }
+
+    /// Privileges the bot must hold to run the command. Commands that change
+    /// members, or set a title to test it, need `min_bot_privileges`,
+    /// informational ones need nothing.
+    #[must_use]
+    pub fn required_bot_privileges(&self) -> &'static [Privilege] {
+        if self.is_mutating() || matches!(self, Self::TestTitle) {
+            &Config::get().min_bot_privileges
+        } else {
+            &[]
+        }
+    }
+
+    /// Whether the command changes members, which is reported to `webhook_url`
+    /// when it succeeds.
+    #[must_use]
+    pub const fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Self::Title { .. }
+                | Self::Retitle { .. }
+                | Self::BulkTitle { .. }
+                | Self::RemoveTitle { .. }
+                | Self::Restore { .. }
+                | Self::Demote { .. }
+                | Self::Nuke { .. }
+                | Self::Anonymous
+                | Self::AnonTitle { .. }
+                | Self::TempAnon { .. }
+                | Self::DeAnonymous
+                | Self::DeAnonAll
+                | Self::ForgetMe
+                | Self::Resync { .. }
+                | Self::ReapplyPrefix
+        )
+    }
+}
+
+/// Split the arguments of `/retitle` into the old and new title, separated by
+/// `|`. Titles may contain spaces, so nothing else separates them. Missing
+/// titles are left empty and reported by [`Ctx::rename_title`], rather than
+/// failing to parse.
+#[allow(clippy::unnecessary_wraps, clippy::needless_pass_by_value)]
+fn parse_retitle(args: String) -> Result<(String, String), ParseError> {
+    let args = args.trim();
+    let (old, new) = args.split_once('|').unwrap_or((args, ""));
+    Ok((old.trim().to_owned(), new.trim().to_owned()))
+}
+
+/// Payload of `/start` passed by [deep links](https://core.telegram.org/bots#deep-linking).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartPayload {
+    /// Plain `/start`
+    Empty,
+    /// Link to set up the bot for given chat, e.g. `t.me/<bot>?start=-100123`
+    Setup(ChatId),
+    /// Payload in unknown format, treated as plain `/start`
+    Unknown,
+}
+
+impl StartPayload {
+    #[must_use]
+    pub fn parse(payload: &str) -> Self {
+        let payload = payload.trim();
+        if payload.is_empty() {
+            return Self::Empty;
+        }
+        payload
+            .parse::<i64>()
+            .map_or(Self::Unknown, |id| Self::Setup(ChatId(id)))
+    }
+}
+
+/// Run `func` until it succeeds, at most `attempts` times, doubling the delay
+/// between attempts starting from `delay`.
+///
+/// # Errors
+/// The error of the last attempt.
+pub async fn retry<T, E, Func, Fut>(
+    attempts: usize,
+    mut delay: Duration,
+    mut func: Func,
+) -> Result<T, E>
+where
+    E: Display,
+    Func: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match func().await {
+            Err(error) if attempt < attempts => {
+                warn!(attempt, %error, ?delay, "Failed, retrying");
+                sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Commands registered to the Telegram menu, with descriptions overridden by
+/// `overrides`, keyed by command name.
+fn menu_commands(overrides: &HashMap<String, String>) -> Vec<BotCommand> {
+    Command::bot_commands()
+        .into_iter()
+        .map(|mut command| {
+            if let Some(description) = overrides.get(command.command.trim_start_matches('/')) {
+                command.description = description.clone();
+            }
+            command
+        })
+        .collect()
+}
+
+/// Split command arguments into the first word and the rest, both trimmed.
+fn split_first_arg(args: &str) -> (&str, &str) {
+    let args = args.trim();
+    args.split_once(char::is_whitespace)
+        .map_or((args, ""), |(first, rest)| (first, rest.trim()))
+}
+
+fn help_text() -> &'static str {
+    static DESC: LazyLock<String> =
+        LazyLock::new(|| html::escape(&Command::descriptions().to_string()));
+    &DESC
+}
+
+fn setup_text(chat_id: ChatId) -> String {
+    format!(
+        "Setting up Golden Axe for chat <code>{chat_id}</code>:\n1. Promote me to admin with \
+         <i>Add new admins</i> and <i>Invite users via link</i> privileges\n2. Members can then \
+         use /title to set their titles\n\n{}",
+        help_text()
+    )
+}
+
+/// Assert the text is valid for the HTML parse mode of Telegram, which rejects
+/// unsupported tags and unescaped `<`, `>` and `&`.
+#[cfg(test)]
+pub(crate) fn assert_html(text: &str) {
+    const TAGS: &[&str] = &[
+        "b",
+        "strong",
+        "i",
+        "em",
+        "u",
+        "ins",
+        "s",
+        "strike",
+        "del",
+        "a",
+        "code",
+        "pre",
+        "span",
+        "tg-spoiler",
+    ];
+    const ENTITIES: &[&str] = &["lt;", "gt;", "amp;", "quot;"];
+
+    let mut rest = text;
+    while let Some(pos) = rest.find(['<', '>', '&']) {
+        rest = &rest[pos..];
+        if rest.starts_with('&') {
+            assert!(
+                ENTITIES.iter().any(|entity| rest[1..].starts_with(entity)),
+                "unescaped `&` in {text:?}"
+            );
+            rest = &rest[1..];
+            continue;
+        }
+        assert!(rest.starts_with('<'), "unescaped `>` in {text:?}");
+        let end = rest
+            .find('>')
+            .unwrap_or_else(|| panic!("unescaped `<` in {text:?}"));
+        let name = rest[1..end]
+            .trim_start_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        assert!(TAGS.contains(&name), "unsupported tag `{name}` in {text:?}");
+        rest = &rest[end + 1..];
+    }
+}
+
+#[test]
+fn test_assert_html() {
+    assert_html("<b>bold</b> &lt;name&gt; &amp; <a href=\"https://t.me\">link</a>");
+    for bad in ["/title <title>", "a & b", "a > b", "<br>"] {
+        assert!(
+            std::panic::catch_unwind(|| assert_html(bad)).is_err(),
+            "{bad}"
+        );
+    }
+}
+
+#[test]
+fn test_help_text_html() {
+    assert_html(help_text());
+    assert_html(&setup_text(ChatId(-1)));
 }
 
 #[test]
@@ -45,9 +467,213 @@ fn test_command() {
     println!("{:#?}", Command::bot_commands());
 }
 
+#[test]
+fn test_requires_identity() {
+    assert!(!Command::Titles {
+        args: String::new()
+    }
+    .requires_identity());
+    assert!(Command::Title {
+        title: "title".to_owned()
+    }
+    .requires_identity());
+    assert!(Command::Anonymous.requires_identity());
+}
+
+#[tokio::test]
+async fn test_retry() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Fails once, then returns the bot user
+    let calls = AtomicUsize::new(0);
+    let res = retry(3, Duration::from_millis(1), || async {
+        match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Err("network blip"),
+            _ => Ok("golden_axe_bot"),
+        }
+    })
+    .await;
+    assert_eq!(res, Ok("golden_axe_bot"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // Gives up after exhausting attempts
+    let calls = AtomicUsize::new(0);
+    let res: Result<(), _> = retry(3, Duration::from_millis(1), || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Err("down")
+    })
+    .await;
+    assert_eq!(res, Err("down"));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_menu_commands() {
+    use teloxide::payloads::SetMyCommands;
+
+    let overrides = HashMap::from([("title".to_owned(), "设置头衔".to_owned())]);
+    let payload = serde_json::to_value(SetMyCommands::new(menu_commands(&overrides))).unwrap();
+    let description = |name: &str| {
+        payload["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|command| command["command"].as_str().unwrap().trim_start_matches('/') == name)
+            .map(|command| command["description"].as_str().unwrap().to_owned())
+    };
+
+    assert_eq!(description("title").as_deref(), Some("设置头衔"));
+    // Built-in descriptions are kept for others
+    assert_eq!(description("help").as_deref(), Some("Display this text."));
+}
+
+#[test]
+fn test_split_first_arg() {
+    assert_eq!(
+        split_first_arg(" @user  Some Title "),
+        ("@user", "Some Title")
+    );
+    assert_eq!(split_first_arg("done_message"), ("done_message", ""));
+    assert_eq!(split_first_arg(""), ("", ""));
+}
+
+#[test]
+fn test_command_span() {
+    use std::{fmt::Debug, sync::Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id},
+        Subscriber,
+    };
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        Layer, Registry,
+    };
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0 += &format!(" {}={:?}", field.name(), value);
+        }
+    }
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            let mut fields = Fields(attrs.metadata().name().to_owned());
+            attrs.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+
+    let spans = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = Registry::default().with(Recorder(spans.clone()));
+    tracing::subscriber::with_default(subscriber, || {
+        let command = Command::Title {
+            title: "secret".to_owned(),
+        };
+        drop(command_span(&command, ChatId(-1), Some(UserId(2))));
+    });
+
+    assert_eq!(
+        spans.lock().unwrap().as_slice(),
+        [r#"command command="Title" chat_id=-1 user_id=Some(2)"#]
+    );
+}
+
+#[test]
+fn test_readiness_text() {
+    assert!(readiness_text(&[]).contains("ready to manage titles"));
+    assert!(
+        readiness_text(&[Privilege::PromoteMembers, Privilege::InviteUsers])
+            .ends_with("<code>promote_members</code>, <code>invite_users</code>")
+    );
+}
+
+#[tokio::test]
+async fn test_edited_message_ignored() {
+    use std::ops::ControlFlow;
+
+    let edited = |chat: &str| -> Update {
+        serde_json::from_str(&format!(
+            r#"{{
+                "update_id": 1,
+                "edited_message": {{
+                    "message_id": 1,
+                    "date": 0,
+                    "edit_date": 0,
+                    "chat": {chat},
+                    "from": {{ "id": 2, "is_bot": false, "first_name": "User" }},
+                    "text": "/title VIP",
+                    "entities": [{{ "type": "bot_command", "offset": 0, "length": 6 }}]
+                }}
+            }}"#
+        ))
+        .unwrap()
+    };
+
+    // Neither run as a command nor answered in private, but passed on to the
+    // default handler. No handler is reached, so no dependency is needed
+    for chat in [
+        r#"{ "id": -1, "type": "supergroup", "title": "Group" }"#,
+        r#"{ "id": 2, "type": "private", "first_name": "User" }"#,
+    ] {
+        assert!(matches!(
+            handler().dispatch(dptree::deps![edited(chat)]).await,
+            ControlFlow::Continue(_)
+        ));
+    }
+}
+
+#[test]
+fn test_start_payload() {
+    assert_eq!(StartPayload::parse(""), StartPayload::Empty);
+    assert_eq!(
+        StartPayload::parse("-1001234567890"),
+        StartPayload::Setup(ChatId(-1_001_234_567_890))
+    );
+    assert_eq!(StartPayload::parse("ref_abc"), StartPayload::Unknown);
+    assert!(setup_text(ChatId(-100)).contains("<code>-100</code>"));
+}
+
+/// Handlers of updates the bot reacts to, anything else goes to the default
+/// handler.
+fn handler() -> UpdateHandler<Infallible> {
+    dptree::entry()
+        .branch(
+            // Only new messages run commands. Edits arrive as separate
+            // `edited_message` updates and are ignored on purpose, so
+            // editing an old message into a command doesn't run it
+            Update::filter_message()
+                .filter(|msg: Message| {
+                    let username = &BOT_INFO.get().expect("Bot info not initialized").1;
+                    accepts_command(Config::get().require_mention, &msg, username)
+                })
+                .filter_command::<Command>()
+                .chain(dptree::endpoint(handle_command)),
+        )
+        .branch(
+            // Anything else sent in private, likely someone looking for
+            // help. Non-command messages in groups are ignored
+            Update::filter_message()
+                .filter(|msg: Message| msg.chat.is_private())
+                .endpoint(handle_private_message),
+        )
+        .branch(Update::filter_inline_query().endpoint(handle_inline_query))
+        .branch(Update::filter_my_chat_member().chain(dptree::endpoint(handle_my_chat_member)))
+        .branch(Update::filter_chat_member().chain(dptree::endpoint(handle_chat_member)))
+}
+
 #[allow(clippy::future_not_send)]
 pub async fn run(bot: BotType, db: sled::Db) -> Result<()> {
-    let me = bot.get_me().await?.user;
+    let me = retry(Config::get().startup_retries, STARTUP_RETRY_DELAY, || {
+        bot.get_me()
+    })
+    .await?
+    .user;
 
     info!(?me, "Bot logged in");
 
@@ -58,7 +684,8 @@ pub async fn run(bot: BotType, db: sled::Db) -> Result<()> {
 
     BOT_INFO.set((me.id, username.to_owned())).unwrap();
 
-    bot.set_my_commands(Command::bot_commands()).await?;
+    bot.set_my_commands(menu_commands(&Config::get().command_descriptions))
+        .await?;
 
     send_debug(&format!(
         "Golden Axe <b>Online</b>, running as @{username} (#{})",
@@ -66,89 +693,491 @@ pub async fn run(bot: BotType, db: sled::Db) -> Result<()> {
     ));
 
     info!("Poll mode");
+    catch!(bot.delete_webhook().await);
 
+    let conf = Config::get();
     let mut deps = DependencyMap::new();
     deps.insert(db);
 
-    Dispatcher::builder(
-        bot.clone(),
-        Update::filter_message()
-            .filter_command::<Command>()
-            .chain(dptree::endpoint(handle_command)),
-    )
-    .default_handler(ignore_update)
-    .dependencies(deps)
-    .build()
-    .setup_ctrlc_handler()
-    .dispatch_with_listener(
-        update_listeners::polling_default(bot).await,
-        LoggingErrorHandler::new(),
-    )
-    .await;
+    Dispatcher::builder(bot.clone(), handler())
+        .default_handler(ignore_update)
+        .dependencies(deps)
+        .build()
+        .setup_ctrlc_handler()
+        .dispatch_with_listener(
+            update_listeners::polling(
+                bot,
+                Some(POLLING_TIMEOUT),
+                None,
+                Some(allowed_updates(conf.on_external_demotion)),
+            ),
+            LoggingErrorHandler::new(),
+        )
+        .await;
 
     Ok(())
 }
 
+/// Updates to poll. Updates of other members are only needed to handle
+/// external demotions.
+fn allowed_updates(on_external_demotion: DemotionPolicy) -> Vec<AllowedUpdate> {
+    let mut allowed = vec![
+        AllowedUpdate::Message,
+        AllowedUpdate::InlineQuery,
+        AllowedUpdate::MyChatMember,
+    ];
+    if on_external_demotion != DemotionPolicy::Keep {
+        allowed.push(AllowedUpdate::ChatMember);
+    }
+    allowed
+}
+
+/// Whether to handle the command in the message. Commands addressed to another
+/// bot, e.g. `/title@other_bot`, never are. With `require_mention`, only
+/// commands addressed to the bot with `username` are, e.g. `/title@username`,
+/// except in private chats where no other bot can be meant.
+///
+/// This is the only place the suffix is checked, as [`BotCommands::parse`]
+/// strips it before arguments reach any handler.
+fn accepts_command(require_mention: bool, msg: &Message, username: &str) -> bool {
+    let addressee = msg
+        .text()
+        .and_then(|text| text.split_whitespace().next())
+        .and_then(|command| command.split_once('@'))
+        .map(|(_, to)| to);
+    match addressee {
+        Some(to) => to.eq_ignore_ascii_case(username),
+        None => !require_mention || msg.chat.is_private(),
+    }
+}
+
+/// Whether the error is likely to go away by itself, e.g. a network error, so
+/// running the same command again may succeed.
+fn is_transient(error: &color_eyre::Report) -> bool {
+    matches!(
+        error.downcast_ref::<RequestError>(),
+        Some(RequestError::Network(_) | RequestError::RetryAfter(_) | RequestError::Io(_))
+    )
+}
+
+/// Remember the command of the sender, as chat and user id, in `failed` if it
+/// failed due to a transient error, so it can be run again with `/retry`.
+fn remember_failure(
+    failed: &TtlCache<(ChatId, UserId), Command>,
+    sender: (ChatId, UserId),
+    command: Command,
+    res: Result<()>,
+) -> Result<()> {
+    match res {
+        Err(error) if command.is_retryable() && is_transient(&error) => {
+            failed.insert(sender, command);
+            bail!("{error}, send /retry to try again")
+        }
+        res => res,
+    }
+}
+
 fn ignore_update(_: Arc<Update>) -> impl Future<Output = ()> {
     ready(())
 }
 
+/// Reply to a non-command message with the onboarding text, at most once per
+/// [`ONBOARDING_COOLDOWN`] in each chat.
+async fn handle_private_message(bot: BotType, msg: Message) -> Result<(), Infallible> {
+    if let Some(reply) = onboarding_reply(&msg)
+        && ONBOARDED.try_start(msg.chat.id)
+    {
+        catch!(bot.send_message(msg.chat.id, reply).await);
+    }
+    Ok(())
+}
+
+/// The onboarding reply to the message, only for text sent in private.
+fn onboarding_reply(msg: &Message) -> Option<&'static str> {
+    (msg.chat.is_private() && msg.text().is_some()).then_some(ONBOARDING_TEXT)
+}
+
+/// Offer to set the title for inline queries like `title VIP`, see
+/// [`inline_title_result`].
+async fn handle_inline_query(bot: BotType, query: InlineQuery) -> Result<(), Infallible> {
+    let username = &BOT_INFO.get().expect("Bot info not initialized").1;
+    let results = inline_title_result(&query, username);
+    catch!(
+        bot.answer_inline_query(query.id, results)
+            .is_personal(true)
+            .cache_time(0)
+            .await
+    );
+    Ok(())
+}
+
+/// The result offered for an inline query like `title VIP`, sent from a group.
+///
+/// Inline queries don't tell which chat they're sent from, so the title cannot
+/// be set right away. Instead, choosing the result sends `/title@<bot> VIP`
+/// into the chat, which is then handled like any other command, with the chat
+/// and the sender known.
+fn inline_title_result(query: &InlineQuery, username: &str) -> Option<InlineQueryResult> {
+    if matches!(
+        query.chat_type,
+        Some(ChatType::Sender | ChatType::Private | ChatType::Channel)
+    ) {
+        return None;
+    }
+    let (command, title) = split_first_arg(query.query.trim());
+    if !command.eq_ignore_ascii_case("title") || title.is_empty() {
+        return None;
+    }
+    let content = InputMessageContentText::new(format!("/title@{username} {title}"));
+    let article = InlineQueryResultArticle::new(
+        "title",
+        format!("Set title to {title}"),
+        InputMessageContent::Text(content),
+    )
+    .description("Sends /title to this chat");
+    Some(InlineQueryResult::Article(article))
+}
+
+/// Post a readiness check when the bot itself gets promoted to admin, and
+/// handle the titles of the chat when the bot is removed.
+async fn handle_my_chat_member(
+    bot: BotType,
+    update: ChatMemberUpdated,
+    db: Db,
+) -> Result<(), Infallible> {
+    let conf = Config::get();
+    if matches!(
+        update.new_chat_member.kind,
+        ChatMemberKind::Left | ChatMemberKind::Banned(_)
+    ) {
+        match forget_removed_chat(&db, update.chat.id, conf.purge_on_removal) {
+            Ok(removed) => info!(chat = ?update.chat.id, removed, "Removed from chat"),
+            Err(error) => send_debug(&error),
+        }
+        return Ok(());
+    }
+    if conf.welcome_check
+        && !update.old_chat_member.is_administrator()
+        && update.new_chat_member.is_administrator()
+    {
+        let settings = ChatSettings::load(&db, update.chat.id).unwrap_or_else(|error| {
+            send_debug(&error);
+            ChatSettings::default()
+        });
+        let required = required_privileges(settings.promote_privileges(&conf.promote_privileges));
+        let missing = missing_privileges(&required, &update.new_chat_member.kind);
+        info!(chat = ?update.chat.id, ?missing, "Promoted to admin");
+        catch!(
+            bot.send_message(update.chat.id, readiness_text(&missing))
+                .await
+        );
+    }
+    Ok(())
+}
+
+/// Handle changes of other members, reacting to admins demoted outside of the
+/// bot as configured by `on_external_demotion`.
+async fn handle_chat_member(
+    bot: BotType,
+    update: ChatMemberUpdated,
+    db: Db,
+) -> Result<(), Infallible> {
+    let me = BOT_INFO.get().expect("Bot info not initialized").0;
+    match handle_external_demotion(&db, &update, me, Config::get().on_external_demotion) {
+        Ok(Some(message)) => catch!(bot.send_message(update.chat.id, message).await),
+        Ok(None) => {}
+        Err(error) => send_debug(&error),
+    }
+    Ok(())
+}
+
+/// Remove all titles of a chat the bot was removed from if `purge` is set,
+/// returning how many were removed. Otherwise they're kept for when the bot is
+/// added back.
+///
+/// # Errors
+/// If the database returns an error.
+fn forget_removed_chat(db: &Db, chat_id: ChatId, purge: bool) -> Result<usize> {
+    if !purge {
+        return Ok(0);
+    }
+    TitleRecord::remove_all_in_chat(db, chat_id)
+}
+
+fn readiness_text(missing: &[Privilege]) -> String {
+    if missing.is_empty() {
+        "Thanks for promoting me! I'm ready to manage titles, use /title to set yours.".to_owned()
+    } else {
+        format!(
+            "Thanks for promoting me! To manage titles I still need these privileges: {}",
+            format_privileges(missing)
+        )
+    }
+}
+
+/// Span covering the handling of a command. Only the command name and ids are
+/// recorded, arguments are left out since they may contain anything.
+fn command_span(command: &Command, chat_id: ChatId, user_id: Option<UserId>) -> Span {
+    let name = format!("{command:?}");
+    let name = name.split_whitespace().next().unwrap_or_default();
+    info_span!(
+        "command",
+        command = name,
+        chat_id = chat_id.0,
+        user_id = ?user_id.map(|id| id.0)
+    )
+}
+
 async fn handle_command(
     bot: BotType,
     msg: Message,
     command: Command,
     db: Db,
+) -> Result<(), Infallible> {
+    // Held back so only the last of rapid edits is applied, while the handler
+    // returns for later updates of the chat to come in. Superseded ones skip
+    // metrics and webhook events, as the applied one replies and is counted
+    // instead. Anonymous admins all send as the same user, so they're not
+    // debounced
+    let window = Config::get().title_debounce;
+    if matches!(&command, Command::Title { title } if !title.is_empty())
+        && msg.sender_chat.is_none()
+        && !window.is_zero()
+        && let Some(user) = msg.from()
+    {
+        let key = (msg.chat.id, user.id);
+        let apply = {
+            let (bot, db) = (bot.clone(), db.clone());
+            move |(msg, command): (Message, Command)| {
+                let span = command_span(&command, msg.chat.id, msg.from().map(|user| user.id));
+                run_command(bot, msg, command, db).instrument(span)
+            }
+        };
+        let superseded = TITLE_EDITS.push(key, (msg, command), window, apply);
+        if let Some((msg, command)) = superseded {
+            let from = msg.from().map(User::full_name);
+            info!(?from, ?command, "Superseded by a later /title, ignored");
+            if command.deletes_message(&Config::get().keep_commands) {
+                Ctx::new(&bot, &msg, &db)
+                    .expect("Command messages should have sender")
+                    .del_msg_delayed();
+            }
+        }
+        return Ok(());
+    }
+
+    let span = command_span(&command, msg.chat.id, msg.from().map(|user| user.id));
+    run_command(bot, msg, command, db).instrument(span).await
+}
+
+async fn run_command(
+    bot: BotType,
+    msg: Message,
+    command: Command,
+    db: Db,
 ) -> Result<(), Infallible> {
     let from = msg.from().map(User::full_name);
     let ctx = Ctx::new(&bot, &msg, &db).expect("Command messages should have sender");
 
+    // Run the last failed command instead, if any
+    let command = match command {
+        Command::Retry => FAILED_COMMANDS
+            .take(&(ctx.chat_id(), ctx.sender_id()))
+            .unwrap_or(Command::Retry),
+        command => command,
+    };
+
     info!(?from, ?command, "Handing");
 
-    catch!(match command {
-        Command::Help | Command::Start => {
-            static DESC: LazyLock<String> = LazyLock::new(|| Command::descriptions().to_string());
-            ctx.reply_to(&*DESC).await
+    let name = format!("{command:?}");
+    let event = command
+        .is_mutating()
+        .then(|| WebhookEvent::new(command.name(), ctx.chat_id(), ctx.sender_id()));
+    let delete_command = command.deletes_message(&Config::get().keep_commands);
+    let started = Instant::now();
+    let res = match command {
+        Command::Help => ctx.reply_to(help_text()).await.map(|()| Outcome::Done),
+        Command::Start { payload } => match StartPayload::parse(&payload) {
+            StartPayload::Setup(chat_id) => ctx.reply_to(setup_text(chat_id)).await,
+            StartPayload::Empty | StartPayload::Unknown => ctx.reply_to(help_text()).await,
+        }
+        .map(|()| Outcome::Done),
+        Command::Feedback { text } => {
+            ctx.handle_light_with(delete_command, |ctx| async move {
+                ctx.send_feedback(&text)?;
+                ctx.reply_text("Thanks, your feedback has been sent to the operator")
+                    .await
+            })
+            .await
+        }
+        Command::Chats => {
+            ctx.handle_light_with(delete_command, |ctx| async move {
+                ctx.assert_superuser()?;
+                let chats = ctx.describe_chats().await?;
+                ctx.reply_to(chats).await
+            })
+            .await
+        }
+        Command::Verify { args } => {
+            ctx.handle_light_with(delete_command, |ctx| async move {
+                let args = args.trim();
+                if !matches!(args, "" | "repair") {
+                    let check = ctx.verify_roster(args).await?;
+                    return ctx.reply_to(check.to_string()).await;
+                }
+                ctx.assert_superuser()?;
+                let report = ctx.verify_db()?;
+                send_debug(&report);
+                if args == "repair" && !report.is_consistent() {
+                    ctx.repair_db(&report.inconsistencies)?;
+                    ctx.reply_text(&format!(
+                        "Repaired {} inconsistencies, see debug chat for details",
+                        report.inconsistencies.len()
+                    ))
+                    .await
+                } else {
+                    ctx.reply_text(&format!(
+                        "Found {} inconsistencies, see debug chat for details",
+                        report.inconsistencies.len()
+                    ))
+                    .await
+                }
+            })
+            .await
+        }
+        Command::Retry => {
+            ctx.handle_light_with(delete_command, |_| async {
+                bail!("Nothing to retry, no title change failed recently")
+            })
+            .await
+        }
+        Command::RawKeys { args } => {
+            ctx.handle_light_with(delete_command, |ctx| async move {
+                ctx.assert_superuser()?;
+                let (chat_id, page) = split_first_arg(&args);
+                let chat_id = chat_id
+                    .parse::<i64>()
+                    .map(ChatId)
+                    .wrap_err("format: /rawkeys &lt;chat_id&gt; [page]")?;
+                let page = match page {
+                    "" => 1,
+                    page => page.parse().wrap_err("Bad page number")?,
+                };
+                send_debug(&dump_raw_keys(ctx.db(), chat_id, page)?);
+                ctx.reply_text("Raw keys sent to debug chat").await
+            })
+            .await
+        }
+        Command::Repair { title } => {
+            ctx.handle_light_with(delete_command, |ctx| async move {
+                ctx.assert_superuser()?;
+                ensure!(!title.is_empty(), "format: /repair &lt;title&gt;");
+                let found = ctx.repair_title(&title)?;
+                if found.is_empty() {
+                    return ctx
+                        .reply_text("No inconsistency found, nothing to repair")
+                        .await;
+                }
+                let details = found.iter().map(ToString::to_string).collect::<Vec<_>>();
+                ctx.reply_to(format!(
+                    "Repaired {} inconsistencies:\n{}",
+                    found.len(),
+                    details.join("\n")
+                ))
+                .await
+            })
+            .await
         }
         cmd => {
-            ctx.handle_with(|mut ctx| async move {
+            let privileges = cmd.required_bot_privileges();
+            let identity = cmd.requires_identity();
+            ctx.handle_with(identity, privileges, delete_command, |ctx| async move {
                 match cmd {
                     Command::Title { title } => {
                         ensure!(!title.is_empty(), "Title cannot be empty");
-                        ctx.prep_edit().await?;
-                        ctx.set_title(title).await?;
+                        let sender = (ctx.chat_id(), ctx.sender_id());
+                        let res = async {
+                            ctx.prep_edit().await?;
+                            ctx.set_title(title.clone()).await
+                        }
+                        .await;
+                        remember_failure(&FAILED_COMMANDS, sender, Command::Title { title }, res)?;
+                        if Config::get().title_preview {
+                            ctx.done_with_preview().await
+                        } else {
+                            ctx.done().await
+                        }
+                    }
+                    Command::Retitle { old, new } => {
+                        ctx.rename_title(&old, new).await?;
                         ctx.done().await
                     }
+                    Command::Available { title } => {
+                        ensure!(!title.is_empty(), "Title cannot be empty");
+                        let availability =
+                            check_availability(ctx.db(), ctx.chat_id(), ctx.sender_id(), &title)?;
+                        ctx.reply_text_then_del(availability).await
+                    }
+                    Command::Limits => {
+                        let limits = ctx.describe_limits()?;
+                        ctx.reply_to(limits).await
+                    }
+                    Command::TestTitle => {
+                        ctx.test_title().await?;
+                        ctx.reply_text_then_del("Title can be set, all checks passed.")
+                            .await
+                    }
                     Command::RemoveTitle { title } => {
+                        ctx.remove_title(&title).await?;
+                        ctx.done().await
+                    }
+                    Command::Verbose { duration } => {
                         ctx.assert_sender_owner()?;
-                        ctx.remove_title_with_sig(&title)?;
+                        let duration = humantime::parse_duration(duration.trim())
+                            .wrap_err("format: /verbose &lt;duration&gt;, e.g. /verbose 30m")?;
+                        ctx.pause_deletion(duration)?;
                         ctx.done().await
                     }
-                    Command::Demote { username } => match username.as_str() {
-                        "" => {
+                    Command::Restore { title } => {
+                        ctx.assert_sender_owner()?;
+                        ctx.restore_title(&title).await?;
+                        ctx.done().await
+                    }
+                    Command::Protect { title } => {
+                        ctx.assert_sender_owner()?;
+                        set_protected(ctx.db(), ctx.chat_id(), &title, true)?;
+                        ctx.done().await
+                    }
+                    Command::Unprotect { title } => {
+                        ctx.assert_sender_owner()?;
+                        set_protected(ctx.db(), ctx.chat_id(), &title, false)?;
+                        ctx.done().await
+                    }
+                    Command::Demote { username } => match parse_username(&username) {
+                        None if username.trim().is_empty() => {
+                            ctx.assert_self_demotable()?;
                             ctx.assert_editable()?;
                             ctx.assert_bot_promotable()?;
-                            ctx.demote().await?;
-                            ctx.remove_title_with_id()?;
+                            ctx.demote_and_forget().await?;
                             ctx.done().await
                         }
-                        string if string.starts_with('@') && string.len() > 1 => {
+                        Some(username) => {
                             ctx.assert_sender_owner()?;
-                            let name = &string[1..];
                             let target = ctx
-                                .find_admin_with_username(name)
+                                .find_admin_with_username(&username)
                                 .await?
                                 .ok_or_else(|| eyre!("No such user"))?;
 
                             ctx.with_sender(target, |ctx| async move {
+                                ctx.assert_demotable()?;
                                 ctx.assert_editable()?;
                                 ctx.assert_bot_promotable()?;
-                                ctx.demote().await?;
-                                ctx.remove_title_with_id()?;
+                                ctx.demote_and_forget().await?;
                                 ctx.done().await
                             })
                             .await
                         }
-                        _ => {
+                        None => {
                             bail!(
                                 "format: /demote to demote yourself or /demote @someone if you're \
                                  owner"
@@ -156,46 +1185,574 @@ async fn handle_command(
                         }
                     },
                     Command::Anonymous => {
-                        ctx.assert_bot_anonymous()?;
-                        if ctx.is_anonymous() {
-                            bail!("You are already anonymous")
-                        }
-                        if ctx.get_record_with_id()?.is_none() {
-                            bail!("Before making anonymous, use /title first to register")
-                        }
-                        ctx.prep_edit().await?;
-                        ctx.set_anonymous().await?;
+                        let sender = (ctx.chat_id(), ctx.sender_id());
+                        let res = ctx.make_anonymous().await;
+                        remember_failure(&FAILED_COMMANDS, sender, Command::Anonymous, res)?;
+                        ctx.done().await
+                    }
+                    Command::AnonTitle { title } => {
+                        ensure!(!title.is_empty(), "Title cannot be empty");
+                        let sender = (ctx.chat_id(), ctx.sender_id());
+                        let res = ctx.set_title_anonymous(title.clone()).await;
+                        let command = Command::AnonTitle { title };
+                        remember_failure(&FAILED_COMMANDS, sender, command, res)?;
+                        ctx.done().await
+                    }
+                    Command::TempAnon { duration } => {
+                        let duration = humantime::parse_duration(duration.trim())
+                            .wrap_err("format: /tempanon &lt;duration&gt;, e.g. /tempanon 1h")?;
+                        ctx.make_anonymous().await?;
+                        let at = unix_now() + duration.as_secs();
+                        schedule_revert(ctx.db(), ctx.chat_id(), ctx.sender_id(), at)?;
                         ctx.done().await
                     }
                     Command::DeAnonymous => {
-                        ctx.de_anonymous().await?;
+                        let sender = (ctx.chat_id(), ctx.sender_id());
+                        let res = ctx.de_anonymous().await;
+                        remember_failure(&FAILED_COMMANDS, sender, Command::DeAnonymous, res)?;
+                        cancel_revert(ctx.db(), ctx.chat_id(), ctx.sender_id())?;
                         ctx.done().await
                     }
-                    Command::Nuke => {
+                    Command::DeAnonAll => {
                         ctx.assert_sender_owner()?;
-                        ctx.nuke().await?;
+                        let report = ctx.de_anonymous_all().await?;
+                        ctx.reply_to(report).await
+                    }
+                    Command::Style { args } => {
+                        ensure!(
+                            !args.trim().is_empty(),
+                            "format: /style color=&lt;hex&gt; badge=&lt;name&gt;, empty to unset"
+                        );
+                        let (db, chat_id, user_id) = (ctx.db(), ctx.chat_id(), ctx.sender_id());
+                        let mut style = TitleStyle::load(db, chat_id, user_id)?.unwrap_or_default();
+                        style.update(&args)?;
+                        style.save(db, chat_id, user_id)?;
                         ctx.done().await
                     }
-                    Command::Titles => {
-                        let keys = ctx.list_titles()?;
-                        let show = if keys.is_empty() {
-                            "No titles found.".to_owned()
+                    Command::MyData => {
+                        let data = ctx.export_user_data()?;
+                        ctx.reply_to(format!("<pre>{}</pre>", html::escape(&data)))
+                            .await
+                    }
+                    Command::ForgetMe => {
+                        ctx.forget().await?;
+                        ctx.done().await
+                    }
+                    Command::Set { args } => {
+                        ctx.assert_sender_owner()?;
+                        if args.trim().is_empty() {
+                            let settings = serde_json::to_string_pretty(&ctx.settings()?)?;
+                            ctx.reply_to(format!("<pre>{}</pre>", html::escape(&settings)))
+                                .await
                         } else {
-                            let titles = keys
-                                .iter()
-                                .map(std::string::ToString::to_string)
-                                .collect::<Vec<_>>()
-                                .join("\n");
-                            format!("<code>in Chat({}):</code>\n{}", keys[0].chat_id, titles)
+                            let (key, value) = split_first_arg(&args);
+                            ctx.update_setting(key, value)?;
+                            ctx.done().await
+                        }
+                    }
+                    Command::Reserve { args } => {
+                        ctx.assert_sender_owner()?;
+                        let (target, title) = split_first_arg(&args);
+                        let user_id = ctx.resolve_user(target).await?;
+                        ctx.reserve_title(user_id, title)?;
+                        ctx.done().await
+                    }
+                    Command::BulkReserve { args } => {
+                        ctx.assert_sender_owner()?;
+                        // Commands are split from their arguments at the first
+                        // space, so a list starting on the next line would not
+                        // parse. Take it from the replied message instead
+                        let list = if args.trim().is_empty() {
+                            ctx.msg()
+                                .reply_to_message()
+                                .and_then(Message::text)
+                                .unwrap_or_default()
+                        } else {
+                            &args
                         };
+                        let mut entries = Vec::new();
+                        for line in list.lines().filter(|line| !line.trim().is_empty()) {
+                            let (target, title) = split_first_arg(line);
+                            let user_id = ctx
+                                .resolve_user(target)
+                                .await
+                                .wrap_err_with(|| format!("Bad line `{}`", html::escape(line)))?;
+                            entries.push((user_id, title.to_owned()));
+                        }
+                        ensure!(
+                            !entries.is_empty(),
+                            "format: /bulkreserve @username title, one per line, or reply to such \
+                             a list with /bulkreserve"
+                        );
+                        let report = ctx.reserve_titles(entries)?;
+                        ctx.reply_to(report.to_string()).await
+                    }
+                    Command::BulkTitle { title } => {
+                        ctx.assert_sender_owner()?;
+                        ensure!(!title.is_empty(), "Title cannot be empty");
+                        let targets = ctx
+                            .msg()
+                            .reply_to_message()
+                            .and_then(Message::text)
+                            .wrap_err("Reply to a message listing @usernames or user ids")?
+                            .to_owned();
+                        let report = ctx.bulk_title(&title, &targets).await?;
+                        ctx.reply_to(report).await
+                    }
+                    Command::Quota { quota } => {
+                        ctx.assert_sender_owner()?;
+                        if quota.trim().is_empty() {
+                            let quota = ctx.describe_quota()?;
+                            ctx.reply_to(quota).await
+                        } else {
+                            ctx.set_quota(&quota)?;
+                            ctx.done().await
+                        }
+                    }
+                    Command::Blocked { args } => {
+                        ctx.assert_sender_owner()?;
+                        match split_first_arg(&args) {
+                            ("", _) => {
+                                let words = ctx.settings()?.describe_blocked_words();
+                                ctx.reply_to(words).await
+                            }
+                            ("add", word) => {
+                                ctx.update_blocked_word(true, word)?;
+                                ctx.done().await
+                            }
+                            ("remove", word) => {
+                                ctx.update_blocked_word(false, word)?;
+                                ctx.done().await
+                            }
+                            _ => bail!("format: /blocked [add|remove &lt;word&gt;]"),
+                        }
+                    }
+                    Command::ExportTitles => {
+                        ctx.assert_sender_owner()?;
+                        let file = ctx.export_titles()?;
+                        ctx.reply_with_file(file).await
+                    }
+                    Command::Reservations => {
+                        ctx.assert_sender_owner()?;
+                        let reservations = ctx.list_reservations()?;
+                        ctx.reply_to(reservations).await
+                    }
+                    Command::TitleHistory { title } => {
+                        ctx.assert_sender_owner()?;
+                        let history = ctx.list_title_history(title.trim())?;
+                        ctx.reply_to(history).await
+                    }
+                    Command::Unreserve { title } => {
+                        ctx.assert_sender_owner()?;
+                        ctx.unreserve(title.trim())?;
+                        ctx.done().await
+                    }
+                    Command::Resync { username } => match parse_username(&username) {
+                        None if username.trim().is_empty() => {
+                            let report = ctx.resync().await?;
+                            ctx.reply_to(report).await
+                        }
+                        Some(username) => {
+                            ctx.assert_sender_owner()?;
+                            let target = ctx
+                                .find_admin_with_username(&username)
+                                .await?
+                                .ok_or_else(|| eyre!("No such user"))?;
+
+                            ctx.with_sender(target, |ctx| async move {
+                                let report = ctx.resync().await?;
+                                ctx.reply_to(report).await
+                            })
+                            .await
+                        }
+                        None => {
+                            bail!(
+                                "format: /resync to resync yourself or /resync @someone if you're \
+                                 owner"
+                            )
+                        }
+                    },
+                    Command::ReapplyPrefix => {
+                        ctx.assert_sender_owner()?;
+                        let report = ctx.reapply_decoration().await?;
+                        ctx.reply_to(report).await
+                    }
+                    Command::Snapshot { args } => {
+                        ctx.assert_sender_owner()?;
+                        let records = ctx.list_titles()?;
+                        match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+                            ["save", name] => {
+                                save_snapshot(ctx.db(), ctx.chat_id(), name, &records)?;
+                                ctx.done().await
+                            }
+                            ["diff", name] => {
+                                let saved = load_snapshot(ctx.db(), ctx.chat_id(), name)?;
+                                let diff = TitleDiff::between(&saved, &records);
+                                ctx.reply_to(diff.to_string()).await
+                            }
+                            _ => bail!(
+                                "format: /snapshot save &lt;name&gt; or /snapshot diff \
+                                 &lt;name&gt;"
+                            ),
+                        }
+                    }
+                    Command::Schedule { args } => {
+                        ctx.assert_sender_owner()?;
+                        match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+                            ["clear", interval] => {
+                                let every = humantime::parse_duration(interval)
+                                    .wrap_err("Bad interval, e.g. 1day or 7days")?;
+                                schedule_clear(ctx.db(), ctx.chat_id(), every, unix_now())?;
+                                ctx.done().await
+                            }
+                            ["cancel"] => {
+                                ensure!(
+                                    cancel_clear(ctx.db(), ctx.chat_id())?,
+                                    "No clear is scheduled"
+                                );
+                                ctx.done().await
+                            }
+                            _ => bail!(
+                                "format: /schedule clear &lt;interval&gt; or /schedule cancel"
+                            ),
+                        }
+                    }
+                    Command::Nuke { reason } => {
+                        ctx.assert_sender_owner()?;
+                        ctx.nuke(&reason).await?;
+                        ctx.done().await
+                    }
+                    Command::Titles { args } => {
+                        let query = TitleQuery::parse(&args)?;
+                        let (records, capped) = ctx.list_titles_for_listing()?;
+                        let records = query.apply(records);
+                        let statuses = if query.show_status {
+                            ctx.member_statuses(&records).await?
+                        } else {
+                            HashMap::new()
+                        };
+                        let mut show = render_titles(
+                            ctx.chat_id(),
+                            &records,
+                            &statuses,
+                            query.page.unwrap_or(1),
+                        )?;
+                        if capped && let Some(cap) = Config::get().titles_scan_cap {
+                            add_cap_notice(&mut show, cap);
+                            send_debug(&format!(
+                                "/titles in Chat({}) stopped at the scan cap of {cap} records",
+                                ctx.chat_id()
+                            ));
+                        }
                         ctx.reply_to(&show).await
                     }
-                    Command::Help | Command::Start => unreachable!(),
+                    Command::Member { username } => {
+                        if ctx.assert_superuser().is_err() {
+                            ctx.assert_sender_owner()?;
+                        }
+                        let member = ctx.describe_member(&username).await?;
+                        ctx.reply_to(member).await
+                    }
+                    Command::Diagnose => {
+                        let report = render_diagnosis(&ctx.diagnose());
+                        ctx.reply_to(report).await
+                    }
+                    Command::Help
+                    | Command::Start { .. }
+                    | Command::Feedback { .. }
+                    | Command::Chats
+                    | Command::Verify { .. }
+                    | Command::RawKeys { .. }
+                    | Command::Repair { .. }
+                    | Command::Retry => unreachable!(),
                 }
             })
             .await
         }
-    });
+    };
+    // Denied or failed commands are replied to and return Ok, so only those
+    // carried out count as successful
+    let done = matches!(res, Ok(Outcome::Done));
+    record_command(
+        name.split_whitespace().next().unwrap_or_default(),
+        done,
+        started.elapsed(),
+    );
+    count_command(done);
+    if done && let Some(event) = event {
+        match event.with_title_from(&db) {
+            Ok(event) => emit_event(event),
+            Err(error) => send_debug(&error),
+        }
+    }
+    if let Err(e) = res {
+        send_debug_event(&DebugEvent::error(&e).command(name).chat(msg.chat.id));
+    }
     catch!(db.flush_async().await);
     Ok(())
 }
+
+#[test]
+fn test_deletes_message() {
+    let keep = vec!["titles".to_owned(), "mydata".to_owned()];
+
+    assert_eq!(Command::MyData.name(), "mydata");
+    assert!(!Command::MyData.deletes_message(&keep));
+    assert!(!Command::Titles {
+        args: String::new()
+    }
+    .deletes_message(&keep));
+    assert!(Command::Title {
+        title: "title".to_owned()
+    }
+    .deletes_message(&keep));
+
+    // All deleted by default
+    assert!(Command::MyData.deletes_message(&[]));
+}
+
+#[test]
+fn test_forget_removed_chat() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |chat_id, user_id| TitleRecord {
+        title: "VIP".into(),
+        chat_id: ChatId(chat_id),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    record(1, 1).insert_into(&db).unwrap();
+    record(2, 1).insert_into(&db).unwrap();
+
+    // Kept unless purging
+    assert_eq!(forget_removed_chat(&db, ChatId(1), false).unwrap(), 0);
+    assert!(TitleRecord::get_with_id(&db, ChatId(1), UserId(1))
+        .unwrap()
+        .is_some());
+
+    // Only the chat removed from is purged
+    assert_eq!(forget_removed_chat(&db, ChatId(1), true).unwrap(), 1);
+    assert!(TitleRecord::get_with_id(&db, ChatId(1), UserId(1))
+        .unwrap()
+        .is_none());
+    assert!(TitleRecord::get_with_title(&db, ChatId(1), "VIP")
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(2), UserId(1)).unwrap(),
+        Some(record(2, 1))
+    );
+}
+
+#[test]
+fn test_accepts_command() {
+    let msg_in = |chat: serde_json::Value, text: &str| -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": chat,
+            "from": { "id": 2, "is_bot": false, "first_name": "User" },
+            "text": text
+        }))
+        .unwrap()
+    };
+    let msg = |text: &str| {
+        msg_in(
+            serde_json::json!({ "id": -1, "type": "supergroup", "title": "Chat" }),
+            text,
+        )
+    };
+
+    // Mentioned commands are handled, bare ones ignored when required
+    assert!(accepts_command(
+        true,
+        &msg("/title@GoldenAxeBot VIP"),
+        "goldenaxebot"
+    ));
+    assert!(!accepts_command(true, &msg("/title VIP"), "goldenaxebot"));
+    assert!(!accepts_command(
+        true,
+        &msg("/title@OtherBot VIP"),
+        "goldenaxebot"
+    ));
+
+    // Bare commands are handled in private chats, where no other bot is meant
+    let private = serde_json::json!({ "id": 2, "type": "private", "first_name": "User" });
+    assert!(accepts_command(
+        true,
+        &msg_in(private.clone(), "/help"),
+        "goldenaxebot"
+    ));
+    assert!(!accepts_command(
+        true,
+        &msg_in(private, "/help@OtherBot"),
+        "goldenaxebot"
+    ));
+
+    // Otherwise all but those addressed to other bots are handled
+    assert!(accepts_command(false, &msg("/title VIP"), "goldenaxebot"));
+    assert!(accepts_command(
+        false,
+        &msg("/title@GoldenAxeBot VIP"),
+        "goldenaxebot"
+    ));
+    assert!(!accepts_command(
+        false,
+        &msg("/title@OtherBot VIP"),
+        "goldenaxebot"
+    ));
+
+    // The suffix never reaches the arguments
+    for text in ["/title VIP", "/title@GoldenAxeBot VIP"] {
+        match Command::parse(text, "goldenaxebot").unwrap() {
+            Command::Title { title } => assert_eq!(title, "VIP"),
+            command => panic!("Unexpected {command:?}"),
+        }
+    }
+    match Command::parse("/titles@GoldenAxeBot", "goldenaxebot").unwrap() {
+        Command::Titles { args } => assert!(args.is_empty()),
+        command => panic!("Unexpected {command:?}"),
+    }
+}
+
+#[test]
+fn test_parse_retitle() {
+    let parse = |args: &str| parse_retitle(args.to_owned()).unwrap();
+    assert_eq!(
+        parse("Old Title | New Title"),
+        ("Old Title".into(), "New Title".into())
+    );
+    assert_eq!(parse("Old|New"), ("Old".into(), "New".into()));
+    // Spaces don't separate titles
+    assert_eq!(parse(" Old New "), ("Old New".into(), String::new()));
+    assert_eq!(parse("Old"), ("Old".into(), String::new()));
+
+    match Command::parse("/retitle VIP | Very VIP", "bot").unwrap() {
+        Command::Retitle { old, new } => assert_eq!((old, new), ("VIP".into(), "Very VIP".into())),
+        command => panic!("Unexpected {command:?}"),
+    }
+}
+
+#[test]
+fn test_arguments_of() {
+    // Compared with the fields of each variant, read from the debug output of
+    // the parsed command, e.g. `Retitle { old: "a", new: "b" }`
+    for command in Command::bot_commands() {
+        let name = command.command.trim_start_matches('/');
+        let parsed = Command::parse(&format!("/{name} a | b"), "bot")
+            .or_else(|_| Command::parse(&format!("/{name}"), "bot"))
+            .unwrap();
+        let debug = format!("{parsed:?}");
+        let fields = debug
+            .split_once(" { ")
+            .map_or_else(Vec::new, |(_, fields)| {
+                fields
+                    .trim_end_matches(" }")
+                    .split(", ")
+                    .filter_map(|field| field.split_once(':'))
+                    .map(|(name, _)| name.to_owned())
+                    .collect()
+            });
+        assert_eq!(Command::arguments_of(name), fields, "/{name}");
+    }
+}
+
+#[test]
+fn test_parse_bulkreserve() {
+    match Command::parse("/bulkreserve @alice VIP\n@bob Staff", "bot").unwrap() {
+        Command::BulkReserve { args } => assert_eq!(args, "@alice VIP\n@bob Staff"),
+        command => panic!("Unexpected {command:?}"),
+    }
+    match Command::parse("/bulkreserve@bot", "bot").unwrap() {
+        Command::BulkReserve { args } => assert!(args.is_empty()),
+        command => panic!("Unexpected {command:?}"),
+    }
+    // The list cannot start on the next line, it's taken from the replied
+    // message instead
+    assert!(Command::parse("/bulkreserve\n@alice VIP", "bot").is_err());
+}
+
+#[test]
+fn test_onboarding_reply() {
+    let msg = |chat: serde_json::Value| -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": chat,
+            "from": { "id": 2, "is_bot": false, "first_name": "User" },
+            "text": "hello?"
+        }))
+        .unwrap()
+    };
+
+    // Text in private gets the onboarding reply
+    let private = msg(serde_json::json!({ "id": 2, "type": "private", "first_name": "User" }));
+    assert_eq!(onboarding_reply(&private), Some(ONBOARDING_TEXT));
+
+    // Text in groups stays ignored
+    let group = msg(serde_json::json!({ "id": -1, "type": "supergroup", "title": "Chat" }));
+    assert_eq!(onboarding_reply(&group), None);
+}
+
+#[test]
+fn test_inline_title_result() {
+    let query = |query: &str, chat_type: &str| -> InlineQuery {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "from": { "id": 2, "is_bot": false, "first_name": "User" },
+            "query": query,
+            "offset": "",
+            "chat_type": chat_type
+        }))
+        .unwrap()
+    };
+    let article = |query| match inline_title_result(&query, "axe_bot") {
+        Some(InlineQueryResult::Article(article)) => Some(article),
+        _ => None,
+    };
+
+    let result = article(query("title  Big VIP ", "supergroup")).unwrap();
+    assert_eq!(result.title, "Set title to Big VIP");
+    assert_eq!(
+        result.input_message_content,
+        InputMessageContent::Text(InputMessageContentText::new("/title@axe_bot Big VIP"))
+    );
+    assert!(article(query("Title VIP", "group")).is_some());
+
+    // Not a title, or not sent from a group
+    assert!(article(query("title", "supergroup")).is_none());
+    assert!(article(query("titles", "supergroup")).is_none());
+    assert!(article(query("title VIP", "private")).is_none());
+    assert!(article(query("title VIP", "sender")).is_none());
+}
+
+#[test]
+fn test_retry_failed_command() {
+    let failed = TtlCache::new(RETRY_WINDOW);
+    let sender = (ChatId(1), UserId(2));
+    let title = |title: &str| Command::Title {
+        title: title.to_owned(),
+    };
+
+    // Nothing to retry
+    assert!(failed.take(&sender).is_none());
+    assert!(remember_failure(&failed, sender, title("ok"), Ok(())).is_ok());
+    assert!(failed.take(&sender).is_none());
+
+    // Not remembered if the error isn't transient, or the command not safe to
+    // run again
+    let res = Err(eyre!("Title too long"));
+    assert!(remember_failure(&failed, sender, title("VIP"), res).is_err());
+    let res = Err(RequestError::RetryAfter(Duration::from_secs(1)).into());
+    let nuke = Command::Nuke {
+        reason: String::new(),
+    };
+    assert!(remember_failure(&failed, sender, nuke, res).is_err());
+    assert!(failed.take(&sender).is_none());
+
+    // Failed title set retried once
+    let res = Err(RequestError::RetryAfter(Duration::from_secs(1)).into());
+    let error = remember_failure(&failed, sender, title("VIP"), res).unwrap_err();
+    assert!(error.to_string().contains("/retry"));
+    assert!(matches!(failed.take(&sender), Some(Command::Title { title }) if title == "VIP"));
+    assert!(failed.take(&sender).is_none());
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Caches a value for each key for a period of time, e.g. chat information
+/// that rarely changes but is needed by every command.
+#[derive(Debug)]
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the cached value of the key, unless expired.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Cache the value of the key, overwriting the existing one.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn insert(&self, key: K, value: V) {
+        self.insert_at(key, value, Instant::now());
+    }
+
+    /// Remove the cached value of the key, returning it unless expired.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn take(&self, key: &K) -> Option<V> {
+        self.take_at(key, Instant::now())
+    }
+
+    fn get_at(&self, key: &K, now: Instant) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((cached_at, value)) if now.saturating_duration_since(*cached_at) < self.ttl => {
+                Some(value.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert_at(&self, key: K, value: V, now: Instant) {
+        self.entries.lock().unwrap().insert(key, (now, value));
+    }
+
+    fn take_at(&self, key: &K, now: Instant) -> Option<V> {
+        match self.entries.lock().unwrap().remove(key) {
+            Some((cached_at, value)) if now.saturating_duration_since(cached_at) < self.ttl => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_ttl_cache() {
+    let cache = TtlCache::new(Duration::from_secs(60));
+    let now = Instant::now();
+
+    assert_eq!(cache.get_at(&1, now), None);
+    cache.insert_at(1, "a", now);
+    assert_eq!(cache.get_at(&1, now + Duration::from_secs(59)), Some("a"));
+    assert_eq!(cache.get_at(&1, now + Duration::from_secs(60)), None);
+    assert_eq!(cache.get_at(&2, now), None);
+
+    cache.insert_at(2, "b", now);
+    assert_eq!(cache.take_at(&2, now), Some("b"));
+    assert_eq!(cache.take_at(&2, now), None);
+    cache.insert_at(3, "c", now);
+    assert_eq!(cache.take_at(&3, now + Duration::from_secs(60)), None);
+}
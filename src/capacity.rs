@@ -0,0 +1,222 @@
+use color_eyre::{
+    eyre::{bail, Context, ContextCompat},
+    Result,
+};
+use serde::Deserialize;
+use sled::{Db, IVec};
+use teloxide::types::{ChatId, UserId};
+
+use crate::TitleRecord;
+
+/// What to do when storing a new title would exceed `max_db_entries`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CapacityPolicy {
+    /// Remove the oldest records to make room
+    #[default]
+    Evict,
+    /// Refuse to store the new title
+    Refuse,
+}
+
+/// Remember when the user first got a title in the chat, stored under
+/// `created$<chat_id>$<user_id>` as big endian unix timestamp.
+///
+/// # Errors
+/// If the insertion fails.
+pub fn mark_created(db: &Db, chat_id: ChatId, user_id: UserId, now: u64) -> Result<()> {
+    db.insert(make_created_key(chat_id, user_id), &now.to_be_bytes())?;
+    Ok(())
+}
+
+/// Number of title records, stored under `titlecount$` as big endian so the
+/// capacity is checked without scanning. Kept along with `chat$` keys by
+/// [`TitleRecord`], and recounted on startup in case it drifted.
+const COUNT_KEY: &str = "titlecount$";
+
+/// Check there's room for one more title record before claiming it. With
+/// [`CapacityPolicy::Refuse`] nothing is stored once `max` records are, while
+/// with [`CapacityPolicy::Evict`] the title is let in and the oldest records
+/// are evicted after, see [`evict_overflow`].
+///
+/// # Errors
+/// If the policy is [`CapacityPolicy::Refuse`] and the database is full, or
+/// the database returns an error.
+pub fn check_capacity(db: &Db, max: usize, policy: CapacityPolicy) -> Result<()> {
+    if policy == CapacityPolicy::Refuse && count_titles(db)? >= max {
+        bail!("Too many titles stored, please contact the bot operator");
+    }
+    Ok(())
+}
+
+/// Remove the oldest records while more than `max` are stored, records stored
+/// before creation was tracked being the oldest. The `claimed` record is never
+/// evicted. Returns the evicted records, whose live titles are left to the
+/// caller.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn evict_overflow(db: &Db, max: usize, claimed: &TitleRecord) -> Result<Vec<TitleRecord>> {
+    let count = count_titles(db)?;
+    if count <= max {
+        return Ok(Vec::new());
+    }
+
+    let mut records = db
+        .scan_prefix("chat$")
+        .map(|x| -> Result<_> {
+            let (key, value) = x.wrap_err("Failed to scan database")?;
+            let record = TitleRecord::parse_chat_key(&key, &value)?;
+            let created_at = created_at(db, record.chat_id, record.user_id)?;
+            Ok((created_at.unwrap_or_default(), record))
+        })
+        .try_collect::<Vec<_>>()?;
+    records.retain(|(_, record)| {
+        (record.chat_id, record.user_id) != (claimed.chat_id, claimed.user_id)
+    });
+    records.sort_by_key(|(created_at, _)| *created_at);
+
+    let evicted = records
+        .into_iter()
+        .take(count - max)
+        .map(|(_, record)| record)
+        .collect::<Vec<_>>();
+    for record in &evicted {
+        record.remove_from(db)?;
+        forget_created(db, record.chat_id, record.user_id)?;
+    }
+    Ok(evicted)
+}
+
+/// Number of title records stored.
+///
+/// # Errors
+/// If the database returns an error or the count is malformed.
+pub fn count_titles(db: &Db) -> Result<usize> {
+    parse_count(db.get(COUNT_KEY)?.as_deref())
+}
+
+/// Count `n` new title records.
+///
+/// # Errors
+/// If the database returns an error or the count is malformed.
+pub(crate) fn count_added(db: &Db, n: usize) -> Result<()> {
+    update_count(db, |count| count.saturating_add(n))
+}
+
+/// Count `n` removed title records.
+///
+/// # Errors
+/// If the database returns an error or the count is malformed.
+pub(crate) fn count_removed(db: &Db, n: usize) -> Result<()> {
+    update_count(db, |count| count.saturating_sub(n))
+}
+
+/// Count the title records from scratch, fixing the stored count. Scans the
+/// whole `chat$` keyspace, so it's done once on startup.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn recount_titles(db: &Db) -> Result<usize> {
+    let count = db.scan_prefix("chat$").count();
+    db.insert(COUNT_KEY, &(count as u64).to_be_bytes())?;
+    Ok(count)
+}
+
+fn update_count(db: &Db, update: impl Fn(usize) -> usize) -> Result<()> {
+    loop {
+        let old = db.get(COUNT_KEY)?;
+        let count = update(parse_count(old.as_deref())?) as u64;
+        if db
+            .compare_and_swap(COUNT_KEY, old, Some(count.to_be_bytes().to_vec()))?
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+}
+
+fn parse_count(value: Option<&[u8]>) -> Result<usize> {
+    value.map_or(Ok(0), |value| {
+        let count = u64::from_be_bytes(value.try_into().wrap_err("Bad title count")?);
+        Ok(usize::try_from(count)?)
+    })
+}
+
+/// When the user first got a title in the chat, if known.
+///
+/// # Errors
+/// If the database returns an error or the value is malformed.
+pub fn created_at(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Option<u64>> {
+    db.get(make_created_key(chat_id, user_id))?
+        .map(|value| {
+            Ok(u64::from_be_bytes(
+                (*value).try_into().wrap_err("Bad value")?,
+            ))
+        })
+        .transpose()
+}
+
+/// Forget when the user first got a title in the chat.
+///
+/// # Errors
+/// If the removal fails.
+pub fn forget_created(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<()> {
+    db.remove(make_created_key(chat_id, user_id))?;
+    Ok(())
+}
+
+fn make_created_key(chat_id: ChatId, user_id: UserId) -> IVec {
+    format!("created${}${}", chat_id, user_id)
+        .into_bytes()
+        .into()
+}
+
+#[test]
+fn test_capacity() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    for (title, user_id, created) in [("Old", 2, 100), ("Mid", 3, 200)] {
+        record(title, user_id).insert_into(&db).unwrap();
+        mark_created(&db, ChatId(1), UserId(user_id), created).unwrap();
+    }
+    assert_eq!(count_titles(&db).unwrap(), 2);
+
+    // Room left
+    check_capacity(&db, 3, CapacityPolicy::Refuse).unwrap();
+
+    // Refused at the cap, always let in when evicting
+    assert!(check_capacity(&db, 2, CapacityPolicy::Refuse).is_err());
+    check_capacity(&db, 2, CapacityPolicy::Evict).unwrap();
+
+    // The claimed record is kept even when it's the oldest
+    let claimed = record("New", 1);
+    claimed.claim(&db).unwrap();
+    assert_eq!(count_titles(&db).unwrap(), 3);
+    assert!(evict_overflow(&db, 3, &claimed).unwrap().is_empty());
+    assert_eq!(
+        evict_overflow(&db, 1, &claimed).unwrap(),
+        vec![record("Old", 2), record("Mid", 3)]
+    );
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(1)).unwrap(),
+        Some(claimed)
+    );
+    assert_eq!(db.scan_prefix("chat$").count(), 1);
+    assert_eq!(count_titles(&db).unwrap(), 1);
+    assert!(TitleRecord::get_with_title(&db, ChatId(1), "Old")
+        .unwrap()
+        .is_none());
+    assert_eq!(created_at(&db, ChatId(1), UserId(2)).unwrap(), None);
+
+    // Drifted counts are fixed by recounting
+    count_added(&db, 5).unwrap();
+    assert_eq!(recount_titles(&db).unwrap(), 1);
+    assert_eq!(count_titles(&db).unwrap(), 1);
+}
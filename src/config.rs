@@ -1,7 +1,7 @@
 #![allow(clippy::use_self)]
 
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashMap},
     hash::{Hash, Hasher},
     path::PathBuf,
     sync::OnceLock,
@@ -11,14 +11,18 @@ use std::{
 use color_eyre::{eyre::Context, Result};
 use figment::{providers::Env, Figment};
 use serde::Deserialize;
-use serde_with::{serde_as, DisplayFromStr};
+use serde_with::{serde_as, CommaSeparator, DisplayFromStr, StringWithSeparator};
 use tracing::level_filters::LevelFilter;
 
+use crate::{CapacityPolicy, DebugFormat, DemotionPolicy, Privilege};
+
 mod default {
     use std::{path::PathBuf, time::Duration};
 
     use tracing::level_filters::LevelFilter;
 
+    use crate::Privilege;
+
     pub const fn log() -> LevelFilter {
         LevelFilter::INFO
     }
@@ -30,6 +34,54 @@ mod default {
     pub const fn delete_after() -> Duration {
         Duration::from_secs(10)
     }
+
+    pub fn promote_privileges() -> Vec<Privilege> {
+        vec![Privilege::InviteUsers]
+    }
+
+    pub fn min_bot_privileges() -> Vec<Privilege> {
+        vec![Privilege::PromoteMembers, Privilege::InviteUsers]
+    }
+
+    pub const fn not_admin_cooldown() -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    pub const fn welcome_check() -> bool {
+        true
+    }
+
+    pub const fn startup_retries() -> usize {
+        5
+    }
+
+    pub const fn debug_chat_max_failures() -> usize {
+        5
+    }
+
+    pub const fn admin_refresh_retries() -> usize {
+        3
+    }
+
+    pub const fn signature_grace() -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    pub const fn title_debounce() -> Duration {
+        Duration::from_secs(2)
+    }
+
+    pub const fn webhook_attempts() -> usize {
+        3
+    }
+
+    pub const fn title_sweep_interval() -> Duration {
+        Duration::from_secs(10 * 60)
+    }
+
+    pub const fn trash_retention() -> Duration {
+        Duration::from_secs(7 * 24 * 60 * 60)
+    }
 }
 
 #[serde_as]
@@ -43,8 +95,88 @@ pub struct Config {
     #[serde(with = "humantime_serde")]
     #[serde(default = "default::delete_after")]
     pub delete_after: Duration,
+    #[serde(default = "default::promote_privileges")]
+    pub promote_privileges: Vec<Privilege>,
+    #[serde(default = "default::min_bot_privileges")]
+    pub min_bot_privileges: Vec<Privilege>,
+    #[serde(default)]
+    pub superusers: Vec<u64>,
+    #[serde(default)]
+    pub demote_clears_title: bool,
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::not_admin_cooldown")]
+    pub not_admin_cooldown: Duration,
+    #[serde(default = "default::welcome_check")]
+    pub welcome_check: bool,
+    #[serde(default)]
+    pub soft_delete: bool,
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::trash_retention")]
+    pub trash_retention: Duration,
+    pub denied_message: Option<String>,
+    #[serde(default)]
+    pub explain_denial: bool,
+    pub api_token: Option<String>,
+    pub transfer_token: Option<String>,
+    #[serde(default)]
+    pub reject_chat_title: bool,
+    pub max_db_entries: Option<usize>,
+    #[serde(default)]
+    pub db_overflow: CapacityPolicy,
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub replica_refresh: Option<Duration>,
+    #[serde(default = "default::startup_retries")]
+    pub startup_retries: usize,
+    pub statsd: Option<String>,
+    #[serde(default)]
+    pub command_descriptions: HashMap<String, String>,
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::signature_grace")]
+    pub signature_grace: Duration,
+    #[serde(default = "default::debug_chat_max_failures")]
+    pub debug_chat_max_failures: usize,
+    #[serde(default)]
+    pub title_preview: bool,
+    #[serde(default = "default::admin_refresh_retries")]
+    pub admin_refresh_retries: usize,
+    #[serde(default)]
+    pub keep_commands: Vec<String>,
+    #[serde(default)]
+    pub purge_on_removal: bool,
+    pub roster_url: Option<String>,
+    #[serde(default)]
+    pub preserve_emoji_prefix: bool,
+    #[serde(default)]
+    pub require_mention: bool,
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::title_debounce")]
+    pub title_debounce: Duration,
+    #[serde(default)]
+    pub on_external_demotion: DemotionPolicy,
+    #[serde(default)]
+    pub name_title_holder: bool,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
+    #[serde(default = "default::webhook_attempts")]
+    pub webhook_attempts: usize,
+    pub titles_scan_cap: Option<usize>,
+    #[serde_as(as = "StringWithSeparator::<CommaSeparator, String>")]
+    #[serde(default)]
+    pub title_blacklist: Vec<String>,
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub title_ttl: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::title_sweep_interval")]
+    pub title_sweep_interval: Duration,
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub heartbeat_interval: Option<Duration>,
     pub token: String,
     pub debug_chat: Option<i64>,
+    #[serde(default)]
+    pub debug_format: DebugFormat,
 }
 
 impl Config {
@@ -95,6 +227,23 @@ impl Config {
         })
     }
 
+    /// Whether the title is in `title_blacklist`. Both are trimmed and
+    /// lowercased before comparing.
+    #[must_use]
+    pub fn is_blacklisted(&self, title: &str) -> bool {
+        let title = title.trim().to_lowercase();
+        self.title_blacklist
+            .iter()
+            .any(|word| word.trim().to_lowercase() == title)
+    }
+
+    /// Period of the title replica refresh, if enabled. A zero
+    /// `replica_refresh` disables it.
+    #[must_use]
+    pub fn replica_period(&self) -> Option<Duration> {
+        self.replica_refresh.filter(|period| !period.is_zero())
+    }
+
     // fn ensure_good(self) -> Result<Self> {
     //     if self.mode.is_webhook() && self.domain.is_none() {
     //         Err(eyre!(
@@ -114,6 +263,49 @@ fn test_config() {
         j.set_env("GOLDEN_AXE_DEBUG_CHAT", "123");
         j.set_env("GOLDEN_AXE_DB_PATH", "/abc");
         j.set_env("GOLDEN_AXE_DELETE_AFTER", "100s");
+        j.set_env(
+            "GOLDEN_AXE_PROMOTE_PRIVILEGES",
+            "[invite_users, pin_messages]",
+        );
+        j.set_env("GOLDEN_AXE_MIN_BOT_PRIVILEGES", "[promote_members]");
+        j.set_env("GOLDEN_AXE_SUPERUSERS", "[1, 2]");
+        j.set_env("GOLDEN_AXE_DEBUG_FORMAT", "html");
+        j.set_env("GOLDEN_AXE_DEMOTE_CLEARS_TITLE", "true");
+        j.set_env("GOLDEN_AXE_NOT_ADMIN_COOLDOWN", "10m");
+        j.set_env("GOLDEN_AXE_WELCOME_CHECK", "false");
+        j.set_env("GOLDEN_AXE_SOFT_DELETE", "true");
+        j.set_env("GOLDEN_AXE_TRASH_RETENTION", "1day");
+        j.set_env("GOLDEN_AXE_DENIED_MESSAGE", "Ask the owner");
+        j.set_env("GOLDEN_AXE_EXPLAIN_DENIAL", "true");
+        j.set_env("GOLDEN_AXE_API_TOKEN", "secret");
+        j.set_env("GOLDEN_AXE_TRANSFER_TOKEN", "handoff");
+        j.set_env("GOLDEN_AXE_REJECT_CHAT_TITLE", "true");
+        j.set_env("GOLDEN_AXE_MAX_DB_ENTRIES", "1000");
+        j.set_env("GOLDEN_AXE_DB_OVERFLOW", "refuse");
+        j.set_env("GOLDEN_AXE_REPLICA_REFRESH", "30s");
+        j.set_env("GOLDEN_AXE_STARTUP_RETRIES", "3");
+        j.set_env("GOLDEN_AXE_STATSD", "127.0.0.1:8125");
+        j.set_env("GOLDEN_AXE_COMMAND_DESCRIPTIONS", "{title=Retitle}");
+        j.set_env("GOLDEN_AXE_SIGNATURE_GRACE", "1h");
+        j.set_env("GOLDEN_AXE_DEBUG_CHAT_MAX_FAILURES", "10");
+        j.set_env("GOLDEN_AXE_TITLE_PREVIEW", "true");
+        j.set_env("GOLDEN_AXE_ADMIN_REFRESH_RETRIES", "1");
+        j.set_env("GOLDEN_AXE_KEEP_COMMANDS", "[titles, mydata]");
+        j.set_env("GOLDEN_AXE_PURGE_ON_REMOVAL", "true");
+        j.set_env("GOLDEN_AXE_ROSTER_URL", "https://example.com/roster");
+        j.set_env("GOLDEN_AXE_PRESERVE_EMOJI_PREFIX", "true");
+        j.set_env("GOLDEN_AXE_REQUIRE_MENTION", "true");
+        j.set_env("GOLDEN_AXE_TITLE_DEBOUNCE", "0s");
+        j.set_env("GOLDEN_AXE_ON_EXTERNAL_DEMOTION", "notify");
+        j.set_env("GOLDEN_AXE_NAME_TITLE_HOLDER", "true");
+        j.set_env("GOLDEN_AXE_WEBHOOK_URL", "https://example.com/events");
+        j.set_env("GOLDEN_AXE_WEBHOOK_SECRET", "hush");
+        j.set_env("GOLDEN_AXE_WEBHOOK_ATTEMPTS", "5");
+        j.set_env("GOLDEN_AXE_TITLES_SCAN_CAP", "500");
+        j.set_env("GOLDEN_AXE_TITLE_BLACKLIST", "Owner,Admin");
+        j.set_env("GOLDEN_AXE_TITLE_TTL", "7days");
+        j.set_env("GOLDEN_AXE_TITLE_SWEEP_INTERVAL", "1m");
+        j.set_env("GOLDEN_AXE_HEARTBEAT_INTERVAL", "1h");
 
         assert_eq!(
             Config::from_env().unwrap(),
@@ -121,8 +313,48 @@ fn test_config() {
                 log: LevelFilter::DEBUG,
                 token: "token".to_string(),
                 debug_chat: Some(123),
+                debug_format: DebugFormat::Html,
                 db_path: "/abc".into(),
                 delete_after: Duration::from_secs(100),
+                promote_privileges: vec![Privilege::InviteUsers, Privilege::PinMessages],
+                min_bot_privileges: vec![Privilege::PromoteMembers],
+                superusers: vec![1, 2],
+                demote_clears_title: true,
+                not_admin_cooldown: Duration::from_secs(600),
+                welcome_check: false,
+                soft_delete: true,
+                trash_retention: Duration::from_secs(24 * 60 * 60),
+                denied_message: Some("Ask the owner".to_owned()),
+                explain_denial: true,
+                api_token: Some("secret".to_owned()),
+                transfer_token: Some("handoff".to_owned()),
+                reject_chat_title: true,
+                max_db_entries: Some(1000),
+                db_overflow: CapacityPolicy::Refuse,
+                replica_refresh: Some(Duration::from_secs(30)),
+                startup_retries: 3,
+                statsd: Some("127.0.0.1:8125".to_owned()),
+                command_descriptions: HashMap::from([("title".to_owned(), "Retitle".to_owned())]),
+                signature_grace: Duration::from_secs(60 * 60),
+                debug_chat_max_failures: 10,
+                title_preview: true,
+                admin_refresh_retries: 1,
+                keep_commands: vec!["titles".to_owned(), "mydata".to_owned()],
+                purge_on_removal: true,
+                roster_url: Some("https://example.com/roster".to_owned()),
+                preserve_emoji_prefix: true,
+                require_mention: true,
+                title_debounce: Duration::ZERO,
+                on_external_demotion: DemotionPolicy::Notify,
+                name_title_holder: true,
+                webhook_url: Some("https://example.com/events".to_owned()),
+                webhook_secret: Some("hush".to_owned()),
+                webhook_attempts: 5,
+                titles_scan_cap: Some(500),
+                title_blacklist: vec!["Owner".to_owned(), "Admin".to_owned()],
+                title_ttl: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+                title_sweep_interval: Duration::from_secs(60),
+                heartbeat_interval: Some(Duration::from_secs(60 * 60)),
             }
         );
         Ok(())
@@ -142,10 +374,85 @@ fn test_config_minimal() {
                 log: LevelFilter::INFO,
                 token: "token".to_string(),
                 debug_chat: None,
+                debug_format: DebugFormat::Plain,
                 db_path: "/data/db.sled".into(),
                 delete_after: Duration::from_secs(10),
+                promote_privileges: vec![Privilege::InviteUsers],
+                min_bot_privileges: vec![Privilege::PromoteMembers, Privilege::InviteUsers],
+                superusers: vec![],
+                demote_clears_title: false,
+                not_admin_cooldown: Duration::from_secs(3600),
+                welcome_check: true,
+                soft_delete: false,
+                trash_retention: Duration::from_secs(7 * 24 * 60 * 60),
+                denied_message: None,
+                explain_denial: false,
+                api_token: None,
+                transfer_token: None,
+                reject_chat_title: false,
+                max_db_entries: None,
+                db_overflow: CapacityPolicy::Evict,
+                replica_refresh: None,
+                startup_retries: 5,
+                statsd: None,
+                command_descriptions: HashMap::new(),
+                signature_grace: Duration::from_secs(24 * 60 * 60),
+                debug_chat_max_failures: 5,
+                title_preview: false,
+                admin_refresh_retries: 3,
+                keep_commands: vec![],
+                purge_on_removal: false,
+                roster_url: None,
+                preserve_emoji_prefix: false,
+                require_mention: false,
+                title_debounce: Duration::from_secs(2),
+                on_external_demotion: DemotionPolicy::Keep,
+                name_title_holder: false,
+                webhook_url: None,
+                webhook_secret: None,
+                webhook_attempts: 3,
+                titles_scan_cap: None,
+                title_blacklist: vec![],
+                title_ttl: None,
+                title_sweep_interval: Duration::from_secs(10 * 60),
+                heartbeat_interval: None,
             }
         );
         Ok(())
     });
 }
+
+#[test]
+fn test_title_blacklist() {
+    figment::Jail::expect_with(|j| {
+        j.set_env("GOLDEN_AXE_TOKEN", "token");
+        j.set_env("GOLDEN_AXE_TITLE_BLACKLIST", "Owner, admin ,Bad Word");
+
+        let conf = Config::from_env().unwrap();
+        assert_eq!(conf.title_blacklist, ["Owner", " admin ", "Bad Word"]);
+        assert!(conf.is_blacklisted("owner"));
+        assert!(conf.is_blacklisted(" ADMIN"));
+        assert!(conf.is_blacklisted("bad word"));
+        assert!(!conf.is_blacklisted("Owner of cats"));
+        assert!(!conf.is_blacklisted("BadWord"));
+        Ok(())
+    });
+}
+
+#[test]
+fn test_replica_period() {
+    figment::Jail::expect_with(|j| {
+        j.set_env("GOLDEN_AXE_TOKEN", "token");
+        assert_eq!(Config::from_env().unwrap().replica_period(), None);
+
+        j.set_env("GOLDEN_AXE_REPLICA_REFRESH", "30s");
+        assert_eq!(
+            Config::from_env().unwrap().replica_period(),
+            Some(Duration::from_secs(30))
+        );
+
+        j.set_env("GOLDEN_AXE_REPLICA_REFRESH", "0s");
+        assert_eq!(Config::from_env().unwrap().replica_period(), None);
+        Ok(())
+    });
+}
@@ -2,8 +2,11 @@
 #![allow(clippy::future_not_send)]
 
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fmt::{self, Display},
     future::Future,
+    sync::LazyLock,
     time::Duration,
 };
 
@@ -11,21 +14,39 @@ use color_eyre::{
     eyre::{bail, ensure, eyre, Context, ContextCompat},
     Result,
 };
-use futures::future::try_join_all;
-use sled::{Db, IVec};
+use futures::{future::join_all, stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{abort, TransactionError},
+    Db, IVec,
+};
 use tap::TapFallible;
 use teloxide::{
-    payloads::{PromoteChatMemberSetters, SendMessageSetters},
+    payloads::{PromoteChatMemberSetters, SendDocumentSetters, SendMessageSetters},
     prelude::*,
     types::{
         Administrator as Admin, ChatId, ChatKind, ChatMember, ChatMemberKind, ChatPublic,
-        PublicChatKind, User, UserId,
+        InputFile, PublicChatKind, User, UserId,
     },
+    utils::html,
+    RequestError,
 };
 use tokio::{time::sleep, try_join};
-use tracing::info;
+use tracing::{info, instrument};
 
-use crate::{catch, send_debug, BotType, Config, BOT, BOT_INFO};
+use crate::{
+    assert_privileges, begin_removal, cancel_revert, catch, check_capacity, check_removal,
+    check_roster, commit_rank, count_added, count_removed, created_at, evict_overflow, expand_rank,
+    expiry_from, fetch_roster, finish_removal, forget_created, forget_holder, forget_signatures,
+    forget_trashed, format_privileges, grant_all, grantable_privileges, held_privileges,
+    interrupted_removal, live_custom_title, mark_created, record_holder, remember_signature,
+    repair_db, required_privileges, reserve_all, resolve_signature, restore_record,
+    scheduled_revert, send_debug, signatures_of, title_history, titles_held, trash_record,
+    trashed_of, unix_now, verify_db, verify_title, BotType, BulkReport, CapacityPolicy,
+    ChatSettings, Check, Config, Cooldown, HeldTitle, Inconsistency, IntegrityReport, Privilege,
+    Reservation, ResyncAction, RosterCheck, TitleStyle, TrashEntry, TtlCache, BOT, BOT_INFO,
+    REPLICA, ROSTER,
+};
 
 /// Context of a "conversion", which is formed when an user sends a command to
 /// the bot.
@@ -53,11 +74,41 @@ pub struct Ctx<'a, S> {
     conversation: S,
 }
 
+/// Maximum length of a custom title allowed by Telegram, in characters as
+/// counted by [`title_len`].
+pub const MAX_TITLE_LEN: usize = 16;
+
+/// How long the title of a chat is cached for [`chat_title`].
+const CHAT_TITLE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long after a promotion the admin list is expected to lag behind, see
+/// [`fetch_admins_expecting`].
+const PROMOTION_GRACE: Duration = Duration::from_secs(30);
+
+/// Delay between fetches of the admin list when it lags behind.
+const ADMIN_REFRESH_DELAY: Duration = Duration::from_secs(1);
+
+/// Last member promoted by the bot in each chat, within [`PROMOTION_GRACE`].
+static RECENTLY_PROMOTED: LazyLock<TtlCache<ChatId, UserId>> =
+    LazyLock::new(|| TtlCache::new(PROMOTION_GRACE));
+
+/// How often each user can send feedback with [`Ctx::send_feedback`].
+const FEEDBACK_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// Throwaway title used by [`Ctx::test_title`].
+const TEST_TITLE: &str = "Golden Axe Test";
+
+/// Most Telegram requests in flight at once when a command fans out over many
+/// members, to stay clear of rate limits.
+pub const MAX_CONCURRENT_REQUESTS: usize = 4;
+
 /// State of the context representing conversation information has been fetched.
 #[derive(Clone)]
 pub struct Loaded {
     pub me: Box<ChatMember>,
     pub sender: Box<ChatMember>,
+    /// Reason why the real identity of an anonymous sender is unknown, if so.
+    pub unidentified: Option<&'static str>,
 }
 
 impl Loaded {
@@ -66,6 +117,7 @@ impl Loaded {
         Self {
             me: Box::new(me),
             sender: Box::new(sender),
+            unidentified: None,
         }
     }
 
@@ -78,6 +130,44 @@ impl Loaded {
     }
 }
 
+/// How a command handled by [`Ctx::handle_with`] went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The command was carried out
+    Done,
+    /// The command was denied or failed, and the sender was told why
+    Rejected,
+}
+
+/// Error of the sender not being one of `allowed`, shown to them as
+/// `message`. The status details are logged to debug by [`Ctx::handle_with`]
+/// when it replies with the error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionDenied {
+    pub allowed: &'static str,
+    pub status: &'static str,
+    pub message: String,
+}
+
+impl Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PermissionDenied {}
+
+impl PermissionDenied {
+    /// Line logged to debug for the denial.
+    #[must_use]
+    pub fn audit(&self) -> String {
+        format!(
+            "Permission denied: sender is {}, only {} allowed",
+            self.status, self.allowed
+        )
+    }
+}
+
 impl<'a, 'u> Ctx<'a, ()> {
     /// Create a new light context.
     ///
@@ -103,31 +193,104 @@ impl<'a, 'u> Ctx<'a, ()> {
     /// This method wraps the function and send all errors directly to the
     /// sender.
     ///
+    /// When `requires_identity` is false, anonymous senders whose identity
+    /// cannot be resolved are allowed through as the anonymous group identity.
+    /// The bot must hold all `privileges` before the function runs. The
+    /// command message is deleted after a while if `delete_command` is set.
+    ///
+    /// Returns whether the function was carried out or the sender was told
+    /// why not.
+    ///
     /// # Errors
     /// Only fetching error and network error will be emitted. Logic errors are
     /// sent to the sender.
-    pub async fn handle_with<Func, Fut>(&self, func: Func) -> Result<()>
+    pub async fn handle_with<Func, Fut>(
+        &self,
+        requires_identity: bool,
+        privileges: &[Privilege],
+        delete_command: bool,
+        func: Func,
+    ) -> Result<Outcome>
     where
         Fut: Future<Output = Result<()>> + Send,
         Func: FnOnce(Ctx<'a, Loaded>) -> Fut + Send,
     {
+        static NOT_ADMIN: LazyLock<Cooldown<ChatId>> =
+            LazyLock::new(|| Cooldown::new(Config::get().not_admin_cooldown));
+
         let ctx = self.clone();
         let mut loaded = ctx.upgrade().await?;
 
+        // Nothing works without admin rights, so instead of erroring on every
+        // command, remind once per cooldown until someone grants admin
+        if let Err(e) = loaded.assert_bot_admin()
+            && self.assert_in_group().is_ok()
+        {
+            if NOT_ADMIN.try_start(self.chat_id()) {
+                self.reply_to(e.to_string()).await?;
+            }
+            return Ok(Outcome::Rejected);
+        }
+
         // Error occurred in inner will be sent to user directly - Logic error
         let inner = move || async {
             loaded.assert_in_group()?;
+            assert_privileges(privileges, &loaded.me_in_chat().kind)?;
             loaded.fetch_real_chat_member().await?;
+            if requires_identity {
+                loaded.assert_identified()?;
+            }
             func(loaded).await?;
             Result::<()>::Ok(())
         };
 
-        if let Err(e) = inner().await {
-            self.reply_to_then_del(e.to_string()).await?;
+        let outcome = match inner().await {
+            Ok(()) => Outcome::Done,
+            Err(e) => {
+                if let Some(denied) = e.downcast_ref::<PermissionDenied>() {
+                    send_debug(&denied.audit());
+                }
+                self.reply_to_then_del(e.to_string()).await?;
+                Outcome::Rejected
+            }
+        };
+        if delete_command {
+            self.del_msg_delayed();
         }
-        self.del_msg_delayed();
 
-        Ok(())
+        Ok(outcome)
+    }
+
+    /// Handle the command with the given function without fetching the
+    /// conversation information. Used by commands that are not bound to a
+    /// group, e.g. superuser commands. The command message is deleted after a
+    /// while if `delete_command` is set. Returns the outcome like
+    /// [`Ctx::handle_with`].
+    ///
+    /// # Errors
+    /// Only network error will be emitted. Logic errors are sent to the
+    /// sender.
+    pub async fn handle_light_with<Func, Fut>(
+        &self,
+        delete_command: bool,
+        func: Func,
+    ) -> Result<Outcome>
+    where
+        Fut: Future<Output = Result<()>> + Send,
+        Func: FnOnce(Self) -> Fut + Send,
+    {
+        let outcome = match func(self.clone()).await {
+            Ok(()) => Outcome::Done,
+            Err(e) => {
+                self.reply_to_then_del(e.to_string()).await?;
+                Outcome::Rejected
+            }
+        };
+        if delete_command {
+            self.del_msg_delayed();
+        }
+
+        Ok(outcome)
     }
 }
 
@@ -139,6 +302,13 @@ impl<'a, S> Ctx<'a, S> {
         self.bot
     }
 
+    /// Get the db reference
+    #[inline]
+    #[must_use]
+    pub const fn db(&self) -> &Db {
+        self.db
+    }
+
     /// Get the msg reference
     #[inline]
     #[must_use]
@@ -182,6 +352,7 @@ impl<'a, S> Ctx<'a, S> {
             chat_id: self.chat_id(),
             user_id: self.sender_id(),
             title: title.into(),
+            expires_at: expiry_from(unix_now()),
         };
 
         record.insert_into(self.db)?;
@@ -189,26 +360,240 @@ impl<'a, S> Ctx<'a, S> {
         Ok(())
     }
 
-    /// Set title of user
+    /// Set title of user, see [`apply_title`].
     ///
     /// # Errors
     /// If the user cannot be set a title or requesting error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
     pub async fn set_title(&self, title: impl Into<String> + Send) -> Result<()> {
-        let title = title.into();
-        let existing = self.get_record_with_sig(&title)?;
-        ensure!(existing.is_none(), "Title already in use");
-        self.remove_title_with_id()?;
+        let mut title = title.into();
+        let previous = self.get_record_with_id()?;
+        if Config::get().preserve_emoji_prefix
+            && let Some(previous) = &previous
+        {
+            let chat_prefix = self.settings()?.title_prefix.unwrap_or_default();
+            if let Some(preserved) = preserve_emoji_prefix(&previous.title, &title, &chat_prefix) {
+                title = preserved;
+            }
+        }
+        apply_title(self.bot, self.db, self.chat_id(), self.sender_id(), title).await?;
+        // Messages signed with the previous title should still resolve to the
+        // anonymous sender for a while
+        if self.is_anonymous()
+            && let Some(previous) = previous
+            && self.get_record_with_id()?.as_ref() != Some(&previous)
+        {
+            remember_signature(
+                self.db,
+                self.chat_id(),
+                &previous.title,
+                self.sender_id(),
+                unix_now() + Config::get().signature_grace.as_secs(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reserve the title for given user, so nobody else can set it until the
+    /// user claims it with `/title`.
+    ///
+    /// # Errors
+    /// If the title is used by someone else or unable to save to db.
+    pub fn reserve_title(&self, user_id: UserId, title: &str) -> Result<()> {
+        ensure!(!title.is_empty(), "Title cannot be empty");
+        if let Some(existing) = self.get_record_with_sig(title)? {
+            ensure!(existing.user_id == user_id, "Title already in use");
+        }
+        Reservation {
+            title: title.to_owned(),
+            chat_id: self.chat_id(),
+            user_id,
+        }
+        .insert_into(self.db)
+    }
+
+    /// Reserve each title for the user in `entries`, see [`reserve_all`].
+    ///
+    /// # Errors
+    /// If any title is empty or the database returns an error.
+    pub fn reserve_titles(&self, entries: Vec<(UserId, String)>) -> Result<BulkReport> {
+        let reservations = entries
+            .into_iter()
+            .map(|(user_id, title)| {
+                ensure!(!title.is_empty(), "Title cannot be empty");
+                Ok(Reservation {
+                    title,
+                    chat_id: self.chat_id(),
+                    user_id,
+                })
+            })
+            .try_collect::<Vec<_>>()?;
+        reserve_all(self.db, reservations)
+    }
+
+    /// List users who held the title in current chat, oldest first.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn list_title_history(&self, title: &str) -> Result<String> {
+        ensure!(!title.is_empty(), "Title cannot be empty");
+        let entries = title_history(self.db, self.chat_id(), title)?;
+        if entries.is_empty() {
+            return Ok("Nobody has held this title.".to_owned());
+        }
+        Ok(entries
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// List reservations in current chat, one per line.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn list_reservations(&self) -> Result<String> {
+        let reservations = Reservation::list_in_chat(self.db, self.chat_id())?;
+        if reservations.is_empty() {
+            return Ok("No reservations found.".to_owned());
+        }
+        Ok(reservations
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Revoke the reservation of the title.
+    ///
+    /// # Errors
+    /// If the title is not reserved or unable to remove from db.
+    pub fn unreserve(&self, title: &str) -> Result<()> {
+        ensure!(!title.is_empty(), "Title cannot be empty");
+        Reservation::get(self.db, self.chat_id(), title)?
+            .ok_or_else(|| eyre!("Title is not reserved"))?
+            .remove_from(self.db)
+    }
+
+    /// Clear the custom title of the sender on Telegram side.
+    ///
+    /// # Errors
+    /// If the title cannot be cleared or requesting error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn clear_title(&self) -> Result<()> {
         self.bot
-            .set_chat_administrator_custom_title(self.chat_id(), self.sender_id(), &title)
+            .set_chat_administrator_custom_title(self.chat_id(), self.sender_id(), "")
             .await
             .map_err(|error| {
                 send_debug(&error);
-                eyre!("Failed to set title")
+                eyre!("Failed to clear title")
             })?;
-        self.save_title(&title)?;
         Ok(())
     }
 
+    /// Re-apply the prefix and suffix of the chat to all existing titles.
+    /// Titles that no longer fit, are already in use or fail to be set are
+    /// skipped and reported.
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn reapply_decoration(&self) -> Result<String> {
+        let plan = self.settings()?.plan_reapply(self.list_titles()?);
+        let mut skipped = plan
+            .too_long
+            .iter()
+            .map(|record| format!("{record} (too long)"))
+            .collect::<Vec<_>>();
+        let mut updated = 0;
+        for (record, title) in plan.updates {
+            if self.get_record_with_sig(&title)?.is_some() {
+                skipped.push(format!("{record} (already in use)"));
+                continue;
+            }
+            if let Err(error) = self
+                .bot
+                .set_chat_administrator_custom_title(self.chat_id(), record.user_id, &title)
+                .await
+            {
+                send_debug(&error);
+                skipped.push(format!("{record} (failed to set)"));
+                continue;
+            }
+            record.remove_from(self.db)?;
+            TitleRecord { title, ..record }.insert_into(self.db)?;
+            updated += 1;
+        }
+
+        let mut report = format!("Updated {updated} titles");
+        if !skipped.is_empty() {
+            report.push_str("\nSkipped:\n");
+            report.push_str(&skipped.join("\n"));
+        }
+        Ok(report)
+    }
+
+    /// Describe every chat that has title records with its title and member
+    /// count. Chats that cannot be fetched (e.g. the bot was removed) are
+    /// marked as unavailable.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn describe_chats(&self) -> Result<String> {
+        let chats = TitleRecord::list_chats(self.db)?;
+        if chats.is_empty() {
+            return Ok("No chats found.".to_owned());
+        }
+        let lines = join_all(chats.into_iter().map(|chat_id| async move {
+            match try_join!(
+                self.bot.get_chat(chat_id),
+                self.bot.get_chat_member_count(chat_id)
+            ) {
+                Ok((chat, count)) => format!(
+                    "<code>{}</code> {} ({} members)",
+                    chat_id,
+                    html::escape(chat.title().unwrap_or("Unknown")),
+                    count
+                ),
+                Err(error) => format!(
+                    "<code>{}</code> unavailable ({})",
+                    chat_id,
+                    html::escape(&error.to_string())
+                ),
+            }
+        }))
+        .await;
+        Ok(lines.join("\n"))
+    }
+
+    /// Cross-check the whole database, see [`verify_db`].
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn verify_db(&self) -> Result<IntegrityReport> {
+        verify_db(self.db)
+    }
+
+    /// Repair given inconsistencies, see [`repair_db`].
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn repair_db(&self, inconsistencies: &[Inconsistency]) -> Result<()> {
+        repair_db(self.db, inconsistencies)
+    }
+
+    /// Check the keys of the title in current chat and repair what's wrong,
+    /// returns the inconsistencies found. See [`verify_title`].
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn repair_title(&self, title: &str) -> Result<Vec<Inconsistency>> {
+        let found = verify_title(self.db, self.chat_id(), title)?;
+        repair_db(self.db, &found)?;
+        Ok(found)
+    }
+
     /// Get the all titles in current chat
     ///
     /// # Errors
@@ -217,15 +602,33 @@ impl<'a, S> Ctx<'a, S> {
         TitleRecord::list_in_chat(self.db, self.chat_id())
     }
 
-    /// Remove the given title from db with signature
+    /// Export all titles in current chat as a JSON file named
+    /// `titles-<chat_id>.json`, see [`export_titles`].
     ///
     /// # Errors
-    /// When unable to remove from db
-    pub fn remove_title_with_sig(&self, sig: &str) -> Result<()> {
-        let existing = self.get_record_with_sig(sig)?;
-        match existing {
-            None => Ok(()),
-            Some(existing) => existing.remove_from(self.db),
+    /// If the database returns an error or the data is not in good shape.
+    pub fn export_titles(&self) -> Result<InputFile> {
+        let json = export_titles(self.db, &self.list_titles()?)?;
+        Ok(InputFile::memory(json).file_name(format!("titles-{}.json", self.chat_id())))
+    }
+
+    /// Get titles in current chat for listing, served from [`REPLICA`] when
+    /// `replica_refresh` is set and not zero, so they may lag behind. At most
+    /// `titles_scan_cap` records are returned, along with whether there were
+    /// more.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn list_titles_for_listing(&self) -> Result<(Vec<TitleRecord>, bool)> {
+        let conf = Config::get();
+        let cap = conf.titles_scan_cap.unwrap_or(usize::MAX);
+        if conf.replica_period().is_some() {
+            let mut records = REPLICA.list_in_chat(self.chat_id());
+            let capped = records.len() > cap;
+            records.truncate(cap);
+            Ok((records, capped))
+        } else {
+            TitleRecord::list_in_chat_capped(self.db, self.chat_id(), cap)
         }
     }
 
@@ -263,6 +666,7 @@ impl<'a, S> Ctx<'a, S> {
     ///
     /// # Errors
     /// If the chat member information cannot be fetched.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
     async fn upgrade(self) -> Result<Ctx<'a, Loaded>> {
         let (me, sender) = try_join!(
             self.bot.get_chat_member(
@@ -287,137 +691,206 @@ impl<'a, S> Ctx<'a, S> {
         })
     }
 
-    /// Find specific admin in the current chat
+    /// Find specific admin in the current chat by username, in any form
+    /// accepted by [`parse_username`]
     ///
     /// # Errors
-    /// API errors
+    /// If the username is malformed or API errors
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
     pub async fn find_admin_with_username(&self, username: &str) -> Result<Option<ChatMember>> {
+        let username = parse_username(username)
+            .ok_or_else(|| eyre!("Bad username `{}`", html::escape(username)))?;
         Ok(self
-            .bot
-            .get_chat_administrators(self.chat_id())
+            .chat_admins()
             .await?
             .into_iter()
-            .find(|user| user.user.username.as_deref() == Some(username)))
+            .find(|member| has_username(&member.user, &username)))
     }
 
-    /// Demote everyone and remove all titles in chat
+    /// All admins of the current chat, including the member just promoted by
+    /// the bot, if any.
     ///
     /// # Errors
-    /// If the bot cannot demote everyone or the database cannot remove all
-    pub async fn nuke(&self) -> Result<()> {
+    /// API errors
+    pub async fn chat_admins(&self) -> Result<Vec<ChatMember>> {
         let chat_id = self.chat_id();
-
-        let all_admins = self
-            .bot
-            .get_chat_administrators(self.chat_id())
-            .await
-            .map_err(|e| {
-                send_debug(&e);
-                eyre!("Failed to load all admins")
-            })?;
-
-        let all_count = all_admins.len() - 1;
-
-        let res = try_join_all(
-            all_admins
-                .into_iter()
-                .filter(|x| x.is_administrator() && x.can_be_edited())
-                .map(|member| {
-                    let id = member.user.id;
-                    if let Some(record) = TitleRecord::get_with_id(self.db, chat_id, id)? {
-                        record.remove_from(self.db)?;
-                    };
-                    let fut = async move {
-                        self.bot.promote_chat_member(chat_id, id).send().await?;
-
-                        Result::<_>::Ok(())
-                    };
-                    Result::<_>::Ok(fut)
-                })
-                .try_collect::<Vec<_>>()?,
+        fetch_admins_expecting(
+            || async { Ok(self.bot.get_chat_administrators(chat_id).await?) },
+            RECENTLY_PROMOTED.get(&chat_id),
+            Config::get().admin_refresh_retries,
+            ADMIN_REFRESH_DELAY,
         )
         .await
-        .map_err(|e| {
-            send_debug(&e);
-            eyre!("Failed to load remove all admins")
-        })?;
-
-        self.reply_to(format!("Found {} admins, demoted {}", all_count, res.len()))
-            .await?;
+    }
 
-        Ok(())
+    /// Resolve a user from `@username` (admins only) or numeric user id.
+    ///
+    /// # Errors
+    /// If the user cannot be found or API errors
+    pub async fn resolve_user(&self, target: &str) -> Result<UserId> {
+        match parse_username(target) {
+            Some(username) => self
+                .find_admin_with_username(&username)
+                .await?
+                .map(|member| member.user.id)
+                .ok_or_else(|| eyre!("No such user")),
+            None => target.trim().parse().map(UserId).map_err(|_| {
+                eyre!(
+                    "Expect @username or user id, got `{}`",
+                    html::escape(target)
+                )
+            }),
+        }
     }
 
-    /// Make the user anonymous
+    /// Check the title of the user given as `@username` or user id against
+    /// the roster fetched from `roster_url`.
     ///
     /// # Errors
-    /// If the user cannot be promoted or requesting error.
-    pub async fn set_anonymous(&self) -> Result<()> {
-        self.bot
-            .promote_chat_member(self.chat_id(), self.sender_id())
-            .can_invite_users(true)
-            .is_anonymous(true)
+    /// If no roster is configured, the user cannot be found or the roster
+    /// cannot be fetched.
+    pub async fn verify_roster(&self, target: &str) -> Result<RosterCheck> {
+        let url = Config::get()
+            .roster_url
+            .as_deref()
+            .wrap_err("No roster is configured")?;
+        self.assert_in_group()?;
+        let chat_id = self.chat_id();
+        let user_id = self.resolve_user(target).await?;
+        let record = TitleRecord::get_with_id(self.db, chat_id, user_id)?;
+        let entries = ROSTER
+            .entries(chat_id, || fetch_roster(url, chat_id))
             .await
             .map_err(|error| {
                 send_debug(&error);
-                eyre!("Failed to make anonymous")
+                eyre!("Failed to fetch the roster")
             })?;
-        Ok(())
+        Ok(check_roster(
+            &entries,
+            user_id,
+            record.as_ref().map(|record| record.title.as_str()),
+        ))
     }
 
-    /// Run [`promote_chat_member`], with `can_invite_users` privilege.
+    /// Statuses of the holders of the records, e.g. `admin` or `left`, keyed
+    /// by user id. Admins are fetched at once, other holders one by one.
     ///
     /// # Errors
-    /// Failed when failed to promote member. This method does not assure that
-    /// the bot is privileged enough to promote the member, so it should be
-    /// checked by the caller.
+    /// API errors
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn member_statuses(
+        &self,
+        records: &[TitleRecord],
+    ) -> Result<HashMap<UserId, &'static str>> {
+        let chat_id = self.chat_id();
+        let members = fetch_members(
+            records.iter().map(|record| record.user_id),
+            || async { Ok(self.bot.get_chat_administrators(chat_id).await?) },
+            |user_id| async move { Ok(self.bot.get_chat_member(chat_id, user_id).await?) },
+        )
+        .await?;
+        Ok(members
+            .into_iter()
+            .map(|(user_id, member)| (user_id, chat_member_kind_to_str(&member.kind)))
+            .collect())
+    }
+
+    /// Demote everyone and remove all titles in chat
     ///
-    /// [`promote_chat_member`]: https://core.telegram.org/bots/api#promotechatmember
-    pub async fn promote(&self) -> Result<()> {
-        self.bot
-            .promote_chat_member(self.chat_id(), self.sender_id())
-            .can_invite_users(true)
-            .send()
-            .await
-            .map_err(|error| {
-                send_debug(&error);
-                eyre!("Promote member error")
-            })?;
+    /// # Errors
+    /// If the bot cannot demote everyone or the database cannot remove all
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn nuke(&self, reason: &str) -> Result<()> {
+        let chat_id = self.chat_id();
+        self.settings()?.check_nuke_reason(reason)?;
+
+        let all_admins = self.chat_admins().await.map_err(|e| {
+            send_debug(&e);
+            eyre!("Failed to load all admins")
+        })?;
+
+        let all_count = all_admins.len() - 1;
+        let targets = all_admins
+            .into_iter()
+            .filter(|x| x.is_administrator() && x.can_be_edited())
+            .map(|member| member.user.id)
+            .collect::<Vec<_>>();
+
+        // Titles are only removed once demotions are done, all at once
+        let interrupted = interrupted_removal(self.db, chat_id)?;
+        begin_removal(self.db, chat_id, &targets)?;
+        let results = join_all(
+            targets
+                .iter()
+                .map(|&id| self.bot.promote_chat_member(chat_id, id).send()),
+        )
+        .await;
+        let mut demoted = Vec::with_capacity(targets.len());
+        for (&id, res) in targets.iter().zip(results) {
+            match res {
+                Ok(_) => demoted.push(id),
+                Err(error) => send_debug(&error),
+            }
+        }
+        let failed = targets.len() - demoted.len();
+        // Admins demoted by an interrupted nuke only have their titles left
+        let stale = interrupted
+            .into_iter()
+            .filter(|id| !targets.contains(id))
+            .collect::<Vec<_>>();
+        finish_removal(self.db, chat_id, &[demoted.as_slice(), &stale].concat())?;
+
+        send_debug(&nuke_audit(
+            chat_id,
+            self.sender_id(),
+            demoted.len(),
+            reason,
+        ));
+        let mut report = format!("Found {} admins, demoted {}", all_count, demoted.len());
+        if failed > 0 {
+            report.push_str(&format!(", failed to demote {failed}"));
+        }
+        self.reply_to(report).await?;
+
         Ok(())
     }
 
-    /// Run [`promote_chat_member`], with all privileges being false.
+    /// Reply to the sender with a message formatted in HTML, where user input
+    /// must have been escaped. Use [`Ctx::reply_text`] for plain text.
     ///
     /// # Errors
-    /// Failed when failed to demote the member. This method does not assure
-    /// that the bot is privileged enough to promote the member, so it
-    /// should be checked by the caller.
-    pub async fn demote(&self) -> Result<()> {
+    /// When the message sending fails.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn reply_to(&self, text: impl Into<String> + Send) -> Result<()> {
         self.bot
-            .promote_chat_member(self.chat_id(), self.sender_id())
-            .send()
-            .await
-            .map_err(|error| {
-                send_debug(&error);
-                eyre!("Demote member error")
-            })?;
+            .send_message(self.chat_id(), text)
+            .reply_to_message_id(self.msg.id)
+            .await?;
         Ok(())
     }
 
-    /// Reply to the sender with a message.
+    /// Reply to the sender with plain text, escaped before sending.
     ///
     /// # Errors
     /// When the message sending fails.
-    pub async fn reply_to(&self, text: impl Into<String> + Send) -> Result<()> {
+    pub async fn reply_text(&self, text: &str) -> Result<()> {
+        self.reply_to(html::escape(text)).await
+    }
+
+    /// Reply to the sender with a file.
+    ///
+    /// # Errors
+    /// When the file sending fails.
+    pub async fn reply_with_file(&self, file: InputFile) -> Result<()> {
         self.bot
-            .send_message(self.chat_id(), text)
+            .send_document(self.chat_id(), file)
             .reply_to_message_id(self.msg.id)
             .await?;
         Ok(())
     }
 
-    /// Reply to the sender with a message and delete the msg after a period of
-    /// time.
+    /// Reply to the sender with a message formatted in HTML and delete the msg
+    /// after a period of time. Use [`Ctx::reply_text_then_del`] for plain text.
     ///
     /// # Errors
     /// When fails to send the message.
@@ -431,15 +904,31 @@ impl<'a, S> Ctx<'a, S> {
         Ok(())
     }
 
+    /// Like [`Ctx::reply_to_then_del`], with plain text escaped before sending.
+    ///
+    /// # Errors
+    /// When fails to send the message.
+    pub async fn reply_text_then_del(&self, text: &str) -> Result<()> {
+        self.reply_to_then_del(html::escape(text)).await
+    }
+
     pub fn del_msg_delayed(&self) {
         self.del_msg_delayed_with_id(self.msg.id);
     }
 
-    /// Delete the message with the given id after a period of time.
+    /// Delete the message with the given id after a period of time, unless
+    /// auto-deletion is paused in the chat.
     ///
     /// # Panics
     /// If either bot or config is not initialized.
     pub fn del_msg_delayed_with_id(&self, msg_id: i32) {
+        // Messages are kept while auto-deletion is paused with `/verbose`
+        if self
+            .settings()
+            .map_or(false, |settings| settings.is_verbose(unix_now()))
+        {
+            return;
+        }
         let chat_id = self.chat_id();
 
         tokio::spawn(async move {
@@ -454,6 +943,7 @@ impl<'a, S> Ctx<'a, S> {
     ///
     /// # Errors
     /// If the bot cannot delete the message.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
     pub async fn del_msg(&self) -> Result<()> {
         let chat_id = self.chat_id();
         self.bot.delete_message(chat_id, self.msg.id).send().await?;
@@ -465,34 +955,176 @@ impl<'a, S> Ctx<'a, S> {
     /// # Errors
     /// When the message deletion failed.
     pub async fn done(&self) -> Result<()> {
-        self.reply_to_then_del("Done! Wait for a while to take effect.")
-            .await
+        let settings = self.settings()?;
+        self.reply_text_then_del(settings.done_message()).await
     }
 
-    /// A guard method to assure the user is in a public group
+    /// Like [`Ctx::done`], followed by a preview of the title of the sender.
     ///
     /// # Errors
-    /// If the user is not in a public group.
-    pub fn assert_in_group(&self) -> Result<()> {
-        if matches!(
-            self.msg.chat.kind,
-            ChatKind::Public(ChatPublic {
-                kind: PublicChatKind::Group(_) | PublicChatKind::Supergroup(_),
-                ..
-            })
-        ) {
-            Ok(())
-        } else {
-            bail!("This command can only be used in group")
-        }
+    /// If the database returns an error or the message cannot be sent.
+    pub async fn done_with_preview(&self) -> Result<()> {
+        let settings = self.settings()?;
+        let title = self
+            .get_record_with_id()?
+            .map(|record| record.title)
+            .unwrap_or_default();
+        self.reply_to_then_del(format!(
+            "{}\n{}",
+            html::escape(settings.done_message()),
+            title_preview(&self.sender.full_name(), &title)
+        ))
+        .await
     }
-}
 
-impl<'a, 'u> Ctx<'a, Loaded> {
-    #[inline]
-    #[must_use]
-    pub const fn me_in_chat(&self) -> &ChatMember {
-        &self.conversation.me
+    /// Get settings of current chat
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn settings(&self) -> Result<ChatSettings> {
+        ChatSettings::load(self.db, self.chat_id())
+    }
+
+    /// Privileges granted on promotion in current chat, see
+    /// [`ChatSettings::promote_privileges`].
+    ///
+    /// # Errors
+    /// If unable to load settings.
+    pub fn promote_privileges(&self) -> Result<Vec<Privilege>> {
+        Ok(self
+            .settings()?
+            .promote_privileges(&Config::get().promote_privileges)
+            .to_vec())
+    }
+
+    /// Update a setting of current chat by its key.
+    ///
+    /// # Errors
+    /// If the key or value is invalid, or unable to save to db.
+    pub fn update_setting(&self, key: &str, value: &str) -> Result<()> {
+        let mut settings = self.settings()?;
+        settings.set(key, value)?;
+        settings.save(self.db, self.chat_id())
+    }
+
+    /// Pause auto-deletion in current chat for given duration, or resume it
+    /// if the duration is zero.
+    ///
+    /// # Errors
+    /// If unable to load or save settings.
+    pub fn pause_deletion(&self, duration: Duration) -> Result<()> {
+        let mut settings = self.settings()?;
+        settings.pause_deletion(unix_now(), duration);
+        settings.save(self.db, self.chat_id())
+    }
+
+    /// Describe the title quota of current chat and how much is used.
+    ///
+    /// # Errors
+    /// If unable to load settings or list titles.
+    pub fn describe_quota(&self) -> Result<String> {
+        Ok(self.settings()?.describe_quota(self.list_titles()?.len()))
+    }
+
+    /// Describe the member given as `@username` (admins only) or user id as
+    /// seen by Telegram, for debugging privilege checks.
+    ///
+    /// # Errors
+    /// If the user cannot be found or API errors
+    pub async fn describe_member(&self, target: &str) -> Result<String> {
+        let user_id = self.resolve_user(target).await?;
+        let member = self.bot.get_chat_member(self.chat_id(), user_id).await?;
+        Ok(render_member(&member))
+    }
+
+    /// Describe the rules titles of the current chat must follow.
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn describe_limits(&self) -> Result<String> {
+        Ok(self.settings()?.describe_limits(
+            self.list_titles()?.len(),
+            Reservation::list_in_chat(self.db, self.chat_id())?.len(),
+            &Config::get().title_blacklist,
+            Config::get().reject_chat_title,
+        ))
+    }
+
+    /// Set the title quota of current chat, see [`ChatSettings::set_quota`].
+    ///
+    /// # Errors
+    /// If the value is invalid, or unable to load or save settings.
+    pub fn set_quota(&self, value: &str) -> Result<()> {
+        let mut settings = self.settings()?;
+        settings.set_quota(value)?;
+        settings.save(self.db, self.chat_id())
+    }
+
+    /// Add or remove a blocked word of current chat, see
+    /// [`ChatSettings::check_words`].
+    ///
+    /// # Errors
+    /// If the word is invalid, or unable to load or save settings.
+    pub fn update_blocked_word(&self, add: bool, word: &str) -> Result<()> {
+        let mut settings = self.settings()?;
+        if add {
+            settings.add_blocked_word(word)?;
+        } else {
+            settings.remove_blocked_word(word)?;
+        }
+        settings.save(self.db, self.chat_id())
+    }
+
+    /// Relay feedback of the sender to the debug chat, see [`relay_feedback`].
+    ///
+    /// # Errors
+    /// If the feedback is empty or the sender sent feedback recently.
+    pub fn send_feedback(&self, text: &str) -> Result<()> {
+        static FEEDBACK: LazyLock<Cooldown<UserId>> =
+            LazyLock::new(|| Cooldown::new(FEEDBACK_COOLDOWN));
+
+        relay_feedback(&FEEDBACK, self.chat_id(), self.sender(), text, |feedback| {
+            send_debug(&feedback);
+        })
+    }
+
+    /// A guard method to assure the sender is one of the configured
+    /// superusers
+    ///
+    /// # Errors
+    /// If the sender is not a superuser.
+    pub fn assert_superuser(&self) -> Result<()> {
+        ensure!(
+            Config::get().superusers.contains(&self.sender_id().0),
+            "This function is superuser only"
+        );
+        Ok(())
+    }
+
+    /// A guard method to assure the user is in a public group
+    ///
+    /// # Errors
+    /// If the user is not in a public group.
+    pub fn assert_in_group(&self) -> Result<()> {
+        if matches!(
+            self.msg.chat.kind,
+            ChatKind::Public(ChatPublic {
+                kind: PublicChatKind::Group(_) | PublicChatKind::Supergroup(_),
+                ..
+            })
+        ) {
+            Ok(())
+        } else {
+            bail!("This command can only be used in group")
+        }
+    }
+}
+
+impl<'a, 'u> Ctx<'a, Loaded> {
+    #[inline]
+    #[must_use]
+    pub const fn me_in_chat(&self) -> &ChatMember {
+        &self.conversation.me
     }
 
     #[inline]
@@ -506,7 +1138,7 @@ impl<'a, 'u> Ctx<'a, Loaded> {
     ///
     /// # Errors
     /// If the function returns an error.
-    pub async fn with_sender<Func, Fut>(&mut self, sender: ChatMember, func: Func) -> Result<()>
+    pub async fn with_sender<Func, Fut>(&self, sender: ChatMember, func: Func) -> Result<()>
     where
         Fut: Future<Output = Result<()>>,
         Func: FnOnce(Ctx<'a, Loaded>) -> Fut,
@@ -524,10 +1156,73 @@ impl<'a, 'u> Ctx<'a, Loaded> {
         Ok(())
     }
 
-    /// If sender is anonymous, try find real sender
+    /// Give `base` to every user listed in `targets`, numbered when shared
+    /// (see [`numbered_titles`]), returning a report of each user. At most
+    /// [`MAX_CONCURRENT_REQUESTS`] users are handled at once, and a failure
+    /// doesn't stop the remaining users.
+    ///
+    /// # Errors
+    /// If no user is listed or the database returns an error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn bulk_title(&self, base: &str, targets: &str) -> Result<String> {
+        let targets = parse_targets(targets);
+        ensure!(
+            !targets.is_empty(),
+            "Reply to a message listing @usernames or user ids"
+        );
+        let titles = numbered_titles(self.db, self.chat_id(), base, targets.len())?;
+        let mut results = stream::iter(targets.into_iter().zip(titles).enumerate())
+            .map(|(index, (target, title))| async move {
+                let line = format!(
+                    "{}: <code>{}</code>",
+                    html::escape(target),
+                    html::escape(&title)
+                );
+                let res = async {
+                    let user_id = self.resolve_user(target).await?;
+                    let member = self.bot.get_chat_member(self.chat_id(), user_id).await?;
+                    self.with_sender(member, |ctx| async move {
+                        ctx.prep_edit().await?;
+                        ctx.set_title(title).await
+                    })
+                    .await
+                }
+                .await;
+                match res {
+                    Ok(()) => (index, true, line),
+                    Err(error) => (
+                        index,
+                        false,
+                        format!("{line} failed: {}", error_html(&error)),
+                    ),
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .collect::<Vec<_>>()
+            .await;
+        // Reported in the order listed, whichever finished first
+        results.sort_unstable_by_key(|(index, ..)| *index);
+        let applied = results.iter().filter(|(_, applied, _)| *applied).count();
+        let lines = results
+            .into_iter()
+            .map(|(.., line)| line)
+            .collect::<Vec<_>>();
+        Ok(format!(
+            "Applied {applied} of {} titles\n{}",
+            lines.len(),
+            lines.join("\n")
+        ))
+    }
+
+    /// If sender is anonymous, try find real sender. When the real sender
+    /// cannot be found, the sender is kept as the anonymous group identity and
+    /// the reason is recorded for [`assert_identified`].
     ///
     /// # Errors
-    /// If the sender is not found or error during fetching
+    /// If error occurred during fetching
+    ///
+    /// [`assert_identified`]: Ctx::assert_identified
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
     pub async fn fetch_real_chat_member(&mut self) -> Result<()> {
         // Sender is anonymous, try to decode the identity
         if self.conversation.sender.user.first_name == "Group" {
@@ -536,62 +1231,382 @@ impl<'a, 'u> Ctx<'a, Loaded> {
             let sig = match self.msg.author_signature() {
                 Some(sig) => sig,
                 None => {
-                    bail!("Unable to identify target (no title)")
+                    self.conversation.unidentified = Some("no title");
+                    return Ok(());
                 }
             };
-            let real = match self.get_record_with_sig(sig)? {
-                Some(real) => real,
-                None => bail!("Unable to identify target (no record found)"),
+            let user_id = match self.get_record_with_sig(sig)? {
+                Some(real) => real.user_id,
+                None => match resolve_signature(self.db, self.chat_id(), sig, unix_now())? {
+                    Some(user_id) => user_id,
+                    None => {
+                        self.conversation.unidentified = Some("no record found");
+                        return Ok(());
+                    }
+                },
             };
-            let real = self.bot.get_chat_member(real.chat_id, real.user_id).await?;
+            let real = self.bot.get_chat_member(self.chat_id(), user_id).await?;
             self.sender = real.user.clone();
             self.conversation.sender = real.into();
         }
         Ok(())
     }
 
+    /// Run [`promote_chat_member`], granting privileges configured in
+    /// `promote_privileges` of the chat, or of the config if unset, that the
+    /// bot itself holds.
+    ///
+    /// # Errors
+    /// Failed when failed to promote member. This method does not assure that
+    /// the bot is privileged enough to promote the member, so it should be
+    /// checked by the caller.
+    ///
+    /// [`promote_chat_member`]: https://core.telegram.org/bots/api#promotechatmember
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn promote(&self) -> Result<()> {
+        let req = self
+            .bot
+            .promote_chat_member(self.chat_id(), self.sender_id());
+        grant_all(req, &self.privileges_to_grant()?)
+            .send()
+            .await
+            .map_err(|error| {
+                send_debug(&error);
+                eyre!("Promote member error")
+            })?;
+        RECENTLY_PROMOTED.insert(self.chat_id(), self.sender_id());
+        Ok(())
+    }
+
+    /// Make the user anonymous
+    ///
+    /// # Errors
+    /// If the user cannot be promoted or requesting error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn set_anonymous(&self) -> Result<()> {
+        let req = self
+            .bot
+            .promote_chat_member(self.chat_id(), self.sender_id());
+        grant_all(req, &self.privileges_to_grant()?)
+            .is_anonymous(true)
+            .await
+            .map_err(|error| {
+                send_debug(&error);
+                eyre!("Failed to make anonymous")
+            })?;
+        Ok(())
+    }
+
+    /// Privileges granted on promotion in current chat that the bot holds, see
+    /// [`grantable_privileges`]. Every promotion goes through this, as
+    /// Telegram refuses to grant rights the bot lacks.
+    ///
+    /// # Errors
+    /// If unable to load settings, or the bot holds none of the privileges.
+    fn privileges_to_grant(&self) -> Result<Vec<Privilege>> {
+        let privileges = grantable_privileges(&self.promote_privileges()?, &self.me_in_chat().kind);
+        ensure!(
+            !privileges.is_empty(),
+            "I hold none of the privileges to grant, promoting would demote instead"
+        );
+        Ok(privileges)
+    }
+
+    /// Run [`promote_chat_member`], with all privileges being false. When
+    /// `demote_clears_title` is set, the custom title is cleared beforehand so
+    /// it won't come back if the member is re-promoted by others.
+    ///
+    /// # Errors
+    /// Failed when failed to demote the member. This method does not assure
+    /// that the bot is privileged enough to promote the member, so it
+    /// should be checked by the caller.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn demote(&self) -> Result<()> {
+        if Config::get().demote_clears_title && self.sender_in_chat().is_administrator() {
+            self.clear_title().await?;
+        }
+        self.bot
+            .promote_chat_member(self.chat_id(), self.sender_id())
+            .send()
+            .await
+            .map_err(|error| {
+                send_debug(&error);
+                eyre!("Demote member error")
+            })?;
+        Ok(())
+    }
+
+    /// Demote the sender with [`Ctx::demote`] and remove their title record,
+    /// which is kept if demoting fails.
+    ///
+    /// # Errors
+    /// If demoting fails or unable to remove from db.
+    pub async fn demote_and_forget(&self) -> Result<()> {
+        forget_once_demoted(self.db, self.chat_id(), self.sender_id(), self.demote()).await
+    }
+
     /// Prepare for editing user privilege
     ///
     /// This will check for proper privileges according to status of the
-    /// conversation.
+    /// conversation. Returns whether the sender was promoted for the edit, so
+    /// the caller can demote them again when the edit is undone.
     ///
     /// # Errors
     ///
     /// If the bot or the user is not privileged enough or suitable to promote
     /// or be promoted.
-    pub async fn prep_edit(&self) -> Result<()> {
+    pub async fn prep_edit(&self) -> Result<bool> {
         use ChatMemberKind::*;
 
         match &self.sender_in_chat().kind {
-            Administrator(_) => self.assert_editable()?,
+            Administrator(_) => self.assert_editable().map(|_| false),
+            kind @ Owner(_) => assert_not_owner(kind).map(|_| false),
             Member => {
                 self.assert_bot_promotable()?;
                 self.promote().await.map_err(|error| {
                     send_debug(&error);
                     eyre!("Failed to promote")
                 })?;
-                self.reply_to("Promoted, wait...").await?;
+                self.reply_text("Promoted, wait...").await?;
                 // Wait a while for the promotion to take effect.
                 sleep(Duration::from_secs_f32(1.5)).await;
+                Ok(true)
             }
             kind => bail!(
                 "Unable to edit you/them because of your(their) status({})",
                 chat_member_kind_to_str(kind)
             ),
         }
+    }
+
+    /// Reconcile the stored title of the sender with the live one on
+    /// Telegram, returning a description of the action taken.
+    ///
+    /// # Errors
+    /// If the title cannot be re-applied or saved, or requesting error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn resync(&self) -> Result<String> {
+        let stored = self.get_record_with_id()?.map(|record| record.title);
+        let live = live_custom_title(&self.sender_in_chat().kind);
+        match ResyncAction::decide(stored.as_deref(), live) {
+            ResyncAction::InSync => Ok("Title is in sync, nothing to do.".to_owned()),
+            ResyncAction::Reapply(title) => {
+                self.prep_edit().await?;
+                self.bot
+                    .set_chat_administrator_custom_title(self.chat_id(), self.sender_id(), &title)
+                    .await
+                    .map_err(|error| {
+                        send_debug(&error);
+                        eyre!("Failed to set title")
+                    })?;
+                Ok(format!(
+                    "Re-applied title <code>{}</code>",
+                    html::escape(&title)
+                ))
+            }
+            ResyncAction::UpdateRecord(title) => {
+                if let Some(existing) = self.get_record_with_sig(&title)? {
+                    ensure!(
+                        existing.user_id == self.sender_id(),
+                        "Live title is already used by someone else"
+                    );
+                }
+                self.remove_title_with_id()?;
+                self.save_title(&title)?;
+                Ok(format!(
+                    "Updated record to <code>{}</code>",
+                    html::escape(&title)
+                ))
+            }
+        }
+    }
+
+    /// Remove the title from db. The owner can remove any title, others can
+    /// only remove their own title if it's not protected, in which case it's
+    /// also cleared on Telegram side when possible. With `soft_delete` the
+    /// record is moved to trash instead.
+    ///
+    /// # Errors
+    /// If the removal is not allowed, or db or requesting error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn remove_title(&self, title: &str) -> Result<()> {
+        let record = match self.get_record_with_sig(title)? {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+        check_removal(
+            self.db,
+            &record,
+            self.sender_id(),
+            self.sender_in_chat().is_owner(),
+        )?;
+        let sender = self.sender_in_chat();
+        if record.user_id == self.sender_id() && sender.is_administrator() && sender.can_be_edited()
+        {
+            self.clear_title().await?;
+        }
+        if Config::get().soft_delete {
+            trash_record(self.db, &record, unix_now())
+        } else {
+            record.remove_from(self.db)
+        }
+    }
+
+    /// Restore a title removed within the trash retention period, and set it
+    /// on Telegram side again.
+    ///
+    /// # Errors
+    /// If soft delete is disabled, the title cannot be restored, or requesting
+    /// error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn restore_title(&self, title: &str) -> Result<()> {
+        let conf = Config::get();
+        ensure!(conf.soft_delete, "Soft delete is not enabled");
+        let record = restore_record(
+            self.db,
+            self.chat_id(),
+            title,
+            unix_now(),
+            conf.trash_retention,
+        )?;
+        self.bot
+            .set_chat_administrator_custom_title(self.chat_id(), record.user_id, &record.title)
+            .await
+            .map_err(|error| {
+                send_debug(&error);
+                eyre!("Restored the record, but failed to set title")
+            })?;
         Ok(())
     }
 
+    /// Export all data stored about the sender in current chat as JSON.
+    ///
+    /// # Errors
+    /// When db returns an error or the data cannot be serialized
+    pub fn export_user_data(&self) -> Result<String> {
+        let data = UserData::collect(self.db, self.chat_id(), self.sender_id())?;
+        serde_json::to_string_pretty(&data).wrap_err("Failed to serialize user data")
+    }
+
+    /// Remove the live custom title of the sender (when the bot is able to)
+    /// and all records of the sender in current chat.
+    ///
+    /// # Errors
+    /// When the title cannot be cleared or unable to remove from db
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn forget(&self) -> Result<()> {
+        let sender = self.sender_in_chat();
+        if sender.is_administrator() && sender.can_be_edited() {
+            self.clear_title().await?;
+        }
+        UserData::remove(self.db, self.chat_id(), self.sender_id())
+    }
+
+    /// Verify that the bot is able to set the sender's title, by setting a
+    /// throwaway title and reverting it to the live one right away, which may
+    /// differ from the record when set outside the bot. A member promoted for
+    /// the test is demoted afterwards. Nothing is saved to db.
+    ///
+    /// # Errors
+    /// If any check fails or the title cannot be set, with the exact reason.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn test_title(&self) -> Result<()> {
+        let promoted = self.prep_edit().await?;
+        let set_title = |title: String| async move {
+            self.bot
+                .set_chat_administrator_custom_title(self.chat_id(), self.sender_id(), title)
+                .await
+                .map_err(|error| eyre!("Failed to set title: {error}"))?;
+            Ok(())
+        };
+        let test = async {
+            let member = self
+                .bot
+                .get_chat_member(self.chat_id(), self.sender_id())
+                .await?;
+            let previous = live_custom_title(&member.kind).unwrap_or_default();
+            set_test_title(previous, set_title).await
+        };
+        restoring_rights(promoted, test, self.demote()).await
+    }
+
+    /// Make the sender anonymous, who must have registered a title first.
+    ///
+    /// # Errors
+    /// If the sender is already anonymous or not registered, or the bot is not
+    /// privileged enough.
+    pub async fn make_anonymous(&self) -> Result<()> {
+        self.assert_bot_anonymous()?;
+        if self.is_anonymous() {
+            bail!("You are already anonymous")
+        }
+        if self.get_record_with_id()?.is_none() {
+            bail!("Before making anonymous, use /title first to register")
+        }
+        self.prep_edit().await?;
+        self.set_anonymous().await
+    }
+
+    /// Set the title of the sender and make them anonymous in one step. The
+    /// previous title is restored if making anonymous fails, and a member
+    /// promoted for it is demoted again if any step fails.
+    ///
+    /// # Errors
+    /// If the sender is already anonymous, the title is invalid, the bot is not
+    /// privileged enough, or requesting error.
+    pub async fn set_title_anonymous(&self, title: String) -> Result<()> {
+        self.assert_bot_anonymous()?;
+        if self.is_anonymous() {
+            bail!("You are already anonymous")
+        }
+        let previous = self.get_record_with_id()?;
+        let promoted = self.prep_edit().await?;
+        let edit = apply_or_rollback(self.set_title(title), self.set_anonymous(), async {
+            match previous {
+                Some(previous) => self.set_title(previous.title).await,
+                None => {
+                    self.remove_title_with_id()?;
+                    self.clear_title().await
+                }
+            }
+        });
+        demoting_on_error(promoted, edit, self.demote()).await
+    }
+
+    /// Rename the title `old` of the sender to `new`. The keys of the old
+    /// title are swapped for the new ones in a single transaction by
+    /// [`TitleRecord::claim`], so nothing is left dangling if interrupted.
+    ///
+    /// # Errors
+    /// If the sender does not hold `old`, `new` is invalid or in use, or
+    /// requesting error.
+    pub async fn rename_title(&self, old: &str, new: String) -> Result<()> {
+        rename_held_title(
+            self.db,
+            self.chat_id(),
+            self.sender_id(),
+            old,
+            new,
+            |new| async move {
+                self.prep_edit().await?;
+                self.set_title(new).await
+            },
+        )
+        .await
+    }
+
     /// De-anonymous user
     ///
     /// # Errors
     /// When user not found or error during interaction with tg api
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
     pub async fn de_anonymous(&self) -> Result<()> {
         self.assert_sender_anonymous()?;
 
-        self.bot
-            .promote_chat_member(self.chat_id(), self.sender_in_chat().user.id)
-            .can_invite_users(true)
+        let req = self
+            .bot
+            .promote_chat_member(self.chat_id(), self.sender_in_chat().user.id);
+        grant_all(req, &self.privileges_to_grant()?)
             .send()
             .await
             .map_err(|error| {
@@ -602,6 +1617,55 @@ impl<'a, 'u> Ctx<'a, Loaded> {
         Ok(())
     }
 
+    /// Make all anonymous admins of the chat that the bot can edit
+    /// un-anonymous at once, keeping the privileges each of them holds, returns
+    /// a report.
+    ///
+    /// # Errors
+    /// If the admins cannot be fetched or the database returns an error.
+    #[instrument(skip_all, fields(chat_id = %self.chat_id(), user_id = %self.sender_id()))]
+    pub async fn de_anonymous_all(&self) -> Result<String> {
+        let chat_id = self.chat_id();
+        let targets = anonymous_admins(&self.chat_admins().await?);
+        // Promoting again replaces all rights, so each admin is granted what
+        // they already hold, with only anonymity cleared
+        let results = join_all(targets.iter().map(|(user_id, privileges)| {
+            grant_all(self.bot.promote_chat_member(chat_id, *user_id), privileges).send()
+        }))
+        .await;
+        let mut done = 0;
+        for (&(user_id, _), res) in targets.iter().zip(results) {
+            match res {
+                Ok(_) => {
+                    cancel_revert(self.db, chat_id, user_id)?;
+                    done += 1;
+                }
+                Err(error) => send_debug(&error),
+            }
+        }
+
+        let mut report = format!(
+            "Found {} anonymous admins, de-anonymized {done}",
+            targets.len()
+        );
+        if done < targets.len() {
+            report.push_str(&format!(", failed {}", targets.len() - done));
+        }
+        Ok(report)
+    }
+
+    /// Ensure that the real identity of the sender is known, i.e. the sender
+    /// is either not anonymous or has been resolved from its signature.
+    ///
+    /// # Errors
+    /// Failed when the sender is an unresolved anonymous admin.
+    pub fn assert_identified(&self) -> Result<()> {
+        match self.conversation.unidentified {
+            Some(reason) => bail!("Unable to identify target ({reason})"),
+            None => Ok(()),
+        }
+    }
+
     /// Ensure that the bot is an admin in the chat.
     ///
     /// # Errors
@@ -623,10 +1687,7 @@ impl<'a, 'u> Ctx<'a, Loaded> {
     pub fn assert_sender_admin(&self) -> Result<()> {
         match &self.sender_in_chat().kind {
             ChatMemberKind::Owner(_) | ChatMemberKind::Administrator(_) => Ok(()),
-            kind => bail!(
-                "You/they are not admin, please contact admin (Currently {})",
-                chat_member_kind_to_str(kind)
-            ),
+            kind => Err(permission_denied("admins", kind)),
         }
     }
 
@@ -637,13 +1698,28 @@ impl<'a, 'u> Ctx<'a, Loaded> {
     pub fn assert_sender_owner(&self) -> Result<()> {
         match &self.sender_in_chat().kind {
             ChatMemberKind::Owner(_) => Ok(()),
-            kind => bail!(
-                "This function is owner only, (you/they are {})",
-                chat_member_kind_to_str(kind)
-            ),
+            kind => Err(permission_denied("the owner", kind)),
         }
     }
 
+    /// Ensure that the sender is not the creator of the chat, who can never be
+    /// demoted.
+    ///
+    /// # Errors
+    /// Failed when the sender is the owner.
+    pub fn assert_demotable(&self) -> Result<()> {
+        assert_demotable(&self.sender_in_chat().kind)
+    }
+
+    /// Ensure that the sender demoting themselves is not the owner, before
+    /// any request is made.
+    ///
+    /// # Errors
+    /// Failed when the sender is the owner.
+    pub fn assert_self_demotable(&self) -> Result<()> {
+        assert_self_demotable(&self.sender_in_chat().kind)
+    }
+
     /// Ensure that the bot is privileged enough to edit the user.
     ///
     /// This means one of these situations:
@@ -715,14 +1791,712 @@ impl<'a, 'u> Ctx<'a, Loaded> {
         ensure!(self.is_anonymous, "Target not anonymous");
         Ok(())
     }
-}
 
-#[must_use]
-pub const fn chat_member_kind_to_str(kind: &ChatMemberKind) -> &'static str {
-    use ChatMemberKind::*;
+    /// Run all self-checks of the bot in the chat, collecting every outcome
+    /// instead of stopping at the first failure.
+    #[must_use]
+    pub fn diagnose(&self) -> Vec<Check> {
+        let conf = Config::get();
+        let me = &self.me_in_chat().kind;
 
-    match kind {
-        Administrator(..) => "admin",
+        let mut checks = vec![Check::new(
+            "Bot status",
+            self.assert_bot_admin()
+                .map(|()| chat_member_kind_to_str(me).to_owned()),
+        )];
+        // Settings that fail to load are reported by the database check below
+        let promote_privileges = self
+            .promote_privileges()
+            .unwrap_or_else(|_| conf.promote_privileges.clone());
+        let mut privileges = required_privileges(&promote_privileges);
+        for privilege in &conf.min_bot_privileges {
+            if !privileges.contains(privilege) {
+                privileges.push(*privilege);
+            }
+        }
+        checks.extend(privileges.into_iter().map(|privilege| {
+            Check::new(
+                format!("Privilege <code>{}</code>", privilege.as_str()),
+                assert_privileges(&[privilege], me).map(|()| "held".to_owned()),
+            )
+        }));
+        checks.push(Check::new(
+            "Database",
+            ChatSettings::load(self.db, self.chat_id()).map(|_| "readable".to_owned()),
+        ));
+        checks.push(Check::new(
+            "Records",
+            self.list_titles()
+                .map(|records| format!("{} titles", records.len())),
+        ));
+        checks
+    }
+}
+
+/// Ensure that member with the kind can be demoted, i.e. not the owner.
+///
+/// # Errors
+/// Failed when the kind is [`ChatMemberKind::Owner`].
+pub fn assert_demotable(kind: &ChatMemberKind) -> Result<()> {
+    ensure!(!kind.is_owner(), "Cannot demote the group creator");
+    Ok(())
+}
+
+/// Ensure that member with the kind, demoting themselves, is not the owner.
+///
+/// # Errors
+/// Failed when the kind is [`ChatMemberKind::Owner`].
+pub fn assert_self_demotable(kind: &ChatMemberKind) -> Result<()> {
+    ensure!(!kind.is_owner(), "You're the owner; I can't demote you");
+    Ok(())
+}
+
+/// Ensure that member with the kind is not the owner, whose title can only be
+/// set by themselves in Telegram since bots can never edit the owner.
+///
+/// # Errors
+/// Failed with guidance when the kind is [`ChatMemberKind::Owner`].
+pub fn assert_not_owner(kind: &ChatMemberKind) -> Result<()> {
+    ensure!(
+        !kind.is_owner(),
+        "Bots cannot edit the group owner. To set your title, open the group info in Telegram, go \
+         to Administrators, select yourself and edit the custom title there."
+    );
+    Ok(())
+}
+
+/// Error as HTML for a report line. Errors of the bot are HTML already, with
+/// user input escaped where they are built, while those of Telegram requests
+/// are plain text and escaped here.
+fn error_html(error: &color_eyre::Report) -> String {
+    match error.downcast_ref::<RequestError>() {
+        Some(error) => html::escape(&error.to_string()),
+        None => error.to_string(),
+    }
+}
+
+/// Ensure the bot, being `me` in the chat, can set the title of `member` as
+/// is. These are the checks [`Ctx::prep_edit`] does on admins, for callers
+/// without a message to promote the member from, like the HTTP API.
+///
+/// # Errors
+/// If the bot cannot promote members, or `member` is not an admin the bot can
+/// edit.
+pub fn assert_title_editable(me: &ChatMemberKind, member: &ChatMemberKind) -> Result<()> {
+    use ChatMemberKind::*;
+
+    ensure!(
+        me.can_promote_members(),
+        "I'm not an admin, please promote me with promotion privilege first"
+    );
+    match member {
+        Administrator(Admin { can_be_edited, .. }) => {
+            ensure!(
+                *can_be_edited,
+                "Unable to change info (maybe promoted by others?)"
+            );
+            Ok(())
+        }
+        kind @ Owner(_) => assert_not_owner(kind),
+        kind => bail!(
+            "Unable to edit because of status ({})",
+            chat_member_kind_to_str(kind)
+        ),
+    }
+}
+
+/// Validate and set the title of the user in the chat. This does not need a
+/// message, so it's shared by [`Ctx::set_title`] and the HTTP API.
+///
+/// # Errors
+/// If the title is invalid or in use, or requesting error.
+pub async fn apply_title(
+    bot: &BotType,
+    db: &Db,
+    chat_id: ChatId,
+    user_id: UserId,
+    title: String,
+) -> Result<()> {
+    let settings = ChatSettings::load(db, chat_id)?;
+    // Expanded before any check, so the rank counts towards the length limit
+    let (title, rank) = expand_rank(db, chat_id, &title)?;
+    ensure!(
+        !Config::get().is_blacklisted(&title),
+        "That title is not allowed here"
+    );
+    let title = settings.decorate_title(&title);
+    validate_title(&title)?;
+    settings.check_title(&title)?;
+    settings.check_words(&title)?;
+    if Config::get().reject_chat_title {
+        assert_not_chat_title(&title, chat_title(bot, chat_id).await?.as_deref())?;
+    }
+    Reservation::check(db, chat_id, &title, user_id)?;
+
+    // Claim the title before the request, so concurrent claims of the same
+    // title cannot both succeed
+    let previous = TitleRecord::get_with_id(db, chat_id, user_id)?;
+    if previous.is_none() {
+        settings.check_quota(TitleRecord::list_in_chat(db, chat_id)?.len())?;
+    }
+    let conf = Config::get();
+    if previous.is_none()
+        && let Some(max) = conf.max_db_entries
+    {
+        check_capacity(db, max, conf.db_overflow)?;
+    }
+    let record = TitleRecord {
+        title,
+        chat_id,
+        user_id,
+        expires_at: expiry_from(unix_now()),
+    };
+    if let Err(error) = record.claim(db) {
+        // Name the holder only when configured, as it reveals who holds which
+        // title to anyone probing with /title
+        if conf.name_title_holder
+            && let Some(holder) = TitleRecord::get_with_title(db, chat_id, &record.title)?
+            && holder.user_id != user_id
+            && let Ok(member) = bot.get_chat_member(chat_id, holder.user_id).await
+        {
+            bail!(title_in_use(Some(&member.user.full_name())));
+        }
+        return Err(error);
+    }
+    if let Err(error) = bot
+        .set_chat_administrator_custom_title(chat_id, user_id, &record.title)
+        .await
+    {
+        send_debug(&error);
+        record.remove_from(db)?;
+        if let Some(previous) = previous {
+            previous.insert_into(db)?;
+        }
+        // Keep the request error, so transient ones can be told apart
+        return Err(error).wrap_err("Failed to set title");
+    }
+    if previous.is_none() {
+        mark_created(db, chat_id, user_id, unix_now())?;
+    }
+    if let Some(rank) = rank {
+        commit_rank(db, chat_id, rank)?;
+    }
+    record_holder(db, &record, unix_now())?;
+    // The title is claimed by the user it was reserved for
+    if let Some(reservation) = Reservation::get(db, chat_id, &record.title)? {
+        reservation.remove_from(db)?;
+    }
+    if previous.is_none()
+        && conf.db_overflow == CapacityPolicy::Evict
+        && let Some(max) = conf.max_db_entries
+    {
+        evict_oldest(bot, db, max, &record).await;
+    }
+    Ok(())
+}
+
+/// Evict the oldest records once `claimed` is stored beyond `max`, clearing
+/// their live titles too. Failures are only reported to the debug chat, as the
+/// claimed title is set already.
+async fn evict_oldest(bot: &BotType, db: &Db, max: usize, claimed: &TitleRecord) {
+    let evicted = match evict_overflow(db, max, claimed) {
+        Ok(evicted) => evicted,
+        Err(error) => return send_debug(&error),
+    };
+    if evicted.is_empty() {
+        return;
+    }
+    for record in &evicted {
+        if let Err(error) = bot
+            .set_chat_administrator_custom_title(record.chat_id, record.user_id, "")
+            .await
+        {
+            send_debug(&error);
+        }
+    }
+    send_debug(&format!(
+        "Database is full (max {max} titles), evicted the oldest: {}",
+        evicted
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+}
+
+/// Set [`TEST_TITLE`] with `set` then revert to `previous`, stopping at the
+/// first failure.
+///
+/// # Errors
+/// The error of `set`.
+async fn set_test_title<F, Fut>(previous: &str, set: F) -> Result<()>
+where
+    F: Fn(String) -> Fut + Send,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    set(TEST_TITLE.to_owned()).await?;
+    set(previous.to_owned()).await
+}
+
+/// Run `edit` on a member, then `demote` them if they were `promoted` just
+/// for it by [`Ctx::prep_edit`], restoring their previous rights whether or
+/// not `edit` succeeds.
+///
+/// # Errors
+/// The error of `edit`, or of `demote` if it fails.
+pub async fn restoring_rights(
+    promoted: bool,
+    edit: impl Future<Output = Result<()>> + Send,
+    demote: impl Future<Output = Result<()>> + Send,
+) -> Result<()> {
+    let result = edit.await;
+    if promoted {
+        demote.await?;
+    }
+    result
+}
+
+/// Run `edit` on a member, then `demote` them if they were `promoted` for it
+/// by [`Ctx::prep_edit`] but `edit` fails, so no rights are left behind.
+///
+/// # Errors
+/// The error of `edit`, or of `demote` if it fails too.
+pub async fn demoting_on_error(
+    promoted: bool,
+    edit: impl Future<Output = Result<()>> + Send,
+    demote: impl Future<Output = Result<()>> + Send,
+) -> Result<()> {
+    if let Err(error) = edit.await {
+        if promoted {
+            demote.await?;
+        }
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Run `demote` on the user, then remove their title record in the chat. The
+/// record is kept if demoting fails, as the title is still live.
+///
+/// # Errors
+/// If `demote` fails or unable to remove from db.
+async fn forget_once_demoted(
+    db: &Db,
+    chat_id: ChatId,
+    user_id: UserId,
+    demote: impl Future<Output = Result<()>> + Send,
+) -> Result<()> {
+    demote.await?;
+    match TitleRecord::get_with_id(db, chat_id, user_id)? {
+        Some(record) => record.remove_from(db),
+        None => Ok(()),
+    }
+}
+
+/// Rename the title `old` of the user to `new` with `set`, once the user is
+/// found holding `old`. See [`Ctx::rename_title`].
+///
+/// # Errors
+/// If either title is missing, the user does not hold `old`, or `set` fails.
+async fn rename_held_title<F, Fut>(
+    db: &Db,
+    chat_id: ChatId,
+    user_id: UserId,
+    old: &str,
+    new: String,
+    set: F,
+) -> Result<()>
+where
+    F: FnOnce(String) -> Fut + Send,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    ensure!(
+        !old.is_empty() && !new.is_empty(),
+        "format: /retitle &lt;old&gt; | &lt;new&gt;"
+    );
+    let holds_old = TitleRecord::get_with_id(db, chat_id, user_id)?.map_or(false, |record| {
+        normalize_title(&record.title) == normalize_title(old)
+    });
+    ensure!(
+        holds_old,
+        "You don't hold the title <code>{}</code>",
+        html::escape(old)
+    );
+    set(new).await
+}
+
+/// Run `apply` then `then`, running `rollback` to undo `apply` if `then`
+/// fails. Nothing runs after `apply` fails.
+///
+/// # Errors
+/// The error of `apply` or `then`, or of `rollback` if it fails too.
+pub async fn apply_or_rollback(
+    apply: impl Future<Output = Result<()>> + Send,
+    then: impl Future<Output = Result<()>> + Send,
+    rollback: impl Future<Output = Result<()>> + Send,
+) -> Result<()> {
+    apply.await?;
+    if let Err(error) = then.await {
+        rollback.await?;
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Audit message of a `/nuke`, sent to debug.
+fn nuke_audit(chat_id: ChatId, user_id: UserId, demoted: usize, reason: &str) -> String {
+    let reason = match reason.trim() {
+        "" => "no reason given".to_owned(),
+        reason => format!("reason: {}", html::escape(reason)),
+    };
+    format!("Chat({chat_id}) nuked by User({user_id}), demoted {demoted}, {reason}")
+}
+
+/// Relay feedback `text` of the user in the chat with `relay`, at most once
+/// per period of `cooldown` for each user.
+///
+/// # Errors
+/// If the feedback is empty or the user is cooling down.
+fn relay_feedback(
+    cooldown: &Cooldown<UserId>,
+    chat_id: ChatId,
+    user: &User,
+    text: &str,
+    relay: impl FnOnce(String),
+) -> Result<()> {
+    let text = text.trim();
+    ensure!(!text.is_empty(), "format: /feedback &lt;text&gt;");
+    ensure!(
+        cooldown.try_start(user.id),
+        "You sent feedback recently, please try again later"
+    );
+    relay(format!(
+        "Feedback from {} (User({})) in Chat({chat_id}):\n{}",
+        html::escape(&user.full_name()),
+        user.id,
+        html::escape(text)
+    ));
+    Ok(())
+}
+
+/// Title of the chat, cached for [`CHAT_TITLE_TTL`].
+///
+/// # Errors
+/// If the chat cannot be fetched.
+async fn chat_title(bot: &BotType, chat_id: ChatId) -> Result<Option<String>> {
+    static CHAT_TITLES: LazyLock<TtlCache<ChatId, Option<String>>> =
+        LazyLock::new(|| TtlCache::new(CHAT_TITLE_TTL));
+
+    if let Some(title) = CHAT_TITLES.get(&chat_id) {
+        return Ok(title);
+    }
+    let title = bot.get_chat(chat_id).await?.title().map(ToOwned::to_owned);
+    CHAT_TITLES.insert(chat_id, title.clone());
+    Ok(title)
+}
+
+/// Length of the title as counted by Telegram, in UTF-16 code units, so
+/// most emojis count as two characters.
+#[must_use]
+pub fn title_len(title: &str) -> usize {
+    title.encode_utf16().count()
+}
+
+/// Ensure the title fits in the limit of Telegram, counted with [`title_len`]
+/// rather than in bytes, so Telegram does not reject it with an opaque error.
+///
+/// # Errors
+/// If the title is too long, telling how long it is.
+pub fn validate_title(title: &str) -> Result<()> {
+    let len = title_len(title);
+    ensure!(
+        len <= MAX_TITLE_LEN,
+        "Title too long (max {MAX_TITLE_LEN} characters, you used {len})"
+    );
+    Ok(())
+}
+
+/// Normalize the title for comparison, ignoring case and extra whitespace.
+#[must_use]
+pub fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Ensure the title does not impersonate the chat itself.
+///
+/// # Errors
+/// If the title is the same as the chat title after normalization.
+fn assert_not_chat_title(title: &str, chat_title: Option<&str>) -> Result<()> {
+    ensure!(
+        chat_title.map(normalize_title) != Some(normalize_title(title)),
+        "Title cannot be the name of the group"
+    );
+    Ok(())
+}
+
+/// Describe whether the title can be claimed by the user in the chat, with the
+/// prefix and suffix of the chat applied. Nothing is written.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn check_availability(
+    db: &Db,
+    chat_id: ChatId,
+    user_id: UserId,
+    title: &str,
+) -> Result<&'static str> {
+    let title = ChatSettings::load(db, chat_id)?.decorate_title(title);
+    if let Some(holder) = TitleRecord::get_with_title(db, chat_id, &title)? {
+        return Ok(if holder.user_id == user_id {
+            "Already yours"
+        } else {
+            "Taken by someone"
+        });
+    }
+    Ok(match Reservation::get(db, chat_id, &title)? {
+        Some(reservation) if reservation.user_id != user_id => "Reserved for someone else",
+        _ => "Available",
+    })
+}
+
+/// Leading emojis of the title, including the whitespace after them, e.g.
+/// `"🌟 "` of `"🌟 Alice"`.
+#[must_use]
+pub fn emoji_prefix(title: &str) -> &str {
+    let is_emoji = |c: char| {
+        matches!(
+            u32::from(c),
+            0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x200D | 0xFE0F
+        )
+    };
+    let end = title
+        .char_indices()
+        .find(|&(_, c)| !is_emoji(c))
+        .map_or(title.len(), |(i, _)| i);
+    if end == 0 {
+        return "";
+    }
+    let spaces = title[end..].len() - title[end..].trim_start().len();
+    &title[..end + spaces]
+}
+
+/// The new title with the emoji prefix of the previous title kept, if the
+/// previous title has one and the new title dropped it. `chat_prefix` set by
+/// the chat is not considered part of either title.
+#[must_use]
+pub fn preserve_emoji_prefix(previous: &str, title: &str, chat_prefix: &str) -> Option<String> {
+    let previous = previous.strip_prefix(chat_prefix).unwrap_or(previous);
+    let title = title.strip_prefix(chat_prefix).unwrap_or(title);
+    let prefix = emoji_prefix(previous);
+    if prefix.is_empty() || !emoji_prefix(title).is_empty() {
+        return None;
+    }
+    Some(format!("{prefix}{title}"))
+}
+
+/// Titles for `count` users sharing `base`: `base` itself, then `base 2`,
+/// `base 3` and so on, skipping those taken or reserved in the chat with the
+/// prefix and suffix of the chat applied. Nothing is written.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn numbered_titles(db: &Db, chat_id: ChatId, base: &str, count: usize) -> Result<Vec<String>> {
+    let settings = ChatSettings::load(db, chat_id)?;
+    let mut titles = Vec::with_capacity(count);
+    let mut n = 1;
+    while titles.len() < count {
+        let title = if n == 1 {
+            base.to_owned()
+        } else {
+            format!("{base} {n}")
+        };
+        let decorated = settings.decorate_title(&title);
+        if TitleRecord::get_with_title(db, chat_id, &decorated)?.is_none()
+            && Reservation::get(db, chat_id, &decorated)?.is_none()
+        {
+            titles.push(title);
+        }
+        n += 1;
+    }
+    Ok(titles)
+}
+
+/// Normalize a username given to commands, with or without the leading `@`:
+/// surrounding whitespace and `@` are stripped and it's lowercased, as
+/// usernames are case-insensitive. `None` if it's not a username, e.g. a
+/// user id.
+#[must_use]
+pub fn parse_username(input: &str) -> Option<String> {
+    let username = input.trim();
+    let username = username.strip_prefix('@').unwrap_or(username);
+    let valid = username.starts_with(|c: char| c.is_ascii_alphabetic())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then(|| username.to_ascii_lowercase())
+}
+
+/// Whether the user has the username, normalized by [`parse_username`].
+#[must_use]
+pub fn has_username(user: &User, username: &str) -> bool {
+    user.username
+        .as_deref()
+        .map_or(false, |name| name.eq_ignore_ascii_case(username))
+}
+
+/// `@username`s and user ids listed in `text`, separated by whitespace or
+/// commas, in order and without duplicates. Other words are ignored.
+#[must_use]
+pub fn parse_targets(text: &str) -> Vec<&str> {
+    let mut targets = Vec::new();
+    for word in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let is_target = if word.starts_with('@') {
+            parse_username(word).is_some()
+        } else {
+            word.parse::<u64>().is_ok()
+        };
+        if is_target && !targets.contains(&word) {
+            targets.push(word);
+        }
+    }
+    targets
+}
+
+/// Message telling the title is held by someone else, naming the holder if
+/// given.
+#[must_use]
+pub fn title_in_use(holder: Option<&str>) -> String {
+    match holder {
+        Some(name) => format!("Title already in use by <b>{}</b>", html::escape(name)),
+        None => "Title already in use".to_owned(),
+    }
+}
+
+/// Preview line of the name with the title, laid out like the chat list of
+/// Telegram desktop. Best-effort, other clients may render it differently.
+#[must_use]
+pub fn title_preview(name: &str, title: &str) -> String {
+    format!(
+        "Preview: <b>{}</b>  <i>{}</i>",
+        html::escape(name),
+        html::escape(title)
+    )
+}
+
+/// Fetch the admins with `fetch`. The list lags behind right after a
+/// promotion, so while `expected` is missing from it, fetch again up to
+/// `retries` times, `delay` apart.
+///
+/// # Errors
+/// If any fetch fails.
+pub async fn fetch_admins_expecting<F, Fut>(
+    mut fetch: F,
+    expected: Option<UserId>,
+    retries: usize,
+    delay: Duration,
+) -> Result<Vec<ChatMember>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Vec<ChatMember>>>,
+{
+    let mut admins = fetch().await?;
+    let Some(expected) = expected else {
+        return Ok(admins);
+    };
+    for _ in 0..retries {
+        if admins.iter().any(|admin| admin.user.id == expected) {
+            break;
+        }
+        sleep(delay).await;
+        admins = fetch().await?;
+    }
+    Ok(admins)
+}
+
+/// Fetch the members of `user_ids`. All admins are fetched with a single
+/// `fetch_admins` and joined in memory, so `fetch_member` is only called for
+/// users that are not admins.
+///
+/// # Errors
+/// If any fetch fails.
+pub async fn fetch_members<A, AFut, M, MFut>(
+    user_ids: impl IntoIterator<Item = UserId>,
+    fetch_admins: A,
+    mut fetch_member: M,
+) -> Result<HashMap<UserId, ChatMember>>
+where
+    A: FnOnce() -> AFut,
+    AFut: Future<Output = Result<Vec<ChatMember>>>,
+    M: FnMut(UserId) -> MFut,
+    MFut: Future<Output = Result<ChatMember>>,
+{
+    let admins = fetch_admins()
+        .await?
+        .into_iter()
+        .map(|admin| (admin.user.id, admin))
+        .collect::<HashMap<_, _>>();
+    let mut members = HashMap::new();
+    for user_id in user_ids {
+        if members.contains_key(&user_id) {
+            continue;
+        }
+        let member = match admins.get(&user_id) {
+            Some(admin) => admin.clone(),
+            None => fetch_member(user_id).await?,
+        };
+        members.insert(user_id, member);
+    }
+    Ok(members)
+}
+
+/// Build the error shown when the sender is not one of `allowed`. The sender
+/// sees `denied_message` or the default message, see [`PermissionDenied`].
+fn permission_denied(allowed: &'static str, kind: &ChatMemberKind) -> color_eyre::Report {
+    let conf = Config::get();
+    let mut message = denied_message(conf.denied_message.as_deref(), allowed);
+    if conf.explain_denial {
+        message.push_str("\n\n");
+        message.push_str(&explain_denial(allowed, kind));
+    }
+    PermissionDenied {
+        allowed,
+        status: chat_member_kind_to_str(kind),
+        message,
+    }
+    .into()
+}
+
+/// Break down a denial: the required status, the status of the sender and how
+/// to gain access.
+fn explain_denial(allowed: &str, kind: &ChatMemberKind) -> String {
+    let how = match kind {
+        _ if allowed == "the owner" => "Only the owner can do this, please ask them instead.",
+        ChatMemberKind::Member => "Set a title with /title to be promoted to admin first.",
+        _ => "Ask an admin to promote you.",
+    };
+    format!(
+        "Required: {allowed}\nYou are: {}\n{how}",
+        chat_member_kind_to_str(kind)
+    )
+}
+
+fn denied_message(custom: Option<&str>, allowed: &str) -> String {
+    custom.map_or_else(
+        || format!("Only {allowed} can do this, please contact the chat owner."),
+        ToOwned::to_owned,
+    )
+}
+
+#[must_use]
+pub const fn chat_member_kind_to_str(kind: &ChatMemberKind) -> &'static str {
+    use ChatMemberKind::*;
+
+    match kind {
+        Administrator(..) => "admin",
         Member => "member",
         Owner(_) => "owner",
         Restricted(_) => "restricted",
@@ -731,36 +2505,174 @@ pub const fn chat_member_kind_to_str(kind: &ChatMemberKind) -> &'static str {
     }
 }
 
+/// Anonymous admins among `admins` that the bot can edit, with the privileges
+/// they hold. The owner and admins promoted by others are left as is.
 #[must_use]
-#[derive(Debug, Clone, PartialEq, Eq)]
+pub fn anonymous_admins(admins: &[ChatMember]) -> Vec<(UserId, Vec<Privilege>)> {
+    admins
+        .iter()
+        .filter(|member| {
+            member.is_administrator() && member.kind.is_anonymous() && member.kind.can_be_edited()
+        })
+        .map(|member| (member.user.id, held_privileges(&member.kind)))
+        .collect()
+}
+
+/// Kind, title and privileges of the member, as returned by Telegram.
+#[must_use]
+pub fn render_member(member: &ChatMember) -> String {
+    let kind = &member.kind;
+    let title = kind.custom_title().map_or_else(
+        || "none".to_owned(),
+        |title| format!("<code>{}</code>", html::escape(title)),
+    );
+    let held = held_privileges(kind);
+    let privileges = if held.is_empty() {
+        "none".to_owned()
+    } else {
+        format_privileges(&held)
+    };
+    format!(
+        "<b>{}</b> (<code>{}</code>)\nKind: {}\nCustom title: {title}\nAnonymous: {}\nCan be \
+         edited by me: {}\nPrivileges: {privileges}",
+        html::escape(&member.user.full_name()),
+        member.user.id,
+        chat_member_kind_to_str(kind),
+        kind.is_anonymous(),
+        kind.can_be_edited(),
+    )
+}
+
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TitleRecord {
     pub title: String,
     pub chat_id: ChatId,
     pub user_id: UserId,
+    /// Unix timestamp in seconds after which the title is reaped, see
+    /// `title_ttl`. Stored after the title in the value of the chat key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
 impl TitleRecord {
-    fn list_in_chat(db: &Db, chat: ChatId) -> Result<Vec<Self>> {
-        let prefix = format!("chat${}", chat);
-        db.scan_prefix(&prefix)
+    pub(crate) fn list_in_chat(db: &Db, chat: ChatId) -> Result<Vec<Self>> {
+        Ok(Self::list_in_chat_capped(db, chat, usize::MAX)?.0)
+    }
+
+    /// List at most `cap` records in the chat, stopping the scan there.
+    /// Returns whether there were more.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub(crate) fn list_in_chat_capped(
+        db: &Db,
+        chat: ChatId,
+        cap: usize,
+    ) -> Result<(Vec<Self>, bool)> {
+        let prefix = format!("chat${}$", chat);
+        let mut records = db
+            .scan_prefix(&prefix)
+            .take(cap.saturating_add(1))
             .map(|x| {
                 x.wrap_err("Failed to scan database")
                     .and_then(|(key, value)| Self::parse_chat_key(&key, &value))
             })
-            .try_collect()
+            .try_collect::<Vec<_>>()?;
+        let capped = records.len() > cap;
+        records.truncate(cap);
+        Ok((records, capped))
+    }
+
+    /// List ids of all chats that have at least one record.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    fn list_chats(db: &Db) -> Result<Vec<ChatId>> {
+        let mut chats = db
+            .scan_prefix("chat$")
+            .map(|x| {
+                x.wrap_err("Failed to scan database")
+                    .and_then(|(key, value)| Self::parse_chat_key(&key, &value))
+                    .map(|record| record.chat_id)
+            })
+            .try_collect::<Vec<_>>()?;
+        chats.dedup();
+        Ok(chats)
     }
 
     /// Insert given record into DB
     ///
     /// # Errors
     /// If the insertion fails.
-    fn insert_into(&self, db: &Db) -> Result<()> {
+    pub(crate) fn insert_into(&self, db: &Db) -> Result<()> {
         let chat_key: IVec = Self::make_chat_key(self.chat_id, self.user_id);
         let title_key: IVec = Self::make_title_key(self.chat_id, &self.title);
+        let norm_key: IVec = Self::make_norm_key(self.chat_id, &self.title);
 
-        db.insert(&chat_key, self.title.as_bytes())?;
+        if db.insert(&chat_key, self.chat_value())?.is_none() {
+            count_added(db, 1)?;
+        }
         db.insert(&title_key, &self.user_id.0.to_be_bytes())?;
+        db.insert(&norm_key, &self.user_id.0.to_be_bytes())?;
+
+        Ok(())
+    }
+
+    /// Atomically claim the title for the user, replacing the previous title
+    /// of the user if any. Titles differing only in case or whitespace count as
+    /// the same, see [`normalize_title`].
+    ///
+    /// # Errors
+    /// If the title is held by someone else, or the transaction fails.
+    pub(crate) fn claim(&self, db: &Db) -> Result<()> {
+        let chat_key = Self::make_chat_key(self.chat_id, self.user_id);
+        let title_key = Self::make_title_key(self.chat_id, &self.title);
+        let norm_key = Self::make_norm_key(self.chat_id, &self.title);
+        let user_id = self.user_id.0.to_be_bytes();
 
+        let added = db
+            .transaction(|tx| {
+                if let Some(holder) = tx.get(&title_key)?
+                    && *holder != user_id
+                {
+                    return abort(());
+                }
+                // The normalized key may be stale, so check the holder still has
+                // the same title
+                if let Some(holder) = tx.get(&norm_key)?
+                    && *holder != user_id
+                    && let Ok(holder) = <[u8; 8]>::try_from(&*holder)
+                    && let Some(held) = tx.get(Self::make_chat_key(
+                        self.chat_id,
+                        UserId(u64::from_be_bytes(holder)),
+                    ))?
+                    && normalize_title(&Self::title_of(&held)) == normalize_title(&self.title)
+                {
+                    return abort(());
+                }
+                let previous = tx.insert(chat_key.clone(), self.chat_value())?;
+                if let Some(previous) = &previous
+                    && Self::title_of(previous) != self.title
+                {
+                    let previous = Self::title_of(previous);
+                    tx.remove(Self::make_title_key(self.chat_id, &previous))?;
+                    let previous_norm_key = Self::make_norm_key(self.chat_id, &previous);
+                    if tx.get(&previous_norm_key)?.as_deref() == Some(&user_id[..]) {
+                        tx.remove(previous_norm_key)?;
+                    }
+                }
+                tx.insert(title_key.clone(), &user_id[..])?;
+                tx.insert(norm_key.clone(), &user_id[..])?;
+                Ok(previous.is_none())
+            })
+            .map_err(|error| match error {
+                TransactionError::Abort(()) => eyre!(title_in_use(None)),
+                TransactionError::Storage(error) => error.into(),
+            })?;
+        if added {
+            count_added(db, 1)?;
+        }
         Ok(())
     }
 
@@ -771,11 +2683,11 @@ impl TitleRecord {
     ///
     /// # Errors
     /// When get fails or bad encoding.
-    fn get_with_id(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Option<Self>> {
+    pub(crate) fn get_with_id(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Option<Self>> {
         let chat_key: IVec = Self::make_chat_key(chat_id, user_id);
 
-        let title = match db.get(chat_key)? {
-            Some(title_key) => String::from_utf8(title_key.to_vec())?,
+        let (title, expires_at) = match db.get(chat_key)? {
+            Some(value) => Self::parse_chat_value(&value)?,
             None => return Ok(None),
         };
 
@@ -783,76 +2695,479 @@ impl TitleRecord {
             title,
             chat_id,
             user_id,
+            expires_at,
         }))
     }
 
-    /// Get the record from DB with `title`
+    /// Get the record from DB with `title`. Titles differing only in case or
+    /// whitespace are found too, with the title as stored, so the returned
+    /// record can be removed.
     ///
     /// # Errors
     /// When get fails or bad encoding.
-    fn get_with_title(db: &Db, chat_id: ChatId, title: impl Into<String>) -> Result<Option<Self>> {
+    pub(crate) fn get_with_title(
+        db: &Db,
+        chat_id: ChatId,
+        title: impl Into<String>,
+    ) -> Result<Option<Self>> {
         let title = title.into();
 
         let title_key: IVec = Self::make_title_key(chat_id, &title);
-        let user_id = match db.get(title_key)? {
-            Some(chat_key) => u64::from_be_bytes((*chat_key).try_into().wrap_err("Bad value")?),
+        if let Some(user_id) = db.get(title_key)? {
+            let user_id = UserId(u64::from_be_bytes(
+                (*user_id).try_into().wrap_err("Bad value")?,
+            ));
+            let expires_at = Self::get_with_id(db, chat_id, user_id)?
+                .filter(|record| record.title == title)
+                .and_then(|record| record.expires_at);
+            return Ok(Some(Self {
+                title,
+                chat_id,
+                user_id,
+                expires_at,
+            }));
+        }
+
+        let norm_key: IVec = Self::make_norm_key(chat_id, &title);
+        let user_id = match db.get(norm_key)? {
+            Some(user_id) => u64::from_be_bytes((*user_id).try_into().wrap_err("Bad value")?),
             None => return Ok(None),
         };
-
-        Ok(Some(Self {
-            title,
-            chat_id,
-            user_id: UserId(user_id),
-        }))
+        // Skip stale normalized keys whose holder has another title by now
+        Ok(Self::get_with_id(db, chat_id, UserId(user_id))?
+            .filter(|record| normalize_title(&record.title) == normalize_title(&title)))
     }
 
-    fn remove_from(&self, db: &Db) -> Result<()> {
+    pub(crate) fn remove_from(&self, db: &Db) -> Result<()> {
         let chat_key: IVec = Self::make_chat_key(self.chat_id, self.user_id);
         let title_key: IVec = Self::make_title_key(self.chat_id, &self.title);
+        if self.holds_norm_key(db)? {
+            db.remove(Self::make_norm_key(self.chat_id, &self.title))?;
+        }
         db.remove(title_key)?;
-        db.remove(chat_key)?;
+        if db.remove(chat_key)?.is_some() {
+            count_removed(db, 1)?;
+        }
         Ok(())
     }
 
-    fn make_title_key(chat_id: ChatId, title: &str) -> IVec {
+    /// Whether the normalized key of the title points to the user of this
+    /// record, i.e. it's safe to remove along with the record.
+    ///
+    /// # Errors
+    /// When get fails.
+    pub(crate) fn holds_norm_key(&self, db: &Db) -> Result<bool> {
+        let norm_key = Self::make_norm_key(self.chat_id, &self.title);
+        Ok(db.get(norm_key)?.as_deref() == Some(&self.user_id.0.to_be_bytes()[..]))
+    }
+
+    /// Add the normalized keys of titles stored before they were introduced,
+    /// returning how many were added. Done once, marked by
+    /// `migrated$titlenorm`. Of titles differing only in case or whitespace,
+    /// the first one found keeps the key.
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn backfill_norm_keys(db: &Db) -> Result<usize> {
+        const MARKER: &str = "migrated$titlenorm";
+
+        if db.contains_key(MARKER)? {
+            return Ok(0);
+        }
+        let mut added = 0;
+        for entry in db.scan_prefix("chat$") {
+            let (key, value) = entry?;
+            // Reported by `verify_db` instead
+            let Ok(record) = Self::parse_chat_key(&key, &value) else {
+                continue;
+            };
+            let norm_key = Self::make_norm_key(record.chat_id, &record.title);
+            let user_id = record.user_id.0.to_be_bytes();
+            if db
+                .compare_and_swap(norm_key, None::<&[u8]>, Some(&user_id[..]))?
+                .is_ok()
+            {
+                added += 1;
+            }
+        }
+        db.insert(MARKER, &[])?;
+        Ok(added)
+    }
+
+    /// Remove every record of the user in given chat, returns how many
+    /// records were removed.
+    ///
+    /// # Errors
+    /// When get or remove fails.
+    fn remove_all_of_user(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<usize> {
+        let mut count = 0;
+        if let Some(record) = Self::get_with_id(db, chat_id, user_id)? {
+            record.remove_from(db)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Remove every record in given chat, returns how many records were
+    /// removed.
+    ///
+    /// # Errors
+    /// When scan or remove fails.
+    pub(crate) fn remove_all_in_chat(db: &Db, chat_id: ChatId) -> Result<usize> {
+        let user_ids = Self::list_in_chat(db, chat_id)?
+            .into_iter()
+            .map(|record| record.user_id)
+            .collect::<Vec<_>>();
+        finish_removal(db, chat_id, &user_ids)
+    }
+
+    pub(crate) fn make_title_key(chat_id: ChatId, title: &str) -> IVec {
         format!("title${}${}", chat_id, title).into_bytes().into()
     }
 
-    fn make_chat_key(chat_id: ChatId, user_id: UserId) -> IVec {
+    /// Key of the title for case-insensitive lookup, under
+    /// `titlenorm$<chat_id>$<normalized title>`. Like `title$`, it holds the
+    /// user id in big endian.
+    pub(crate) fn make_norm_key(chat_id: ChatId, title: &str) -> IVec {
+        format!("titlenorm${}${}", chat_id, normalize_title(title))
+            .into_bytes()
+            .into()
+    }
+
+    pub(crate) fn make_chat_key(chat_id: ChatId, user_id: UserId) -> IVec {
         format!("chat${}${}", chat_id, user_id).into_bytes().into()
     }
 
-    fn parse_chat_key(key: &IVec, title: &IVec) -> Result<Self> {
+    pub(crate) fn parse_title_key(key: &IVec, user_id: &IVec) -> Result<Self> {
+        Self::parse_user_key("title", key, user_id)
+    }
+
+    /// Parse a key made by [`Self::make_norm_key`], the title of the record
+    /// being the normalized one.
+    pub(crate) fn parse_norm_key(key: &IVec, user_id: &IVec) -> Result<Self> {
+        Self::parse_user_key("titlenorm", key, user_id)
+    }
+
+    /// Parse a `<prefix>$<chat_id>$<title>` key holding the user id.
+    fn parse_user_key(prefix: &str, key: &IVec, user_id: &IVec) -> Result<Self> {
         let key = String::from_utf8(key.to_vec())?;
-        let mut iter = key.split('$');
+        // Title may contain `$`, so only split the first two
+        let mut iter = key.splitn(3, '$');
 
-        ensure!(iter.next() == Some("chat"), "Bad key");
+        ensure!(iter.next() == Some(prefix), "Bad key");
 
         let chat_id = iter
             .next()
             .wrap_err("bad key")?
             .parse::<i64>()
             .map(ChatId)?;
-        let user_id = iter
+        let title = iter.next().wrap_err("bad key")?.to_owned();
+
+        let user_id = u64::from_be_bytes((**user_id).try_into().wrap_err("Bad value")?);
+
+        Ok(Self {
+            title,
+            chat_id,
+            user_id: UserId(user_id),
+            expires_at: None,
+        })
+    }
+
+    pub(crate) fn parse_chat_key(key: &IVec, value: &IVec) -> Result<Self> {
+        let key = String::from_utf8(key.to_vec())?;
+        let mut iter = key.split('$');
+
+        ensure!(iter.next() == Some("chat"), "Bad key");
+
+        let chat_id = iter
+            .next()
+            .wrap_err("bad key")?
+            .parse::<i64>()
+            .map(ChatId)?;
+        let user_id = iter
             .next()
             .wrap_err("bad key")?
             .parse::<u64>()
             .map(UserId)?;
 
-        let title = String::from_utf8(title.to_vec())?;
+        let (title, expires_at) = Self::parse_chat_value(value)?;
 
         Ok(Self {
             title,
             chat_id,
             user_id,
+            expires_at,
         })
     }
+
+    /// Value of the chat key: the title, followed by a NUL and the expiry
+    /// timestamp in decimal if the title expires. Titles stored before expiry
+    /// was added have no expiry.
+    fn chat_value(&self) -> Vec<u8> {
+        match self.expires_at {
+            Some(expires_at) => format!("{}\0{expires_at}", self.title).into_bytes(),
+            None => self.title.as_bytes().to_vec(),
+        }
+    }
+
+    /// Parse the value of the chat key, see [`Self::chat_value`].
+    ///
+    /// # Errors
+    /// When bad encoding.
+    pub(crate) fn parse_chat_value(value: &[u8]) -> Result<(String, Option<u64>)> {
+        let value = std::str::from_utf8(value)?;
+        Ok(match value.split_once('\0') {
+            Some((title, expires_at)) => (
+                title.to_owned(),
+                Some(expires_at.parse().wrap_err("Bad expiry")?),
+            ),
+            None => (value.to_owned(), None),
+        })
+    }
+
+    /// Title in the value of the chat key, without failing on bad encoding.
+    fn title_of(value: &[u8]) -> Cow<'_, str> {
+        let end = value
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(value.len());
+        String::from_utf8_lossy(&value[..end])
+    }
 }
 
 impl Display for TitleRecord {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<code>{}: User({})</code>", self.title, self.user_id)
+        write!(
+            f,
+            "<code>{}: User({})</code>",
+            html::escape(&self.title),
+            self.user_id
+        )
+    }
+}
+
+/// Title record along with the style of its user, see [`export_titles`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedTitle {
+    #[serde(flatten)]
+    pub record: TitleRecord,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<TitleStyle>,
+}
+
+/// Records with the styles of their users as pretty JSON, for owners moving
+/// titles elsewhere.
+///
+/// # Errors
+/// If the database returns an error or the records cannot be serialized.
+pub fn export_titles(db: &Db, records: &[TitleRecord]) -> Result<Vec<u8>> {
+    let titles = records
+        .iter()
+        .map(|record| {
+            Ok(ExportedTitle {
+                style: TitleStyle::load(db, record.chat_id, record.user_id)?,
+                record: record.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    serde_json::to_vec_pretty(&titles).wrap_err("Failed to serialize titles")
+}
+
+/// Everything stored about a single user in a chat, used for data export and
+/// removal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserData {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub record: Option<TitleRecord>,
+    pub style: Option<TitleStyle>,
+    /// When the user first got a title, unix timestamp in seconds
+    pub created_at: Option<u64>,
+    pub history: Vec<HeldTitle>,
+    pub trash: Vec<TrashEntry>,
+    /// When a temporary anonymity ends, unix timestamp in seconds
+    pub anonymous_until: Option<u64>,
+    /// Previous titles still resolving to the user when signed with
+    pub old_signatures: Vec<String>,
+    pub reservations: Vec<Reservation>,
+}
+
+impl UserData {
+    /// Collect all data of the user in given chat from DB.
+    ///
+    /// # Errors
+    /// When get fails or bad encoding.
+    pub fn collect(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Self> {
+        Ok(Self {
+            chat_id,
+            user_id,
+            record: TitleRecord::get_with_id(db, chat_id, user_id)?,
+            style: TitleStyle::load(db, chat_id, user_id)?,
+            created_at: created_at(db, chat_id, user_id)?,
+            history: titles_held(db, chat_id, user_id)?,
+            trash: trashed_of(db, chat_id, user_id)?,
+            anonymous_until: scheduled_revert(db, chat_id, user_id)?,
+            old_signatures: signatures_of(db, chat_id, user_id)?,
+            reservations: Self::reservations(db, chat_id, user_id)?,
+        })
+    }
+
+    /// Remove all data of the user in given chat from DB, everything
+    /// [collected](Self::collect) included.
+    ///
+    /// # Errors
+    /// When the database returns an error or the data is in bad shape.
+    pub fn remove(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<()> {
+        TitleRecord::remove_all_of_user(db, chat_id, user_id)?;
+        TitleStyle::remove(db, chat_id, user_id)?;
+        forget_created(db, chat_id, user_id)?;
+        forget_holder(db, chat_id, user_id)?;
+        forget_trashed(db, chat_id, user_id)?;
+        cancel_revert(db, chat_id, user_id)?;
+        forget_signatures(db, chat_id, user_id)?;
+        for reservation in Self::reservations(db, chat_id, user_id)? {
+            reservation.remove_from(db)?;
+        }
+        Ok(())
     }
+
+    fn reservations(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Vec<Reservation>> {
+        let mut reservations = Reservation::list_in_chat(db, chat_id)?;
+        reservations.retain(|reservation| reservation.user_id == user_id);
+        Ok(reservations)
+    }
+}
+
+#[test]
+fn test_test_title_fits() {
+    assert!(title_len(TEST_TITLE) <= MAX_TITLE_LEN);
+}
+
+#[test]
+fn test_assert_not_chat_title() {
+    assert!(assert_not_chat_title(" golden  AXE fans", Some("Golden Axe Fans")).is_err());
+    assert!(assert_not_chat_title("Golden Axe", Some("Golden Axe Fans")).is_ok());
+    assert!(assert_not_chat_title("Golden Axe", None).is_ok());
+}
+
+#[test]
+fn test_denied_message() {
+    assert_eq!(
+        denied_message(None, "the owner"),
+        "Only the owner can do this, please contact the chat owner."
+    );
+    assert_eq!(
+        denied_message(Some("Ask @boss for help"), "the owner"),
+        "Ask @boss for help"
+    );
+}
+
+#[test]
+fn test_permission_denied_audit() {
+    let error = color_eyre::Report::from(PermissionDenied {
+        allowed: "admins",
+        status: "member",
+        message: "Only admins can do this".to_owned(),
+    });
+    // Shown as the message, the status details are only in the audit
+    assert_eq!(error.to_string(), "Only admins can do this");
+    assert_eq!(
+        error.downcast_ref::<PermissionDenied>().unwrap().audit(),
+        "Permission denied: sender is member, only admins allowed"
+    );
+}
+
+#[test]
+fn test_explain_denial() {
+    assert_eq!(
+        explain_denial("admins", &ChatMemberKind::Member),
+        "Required: admins\nYou are: member\nSet a title with /title to be promoted to admin first."
+    );
+    assert_eq!(
+        explain_denial("admins", &ChatMemberKind::Left),
+        "Required: admins\nYou are: left\nAsk an admin to promote you."
+    );
+    assert_eq!(
+        explain_denial("the owner", &ChatMemberKind::Member),
+        "Required: the owner\nYou are: member\nOnly the owner can do this, please ask them \
+         instead."
+    );
+}
+
+#[test]
+fn test_assert_demotable() {
+    use teloxide::types::Owner;
+
+    let owner = ChatMemberKind::Owner(Owner {
+        custom_title: None,
+        is_anonymous: false,
+    });
+    assert_eq!(
+        assert_demotable(&owner).unwrap_err().to_string(),
+        "Cannot demote the group creator"
+    );
+    assert!(assert_demotable(&ChatMemberKind::Member).is_ok());
+    assert_eq!(
+        assert_self_demotable(&owner).unwrap_err().to_string(),
+        "You're the owner; I can't demote you"
+    );
+    assert!(assert_self_demotable(&ChatMemberKind::Member).is_ok());
+}
+
+#[test]
+fn test_assert_not_owner() {
+    use teloxide::types::Owner;
+
+    let owner = ChatMemberKind::Owner(Owner {
+        custom_title: None,
+        is_anonymous: false,
+    });
+    assert!(assert_not_owner(&owner)
+        .unwrap_err()
+        .to_string()
+        .contains("edit the custom title there"));
+    assert!(assert_not_owner(&ChatMemberKind::Member).is_ok());
+}
+
+#[test]
+fn test_assert_title_editable() {
+    let admin = |can_be_edited: bool, can_promote_members: bool| -> ChatMemberKind {
+        let member: ChatMember = serde_json::from_value(serde_json::json!({
+            "user": { "id": 1, "is_bot": false, "first_name": "User" },
+            "status": "administrator",
+            "can_be_edited": can_be_edited,
+            "is_anonymous": false,
+            "can_manage_chat": true,
+            "can_change_info": false,
+            "can_delete_messages": false,
+            "can_manage_video_chats": false,
+            "can_invite_users": true,
+            "can_restrict_members": false,
+            "can_pin_messages": false,
+            "can_promote_members": can_promote_members
+        }))
+        .unwrap();
+        member.kind
+    };
+    let error = |me: &ChatMemberKind, member: &ChatMemberKind| {
+        assert_title_editable(me, member).unwrap_err().to_string()
+    };
+    let me = admin(false, true);
+
+    assert!(assert_title_editable(&me, &admin(true, false)).is_ok());
+    assert_eq!(
+        error(&me, &admin(false, false)),
+        "Unable to change info (maybe promoted by others?)"
+    );
+    // Members are not promoted without a message
+    assert_eq!(
+        error(&me, &ChatMemberKind::Member),
+        "Unable to edit because of status (member)"
+    );
+    assert_eq!(
+        error(&admin(false, false), &admin(true, false)),
+        "I'm not an admin, please promote me with promotion privilege first"
+    );
 }
 
 #[test]
@@ -863,6 +3178,7 @@ fn test_db() {
         title: "test".into(),
         chat_id: ChatId(1),
         user_id: UserId(2),
+        expires_at: None,
     };
 
     record.insert_into(&db).unwrap();
@@ -892,18 +3208,21 @@ fn test_list_db() {
         title: "test".into(),
         chat_id: ChatId(1),
         user_id: UserId(2),
+        expires_at: None,
     };
 
     let r1 = TitleRecord {
         title: "test".into(),
         chat_id: ChatId(1),
         user_id: UserId(3),
+        expires_at: None,
     };
 
     let r2 = TitleRecord {
         title: "test".into(),
         chat_id: ChatId(1),
         user_id: UserId(4),
+        expires_at: None,
     };
 
     r0.insert_into(&db).unwrap();
@@ -912,6 +3231,1049 @@ fn test_list_db() {
 
     let records = TitleRecord::list_in_chat(&db, ChatId(1)).unwrap();
     let empty = TitleRecord::list_in_chat(&db, ChatId(114_514)).unwrap();
-    assert_eq!(records, vec![r0, r1, r2]);
+    assert_eq!(records, vec![r0.clone(), r1.clone(), r2]);
     assert!(empty.is_empty());
+
+    // The scan stops at the cap
+    let capped = TitleRecord::list_in_chat_capped(&db, ChatId(1), 2).unwrap();
+    assert_eq!(capped, (vec![r0, r1], true));
+    let (records, capped) = TitleRecord::list_in_chat_capped(&db, ChatId(1), 3).unwrap();
+    assert_eq!((records.len(), capped), (3, false));
+}
+
+#[test]
+fn test_export_titles() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |chat_id, title: &str| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(chat_id),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    record(1, "VIP").insert_into(&db).unwrap();
+    // Shares the key prefix of chat 1 but must not be exported with it
+    record(12, "Other").insert_into(&db).unwrap();
+    let style = TitleStyle {
+        color: Some("#ff8800".into()),
+        badge: None,
+    };
+    style.save(&db, ChatId(1), UserId(2)).unwrap();
+
+    let json = export_titles(&db, &TitleRecord::list_in_chat(&db, ChatId(1)).unwrap()).unwrap();
+    assert_eq!(
+        serde_json::from_slice::<Vec<ExportedTitle>>(&json).unwrap(),
+        [ExportedTitle {
+            record: record(1, "VIP"),
+            style: Some(style),
+        }]
+    );
+    // Still readable as plain records
+    assert_eq!(
+        serde_json::from_slice::<Vec<TitleRecord>>(&json).unwrap(),
+        [record(1, "VIP")]
+    );
+    assert!(String::from_utf8(json)
+        .unwrap()
+        .contains("\n  {\n    \"title\": \"VIP\""));
+}
+
+#[test]
+fn test_user_data() {
+    use crate::schedule_revert;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let record = TitleRecord {
+        title: "test".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    // Data of another user, which must be kept
+    let other = TitleRecord {
+        title: "other".into(),
+        user_id: UserId(3),
+        ..record.clone()
+    };
+    other.insert_into(&db).unwrap();
+    let kept = db.iter().keys().try_collect::<Vec<_>>().unwrap();
+
+    // Seed every keyspace holding data of the user, trashing an old record
+    // first as that removes the current record of the user
+    let trashed = TitleRecord {
+        title: "old".into(),
+        ..record.clone()
+    };
+    trash_record(&db, &trashed, 150).unwrap();
+    record.insert_into(&db).unwrap();
+    let mut style = TitleStyle::default();
+    style.update("color=#ff8800").unwrap();
+    style.save(&db, ChatId(1), UserId(2)).unwrap();
+    mark_created(&db, ChatId(1), UserId(2), 100).unwrap();
+    record_holder(&db, &record, 100).unwrap();
+    schedule_revert(&db, ChatId(1), UserId(2), 200).unwrap();
+    remember_signature(&db, ChatId(1), "older", UserId(2), 300).unwrap();
+    let reservation = Reservation {
+        title: "next".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+    };
+    reservation.insert_into(&db).unwrap();
+
+    let data = UserData::collect(&db, ChatId(1), UserId(2)).unwrap();
+    assert_eq!(data.record, Some(record.clone()));
+    assert_eq!(data.style, Some(style));
+    assert_eq!(data.created_at, Some(100));
+    assert_eq!(
+        data.history,
+        [HeldTitle {
+            title: "test".into(),
+            since: 100
+        }]
+    );
+    assert_eq!(
+        data.trash,
+        [TrashEntry {
+            record: trashed,
+            removed_at: 150
+        }]
+    );
+    assert_eq!(data.anonymous_until, Some(200));
+    assert_eq!(data.old_signatures, ["older"]);
+    assert_eq!(data.reservations, [reservation]);
+
+    let json = serde_json::to_value(&data).unwrap();
+    assert_eq!(json["chat_id"], 1);
+    assert_eq!(json["user_id"], 2);
+    assert_eq!(json["record"]["title"], "test");
+    assert_eq!(json["style"]["color"], "#ff8800");
+    assert!(json["style"]["badge"].is_null());
+    assert_eq!(json["history"][0]["since"], 100);
+
+    // Complete removal leaves only the data of others
+    UserData::remove(&db, ChatId(1), UserId(2)).unwrap();
+    assert_eq!(db.iter().keys().try_collect::<Vec<_>>().unwrap(), kept);
+    assert_eq!(
+        UserData::collect(&db, ChatId(1), UserId(2)).unwrap(),
+        UserData {
+            chat_id: ChatId(1),
+            user_id: UserId(2),
+            record: None,
+            style: None,
+            created_at: None,
+            history: vec![],
+            trash: vec![],
+            anonymous_until: None,
+            old_signatures: vec![],
+            reservations: vec![],
+        }
+    );
+}
+
+#[test]
+fn test_list_chats() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    for (chat_id, user_id) in [(1, 2), (1, 3), (12, 2), (-100, 4)] {
+        TitleRecord {
+            title: format!("{chat_id}-{user_id}"),
+            chat_id: ChatId(chat_id),
+            user_id: UserId(user_id),
+            expires_at: None,
+        }
+        .insert_into(&db)
+        .unwrap();
+    }
+
+    let mut chats = TitleRecord::list_chats(&db).unwrap();
+    chats.sort_by_key(|chat| chat.0);
+    assert_eq!(chats, vec![ChatId(-100), ChatId(1), ChatId(12)]);
+}
+
+#[test]
+fn test_claim() {
+    use std::thread;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+
+    // Claiming a new title releases the previous one
+    record("A", 1).claim(&db).unwrap();
+    record("B", 1).claim(&db).unwrap();
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "A").unwrap(),
+        None
+    );
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(1)).unwrap(),
+        Some(record("B", 1))
+    );
+
+    // Exactly one of the racing claims wins
+    let db = &db;
+    let results = thread::scope(|scope| {
+        [2, 3]
+            .map(|user_id| scope.spawn(move || record("VIP", user_id).claim(db)))
+            .map(|handle| handle.join().unwrap())
+    });
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert!(results
+        .iter()
+        .any(|result| matches!(result, Err(error) if error.to_string() == "Title already in use")));
+
+    let winner = TitleRecord::get_with_title(db, ChatId(1), "VIP")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        TitleRecord::get_with_id(db, ChatId(1), winner.user_id).unwrap(),
+        Some(record("VIP", winner.user_id.0))
+    );
+}
+
+#[test]
+fn test_title_expiry() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |title: &str, expires_at| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at,
+    };
+    let stored = || TitleRecord::get_with_id(&db, ChatId(1), UserId(2)).unwrap();
+
+    // Values stored before expiry was added have none
+    db.insert(TitleRecord::make_chat_key(ChatId(1), UserId(2)), "VIP")
+        .unwrap();
+    assert_eq!(stored(), Some(record("VIP", None)));
+
+    // Kept with the title, and renewed when the same title is claimed again
+    record("VIP", Some(100)).claim(&db).unwrap();
+    assert_eq!(stored(), Some(record("VIP", Some(100))));
+    record("VIP", Some(200)).claim(&db).unwrap();
+    assert_eq!(stored(), Some(record("VIP", Some(200))));
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "vip").unwrap(),
+        Some(record("VIP", Some(200)))
+    );
+
+    // The previous title is released as usual
+    record("Staff", Some(300)).claim(&db).unwrap();
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "VIP").unwrap(),
+        None
+    );
+    assert_eq!(
+        TitleRecord::list_in_chat(&db, ChatId(1)).unwrap(),
+        [record("Staff", Some(300))]
+    );
+    assert!(TitleRecord::parse_chat_value(b"VIP\0soon").is_err());
+}
+
+#[test]
+fn test_title_record_display() {
+    let record = TitleRecord {
+        title: "a<b>&c".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    assert_eq!(record.to_string(), "<code>a&lt;b&gt;&amp;c: User(2)</code>");
+}
+
+#[test]
+fn test_html_replies() {
+    use crate::{assert_html, BulkReport, Inconsistency, RosterCheck, TitleDiff};
+
+    let record = |title: &str| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    let reservation = Reservation {
+        title: "<b>&".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+    };
+    let diff = TitleDiff {
+        added: vec![record("<b>&")],
+        removed: vec![record("</b>")],
+        changed: vec![(record("a&b"), record("<b>&"))],
+    };
+    let report = BulkReport {
+        reserved: vec![reservation.clone()],
+        conflicts: vec![("<b>&".into(), "in use")],
+    };
+    let roster = RosterCheck::Mismatch {
+        title: "<b>&".into(),
+        allowed: vec!["a&b".into()],
+    };
+
+    assert_html(&record("<b>&").to_string());
+    assert_html(&reservation.to_string());
+    assert_html(&diff.to_string());
+    assert_html(&report.to_string());
+    assert_html(&roster.to_string());
+    assert_html(&Inconsistency::DanglingTitleKey(record("<b>&")).to_string());
+    assert_html(&title_preview("<b>&", "<b>&"));
+}
+
+#[test]
+fn test_check_availability() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    TitleRecord {
+        title: "Taken".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+    Reservation {
+        title: "Reserved".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+    }
+    .insert_into(&db)
+    .unwrap();
+
+    let check =
+        |user_id, title| check_availability(&db, ChatId(1), UserId(user_id), title).unwrap();
+    assert_eq!(check(3, "Free"), "Available");
+    assert_eq!(check(3, "Taken"), "Taken by someone");
+    assert_eq!(check(2, "Taken"), "Already yours");
+    assert_eq!(check(3, "Reserved"), "Reserved for someone else");
+    assert_eq!(check(2, "Reserved"), "Available");
+    assert_eq!(check(3, "TAKEN "), "Taken by someone");
+    // Nothing is written, the seeded keys and the title count are all there is
+    assert_eq!(db.len(), 5);
+}
+
+#[tokio::test]
+async fn test_fetch_members() {
+    use std::sync::Mutex;
+
+    let member = |id: u64, status: &str| -> ChatMember {
+        serde_json::from_str(&format!(
+            r#"{{
+                "user": {{ "id": {id}, "is_bot": false, "first_name": "User" }},
+                "status": "{status}",
+                "is_anonymous": false
+            }}"#
+        ))
+        .unwrap()
+    };
+    let admin_fetches = Mutex::new(0);
+    let fetched = Mutex::new(Vec::new());
+
+    let members = fetch_members(
+        [UserId(1), UserId(2), UserId(3), UserId(1)],
+        || async {
+            *admin_fetches.lock().unwrap() += 1;
+            Ok(vec![member(1, "creator"), member(2, "creator")])
+        },
+        |user_id| {
+            fetched.lock().unwrap().push(user_id);
+            async move { Ok(member(user_id.0, "left")) }
+        },
+    )
+    .await
+    .unwrap();
+
+    // Admins come from the single admin fetch, only others are fetched alone
+    assert_eq!(*admin_fetches.lock().unwrap(), 1);
+    assert_eq!(*fetched.lock().unwrap(), vec![UserId(3)]);
+    assert_eq!(members.len(), 3);
+    assert!(members[&UserId(1)].is_owner());
+    assert!(matches!(members[&UserId(3)].kind, ChatMemberKind::Left));
+}
+
+#[tokio::test]
+async fn test_apply_or_rollback() {
+    use std::sync::Mutex;
+
+    let steps = Mutex::new(Vec::new());
+    let step = |name, ok| {
+        let steps = &steps;
+        async move {
+            steps.lock().unwrap().push(name);
+            ensure!(ok, "{name} failed");
+            Ok(())
+        }
+    };
+
+    // Title set, then made anonymous
+    apply_or_rollback(
+        step("title", true),
+        step("anonymous", true),
+        step("rollback", true),
+    )
+    .await
+    .unwrap();
+    assert_eq!(*steps.lock().unwrap(), ["title", "anonymous"]);
+
+    // Title rolled back when making anonymous fails
+    steps.lock().unwrap().clear();
+    let error = apply_or_rollback(
+        step("title", true),
+        step("anonymous", false),
+        step("rollback", true),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.to_string(), "anonymous failed");
+    assert_eq!(*steps.lock().unwrap(), ["title", "anonymous", "rollback"]);
+
+    // Nothing else runs when setting the title fails
+    steps.lock().unwrap().clear();
+    assert!(apply_or_rollback(
+        step("title", false),
+        step("anonymous", true),
+        step("rollback", true),
+    )
+    .await
+    .is_err());
+    assert_eq!(*steps.lock().unwrap(), ["title"]);
+}
+
+#[tokio::test]
+async fn test_demoting_on_error() {
+    use std::sync::Mutex;
+
+    let steps = Mutex::new(Vec::new());
+    let step = |name, ok| {
+        let steps = &steps;
+        async move {
+            steps.lock().unwrap().push(name);
+            ensure!(ok, "{name} failed");
+            Ok(())
+        }
+    };
+    // Composed as in `Ctx::set_title_anonymous`
+    let set_title_anonymous = |promoted, title_ok, anonymous_ok| {
+        let edit = apply_or_rollback(
+            step("title", title_ok),
+            step("anonymous", anonymous_ok),
+            step("rollback", true),
+        );
+        demoting_on_error(promoted, edit, step("demote", true))
+    };
+
+    // A promoted member stays promoted once done
+    set_title_anonymous(true, true, true).await.unwrap();
+    assert_eq!(*steps.lock().unwrap(), ["title", "anonymous"]);
+
+    // Title rolled back and the promotion undone when making anonymous fails
+    steps.lock().unwrap().clear();
+    let error = set_title_anonymous(true, true, false).await.unwrap_err();
+    assert_eq!(error.to_string(), "anonymous failed");
+    assert_eq!(
+        *steps.lock().unwrap(),
+        ["title", "anonymous", "rollback", "demote"]
+    );
+
+    // Also undone when setting the title fails
+    steps.lock().unwrap().clear();
+    let error = set_title_anonymous(true, false, true).await.unwrap_err();
+    assert_eq!(error.to_string(), "title failed");
+    assert_eq!(*steps.lock().unwrap(), ["title", "demote"]);
+
+    // Admins who were not promoted are left alone
+    steps.lock().unwrap().clear();
+    assert!(set_title_anonymous(false, true, false).await.is_err());
+    assert_eq!(*steps.lock().unwrap(), ["title", "anonymous", "rollback"]);
+}
+
+#[tokio::test]
+async fn test_restoring_rights() {
+    use std::sync::Mutex;
+
+    let steps = Mutex::new(Vec::new());
+    let set = |title: String| {
+        let steps = &steps;
+        async move {
+            ensure!(title != "Taken", "{title} failed");
+            steps.lock().unwrap().push(title);
+            Ok(())
+        }
+    };
+    let demote = || async {
+        steps.lock().unwrap().push("demoted".to_owned());
+        Ok(())
+    };
+
+    // An admin gets the test title and the previous one back, and is kept
+    restoring_rights(false, set_test_title("Old", set), demote())
+        .await
+        .unwrap();
+    assert_eq!(*steps.lock().unwrap(), [TEST_TITLE, "Old"]);
+
+    // A member promoted for the test is demoted afterwards
+    steps.lock().unwrap().clear();
+    restoring_rights(true, set_test_title("", set), demote())
+        .await
+        .unwrap();
+    assert_eq!(*steps.lock().unwrap(), [TEST_TITLE, "", "demoted"]);
+
+    // Also when setting a title fails
+    steps.lock().unwrap().clear();
+    let error = restoring_rights(true, set_test_title("Taken", set), demote())
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Taken failed");
+    assert_eq!(*steps.lock().unwrap(), [TEST_TITLE, "demoted"]);
+}
+
+#[test]
+fn test_nuke_audit() {
+    assert_eq!(
+        nuke_audit(ChatId(-1), UserId(2), 3, " <raid> "),
+        "Chat(-1) nuked by User(2), demoted 3, reason: &lt;raid&gt;"
+    );
+    assert_eq!(
+        nuke_audit(ChatId(-1), UserId(2), 0, ""),
+        "Chat(-1) nuked by User(2), demoted 0, no reason given"
+    );
+}
+
+#[test]
+fn test_numbered_titles() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let chat_id = ChatId(1);
+
+    // Free titles are used as is
+    assert_eq!(
+        numbered_titles(&db, chat_id, "Volunteer", 1).unwrap(),
+        ["Volunteer"]
+    );
+
+    // Numbered for multiple targets, skipping taken and reserved ones
+    TitleRecord {
+        title: "Volunteer".into(),
+        chat_id,
+        user_id: UserId(1),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+    Reservation {
+        chat_id,
+        user_id: UserId(2),
+        title: "Volunteer 3".into(),
+    }
+    .insert_into(&db)
+    .unwrap();
+    assert_eq!(
+        numbered_titles(&db, chat_id, "Volunteer", 3).unwrap(),
+        ["Volunteer 2", "Volunteer 4", "Volunteer 5"]
+    );
+
+    // Titles of other chats don't collide
+    assert_eq!(
+        numbered_titles(&db, ChatId(2), "Volunteer", 2).unwrap(),
+        ["Volunteer", "Volunteer 2"]
+    );
+}
+
+#[test]
+fn test_error_html() {
+    use teloxide::ApiError;
+
+    let error = RequestError::Api(ApiError::Unknown("Bad Request: <b> & co".to_owned()));
+    assert_eq!(
+        error_html(&error.into()),
+        "A Telegram's error: Unknown error: \"Bad Request: &lt;b&gt; &amp; co\""
+    );
+    // Already HTML
+    assert_eq!(
+        error_html(&eyre!(title_in_use(Some("<Alice>")))),
+        "Title already in use by <b>&lt;Alice&gt;</b>"
+    );
+}
+
+#[test]
+fn test_parse_targets() {
+    assert_eq!(
+        parse_targets("Please give these:\n@alice, 123 @bob\n@alice @ 12a"),
+        ["@alice", "123", "@bob"]
+    );
+    assert!(parse_targets("nobody here").is_empty());
+}
+
+#[test]
+fn test_parse_username() {
+    for input in ["@Alice_1", "alice_1", "  @ALICE_1 ", "@alice_1\n"] {
+        assert_eq!(parse_username(input).as_deref(), Some("alice_1"));
+    }
+    for input in [
+        "", "@", "  ", "123", "@1alice", "@al ice", "@<b>", "@@alice",
+    ] {
+        assert_eq!(parse_username(input), None);
+    }
+
+    let user: User = serde_json::from_value(serde_json::json!({
+        "id": 2,
+        "is_bot": false,
+        "first_name": "Alice",
+        "username": "Alice_1"
+    }))
+    .unwrap();
+    // Resolved the same by /demote, /resync, /reserve and /bulktitle
+    for input in ["@Alice_1", "alice_1", " @ALICE_1"] {
+        assert!(has_username(&user, &parse_username(input).unwrap()));
+    }
+    assert!(!has_username(&user, "alice"));
+}
+
+#[test]
+fn test_anonymous_admins() {
+    use teloxide::payloads::PromoteChatMember;
+
+    let member = |id: u64, status: &str, anonymous: bool, editable: bool| -> ChatMember {
+        serde_json::from_value(serde_json::json!({
+            "user": { "id": id, "is_bot": false, "first_name": "User" },
+            "status": status,
+            "is_anonymous": anonymous,
+            "can_be_edited": editable,
+            "can_manage_chat": false,
+            "can_change_info": false,
+            "can_delete_messages": false,
+            "can_manage_video_chats": false,
+            "can_invite_users": true,
+            "can_restrict_members": false,
+            "can_pin_messages": false,
+            "can_promote_members": false
+        }))
+        .unwrap()
+    };
+    let admins = [
+        member(1, "creator", true, false),
+        member(2, "administrator", true, true),
+        member(3, "administrator", false, true),
+        member(4, "administrator", true, false),
+        member(5, "administrator", true, true),
+    ];
+
+    // Only editable anonymous admins, others untouched
+    let targets = anonymous_admins(&admins);
+    assert_eq!(
+        targets
+            .iter()
+            .map(|(user_id, _)| *user_id)
+            .collect::<Vec<_>>(),
+        [UserId(2), UserId(5)]
+    );
+    assert!(anonymous_admins(&admins[..1]).is_empty());
+
+    // Privileges they hold are granted again, only anonymity is cleared
+    let (user_id, privileges) = &targets[0];
+    assert_eq!(privileges, &[Privilege::InviteUsers]);
+    let req = grant_all(PromoteChatMember::new(ChatId(1), *user_id), privileges);
+    assert_eq!(req.can_invite_users, Some(true));
+    assert_eq!(req.can_pin_messages, None);
+    assert_eq!(req.is_anonymous, None);
+}
+
+#[test]
+fn test_render_member() {
+    let member: ChatMember = serde_json::from_value(serde_json::json!({
+        "user": { "id": 2, "is_bot": false, "first_name": "Alice", "last_name": "<3" },
+        "status": "administrator",
+        "custom_title": "VIP",
+        "can_be_edited": true,
+        "is_anonymous": false,
+        "can_manage_chat": true,
+        "can_change_info": false,
+        "can_delete_messages": false,
+        "can_manage_video_chats": false,
+        "can_invite_users": true,
+        "can_restrict_members": false,
+        "can_pin_messages": false,
+        "can_promote_members": false
+    }))
+    .unwrap();
+
+    assert_eq!(
+        render_member(&member),
+        "<b>Alice &lt;3</b> (<code>2</code>)\nKind: admin\nCustom title: \
+         <code>VIP</code>\nAnonymous: false\nCan be edited by me: true\nPrivileges: \
+         <code>manage_chat</code>, <code>invite_users</code>"
+    );
+}
+
+#[test]
+fn test_validate_title() {
+    // Multi-byte characters count once
+    assert!(validate_title("Café Crème ⭐").is_ok());
+    assert!(validate_title(&"é".repeat(MAX_TITLE_LEN)).is_ok());
+    assert_eq!(
+        validate_title("Twenty-three characters")
+            .unwrap_err()
+            .to_string(),
+        "Title too long (max 16 characters, you used 23)"
+    );
+
+    // Emojis outside the Basic Multilingual Plane count twice, like Telegram
+    // counts them
+    assert!(validate_title(&"\u{1F600}".repeat(8)).is_ok());
+    assert_eq!(
+        validate_title(&"\u{1F600}".repeat(10))
+            .unwrap_err()
+            .to_string(),
+        "Title too long (max 16 characters, you used 20)"
+    );
+
+    // Each family emoji is made of 3 such emojis joined by 2 zero width joiners
+    let families = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}".repeat(10);
+    assert_eq!(
+        validate_title(&families).unwrap_err().to_string(),
+        "Title too long (max 16 characters, you used 80)"
+    );
+}
+
+#[tokio::test]
+async fn test_forget_once_demoted() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = TitleRecord {
+        title: "VIP".to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    record.claim(&db).unwrap();
+    let get = || TitleRecord::get_with_id(&db, ChatId(1), UserId(2)).unwrap();
+
+    // Kept while the title is still live
+    let demote = async { Err(eyre!("Demote member error")) };
+    assert!(forget_once_demoted(&db, ChatId(1), UserId(2), demote)
+        .await
+        .is_err());
+    assert_eq!(get(), Some(record));
+
+    // Removed once demoted, along with its keys
+    forget_once_demoted(&db, ChatId(1), UserId(2), async { Ok(()) })
+        .await
+        .unwrap();
+    assert_eq!(get(), None);
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "VIP").unwrap(),
+        None
+    );
+
+    // Nothing to remove
+    forget_once_demoted(&db, ChatId(1), UserId(2), async { Ok(()) })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_rename_leaves_no_orphan() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    record("Old", 2).claim(&db).unwrap();
+    record("Taken", 3).claim(&db).unwrap();
+    // Claimed like `apply_title` does, without a title set on Telegram
+    let rename = |old: &'static str, new: &str| {
+        let (db, record) = (&db, &record);
+        rename_held_title(
+            db,
+            ChatId(1),
+            UserId(2),
+            old,
+            new.to_owned(),
+            |new| async move { record(&new, 2).claim(db) },
+        )
+    };
+
+    // Only titles held can be renamed
+    assert_eq!(
+        rename("Taken", "New").await.unwrap_err().to_string(),
+        "You don't hold the title <code>Taken</code>"
+    );
+    assert!(rename("Old", "").await.is_err());
+
+    // Colliding renames change nothing
+    assert_eq!(
+        rename("old", "taken").await.unwrap_err().to_string(),
+        title_in_use(None)
+    );
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(2)).unwrap(),
+        Some(record("Old", 2))
+    );
+
+    // Only the keys of the new title are left
+    rename("Old", "New").await.unwrap();
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "Old").unwrap(),
+        None
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "New").unwrap(),
+        Some(record("New", 2))
+    );
+    assert_eq!(db.scan_prefix("title$").count(), 2);
+    assert_eq!(db.scan_prefix("titlenorm$").count(), 2);
+    assert!(verify_db(&db).unwrap().inconsistencies.is_empty());
+}
+
+#[test]
+fn test_title_case_insensitive() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    record("Admin", 2).claim(&db).unwrap();
+
+    // Other cases and spacing are taken too
+    assert!(record("admin", 3).claim(&db).is_err());
+    assert!(record(" ADMIN ", 3).claim(&db).is_err());
+
+    // Found in any case, with the title as typed by the holder
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "aDmIn").unwrap(),
+        Some(record("Admin", 2))
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "Mod").unwrap(),
+        None
+    );
+
+    // The holder can change the case of their own title
+    record("ADMIN", 2).claim(&db).unwrap();
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "admin").unwrap(),
+        Some(record("ADMIN", 2))
+    );
+
+    // Removing by the original title frees every case
+    let found = TitleRecord::get_with_title(&db, ChatId(1), "ADMIN")
+        .unwrap()
+        .unwrap();
+    found.remove_from(&db).unwrap();
+    record("admin", 3).claim(&db).unwrap();
+    assert!(verify_db(&db).unwrap().inconsistencies.is_empty());
+
+    // Stale normalized keys do not block claims
+    db.insert(
+        TitleRecord::make_norm_key(ChatId(1), "Mod"),
+        &5_u64.to_be_bytes(),
+    )
+    .unwrap();
+    record("mod", 4).claim(&db).unwrap();
+}
+
+#[test]
+fn test_backfill_norm_keys() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    // Stored before normalized keys, when titles differing in case could
+    // both be taken
+    for record in [record("Admin", 2), record("ADMIN", 3), record("Mod", 4)] {
+        record.insert_into(&db).unwrap();
+        db.remove(TitleRecord::make_norm_key(ChatId(1), &record.title))
+            .unwrap();
+    }
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "mod").unwrap(),
+        None
+    );
+
+    assert_eq!(TitleRecord::backfill_norm_keys(&db).unwrap(), 2);
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "mod").unwrap(),
+        Some(record("Mod", 4))
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "admin")
+            .unwrap()
+            .map(|record| record.user_id),
+        Some(UserId(2))
+    );
+
+    // Only once
+    db.remove(TitleRecord::make_norm_key(ChatId(1), "Mod"))
+        .unwrap();
+    assert_eq!(TitleRecord::backfill_norm_keys(&db).unwrap(), 0);
+}
+
+#[test]
+fn test_title_in_use() {
+    // Privacy-safe by default
+    assert_eq!(title_in_use(None), "Title already in use");
+    assert_eq!(
+        title_in_use(Some("Alice <3")),
+        "Title already in use by <b>Alice &lt;3</b>"
+    );
+
+    // What a claim of a held title fails with, unless enriched
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |user_id| TitleRecord {
+        title: "VIP".to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    record(2).claim(&db).unwrap();
+    assert_eq!(
+        record(3).claim(&db).unwrap_err().to_string(),
+        title_in_use(None)
+    );
+}
+
+#[test]
+fn test_title_preview() {
+    assert_eq!(
+        title_preview("Alice", "<b>VIP</b> & co"),
+        "Preview: <b>Alice</b>  <i>&lt;b&gt;VIP&lt;/b&gt; &amp; co</i>"
+    );
+}
+
+#[tokio::test]
+async fn test_fetch_admins_expecting() {
+    use std::sync::Mutex;
+
+    let member = |id: u64| -> ChatMember {
+        serde_json::from_str(&format!(
+            r#"{{
+                "user": {{ "id": {id}, "is_bot": false, "first_name": "User" }},
+                "status": "creator",
+                "is_anonymous": false
+            }}"#
+        ))
+        .unwrap()
+    };
+    // The promoted user 2 shows up from the second fetch on
+    let fetches = Mutex::new(0);
+    let fetch = || async {
+        let mut count = fetches.lock().unwrap();
+        *count += 1;
+        Ok(if *count == 1 {
+            vec![member(1)]
+        } else {
+            vec![member(1), member(2)]
+        })
+    };
+    let ids = |admins: Vec<ChatMember>| {
+        admins
+            .into_iter()
+            .map(|admin| admin.user.id)
+            .collect::<Vec<_>>()
+    };
+    let delay = Duration::from_millis(1);
+
+    let admins = fetch_admins_expecting(fetch, Some(UserId(2)), 3, delay)
+        .await
+        .unwrap();
+    assert_eq!(ids(admins), [UserId(1), UserId(2)]);
+    assert_eq!(*fetches.lock().unwrap(), 2);
+
+    // Fetched once when nobody was promoted
+    *fetches.lock().unwrap() = 0;
+    let admins = fetch_admins_expecting(fetch, None, 3, delay).await.unwrap();
+    assert_eq!(ids(admins), [UserId(1)]);
+    assert_eq!(*fetches.lock().unwrap(), 1);
+
+    // Retries are bounded
+    let fetches = Mutex::new(0);
+    let admins = fetch_admins_expecting(
+        || async {
+            *fetches.lock().unwrap() += 1;
+            Ok(vec![member(1)])
+        },
+        Some(UserId(2)),
+        3,
+        delay,
+    )
+    .await
+    .unwrap();
+    assert_eq!(ids(admins), [UserId(1)]);
+    assert_eq!(*fetches.lock().unwrap(), 4);
+}
+
+#[test]
+fn test_relay_feedback() {
+    let cooldown = Cooldown::new(Duration::from_secs(60));
+    let user = |id: u64| -> User {
+        serde_json::from_str(&format!(
+            r#"{{ "id": {id}, "is_bot": false, "first_name": "Alice" }}"#
+        ))
+        .unwrap()
+    };
+    let mut relayed = Vec::new();
+
+    relay_feedback(
+        &cooldown,
+        ChatId(-1),
+        &user(2),
+        " <3 the bot ",
+        |feedback| {
+            relayed.push(feedback);
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        relayed,
+        ["Feedback from Alice (User(2)) in Chat(-1):\n&lt;3 the bot"]
+    );
+
+    // Rate limited per user, without relaying
+    assert!(
+        relay_feedback(&cooldown, ChatId(-1), &user(2), "again", |feedback| {
+            relayed.push(feedback);
+        })
+        .is_err()
+    );
+    assert_eq!(relayed.len(), 1);
+    relay_feedback(&cooldown, ChatId(-1), &user(3), "hi", |feedback| {
+        relayed.push(feedback);
+    })
+    .unwrap();
+    assert_eq!(relayed.len(), 2);
+
+    // Empty feedback doesn't start the cooldown
+    assert!(relay_feedback(&cooldown, ChatId(-1), &user(4), " ", |_| {}).is_err());
+    relay_feedback(&cooldown, ChatId(-1), &user(4), "hi", |_| {}).unwrap();
+}
+
+#[test]
+fn test_preserve_emoji_prefix() {
+    assert_eq!(emoji_prefix("🌟 Alice"), "🌟 ");
+    assert_eq!(emoji_prefix("👩‍💻Dev"), "👩‍💻");
+    assert_eq!(emoji_prefix("Alice 🌟"), "");
+
+    // Dropped emoji prefix is preserved
+    assert_eq!(
+        preserve_emoji_prefix("🌟 Alice", "Bob", ""),
+        Some("🌟 Bob".to_owned())
+    );
+    // Also behind the prefix of the chat
+    assert_eq!(
+        preserve_emoji_prefix("[F]🌟 Alice", "Bob", "[F]"),
+        Some("🌟 Bob".to_owned())
+    );
+
+    // New emoji prefix and titles without one are left alone
+    assert_eq!(preserve_emoji_prefix("🌟 Alice", "🔥 Bob", ""), None);
+    assert_eq!(preserve_emoji_prefix("Alice", "Bob", ""), None);
+    assert_eq!(preserve_emoji_prefix("[F]Alice", "Bob", "[F]"), None);
 }
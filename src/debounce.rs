@@ -0,0 +1,90 @@
+use std::{collections::HashMap, future::Future, hash::Hash, sync::Mutex, time::Duration};
+
+use tokio::time::sleep;
+
+/// Coalesces rapid calls with the same key, so only the last one within a
+/// window goes through, e.g. a user fiddling with their title.
+///
+/// Values are held back by a spawned task rather than by the caller, since
+/// updates of a chat are handled one after another and waiting in the handler
+/// would hold up the later calls it's meant to coalesce.
+#[derive(Debug)]
+pub struct Debouncer<K, V> {
+    /// Latest value of each key still waiting
+    pending: Mutex<HashMap<K, V>>,
+}
+
+impl<K, V> Default for Debouncer<K, V> {
+    fn default() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K, V> Debouncer<K, V>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    V: Send + 'static,
+{
+    /// Queue `value` of `key`, returning the value still pending it replaces,
+    /// if any. Returns right away: the first value queued spawns a task, which
+    /// waits for `window` and then calls `apply` with the last value queued
+    /// meanwhile.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn push<Func, Fut>(
+        &'static self,
+        key: K,
+        value: V,
+        window: Duration,
+        apply: Func,
+    ) -> Option<V>
+    where
+        Func: FnOnce(V) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+    {
+        let replaced = self.pending.lock().unwrap().insert(key.clone(), value);
+        if replaced.is_none() {
+            tokio::spawn(async move {
+                sleep(window).await;
+                let value = self.pending.lock().unwrap().remove(&key);
+                if let Some(value) = value {
+                    apply(value).await;
+                }
+            });
+        }
+        replaced
+    }
+}
+
+#[tokio::test]
+async fn test_debouncer() {
+    use std::sync::LazyLock;
+
+    static DEBOUNCER: LazyLock<Debouncer<u64, &str>> = LazyLock::new(Debouncer::default);
+    static APPLIED: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    let window = Duration::from_millis(100);
+    // Handled one after another like updates of a chat, each returning before
+    // the next one comes in
+    let mut superseded = Vec::new();
+    for (delay, key, title) in [(0, 1, "a"), (20, 1, "b"), (0, 2, "other"), (20, 1, "c")] {
+        sleep(Duration::from_millis(delay)).await;
+        let apply = |title| async move { APPLIED.lock().unwrap().push(title) };
+        superseded.extend(DEBOUNCER.push(key, title, window, apply));
+    }
+    assert_eq!(superseded, ["a", "b"]);
+    assert!(APPLIED.lock().unwrap().is_empty());
+
+    // Three rapid sets result in one call with the final value, while others
+    // are not affected
+    sleep(window * 2).await;
+    let mut applied = APPLIED.lock().unwrap().clone();
+    applied.sort_unstable();
+    assert_eq!(applied, ["c", "other"]);
+
+    // Applied again after the window passed
+    assert_eq!(DEBOUNCER.push(1, "d", window, |_| async {}), None);
+}
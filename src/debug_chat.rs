@@ -1,12 +1,17 @@
-use std::sync::OnceLock;
+use std::{
+    fmt::{self, Display},
+    sync::OnceLock,
+};
 
+use serde::Deserialize;
 use tap::TapOptional;
 use teloxide::{
     prelude::{Request, Requester},
     types::ChatId,
+    utils::html,
 };
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 use crate::{Config, BOT};
 
@@ -23,10 +28,24 @@ pub fn init() {
 
                 tokio::spawn(async move {
                     let bot = BOT.get().unwrap();
+                    let mut failures = SendFailures::new(Config::get().debug_chat_max_failures);
                     while let Some(msg) = rx.recv().await {
-                        if let Err(e) = bot.send_message(ChatId(id), msg).send().await {
+                        if failures.given_up() {
+                            error!("{msg}");
+                            continue;
+                        }
+                        let res = bot.send_message(ChatId(id), &msg).send().await;
+                        if let Err(e) = &res {
                             warn!("Failed to send to debug channel: {:?}", e);
                         }
+                        if failures.record(res.is_ok()) {
+                            error!(
+                                "Failed to send to debug channel {} times in a row, debug \
+                                 messages will only be logged from now on",
+                                failures.threshold
+                            );
+                            error!("{msg}");
+                        }
                     }
                 });
 
@@ -38,6 +57,39 @@ pub fn init() {
     });
 }
 
+/// Counts consecutive failures of sending to the debug chat, giving up after
+/// `threshold` of them in a row, e.g. when the bot is not in the chat.
+#[derive(Debug)]
+struct SendFailures {
+    threshold: usize,
+    count: usize,
+}
+
+impl SendFailures {
+    fn new(threshold: usize) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            count: 0,
+        }
+    }
+
+    /// Whether to stop sending to the debug chat.
+    const fn given_up(&self) -> bool {
+        self.count >= self.threshold
+    }
+
+    /// Record whether a send succeeded. Returns `true` only when this failure
+    /// makes it give up, so the fallback is warned once.
+    fn record(&mut self, ok: bool) -> bool {
+        if ok {
+            self.count = 0;
+            return false;
+        }
+        self.count += 1;
+        self.count == self.threshold
+    }
+}
+
 /// Send a debug message to the debug channel if `debug_chat` is set or or log
 /// it otherwise
 ///
@@ -60,6 +112,101 @@ pub fn send_debug(content: &impl ToString) {
     }
 }
 
+/// Format of debug events sent to the debug chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugFormat {
+    /// Just the message
+    #[default]
+    Plain,
+    /// A compact HTML card with severity, command and chat
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+        })
+    }
+}
+
+/// A debug message with context of where it comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugEvent {
+    pub severity: Severity,
+    pub message: String,
+    pub command: Option<String>,
+    pub chat: Option<ChatId>,
+}
+
+impl DebugEvent {
+    #[must_use]
+    pub fn new(severity: Severity, message: &impl ToString) -> Self {
+        Self {
+            severity,
+            message: message.to_string(),
+            command: None,
+            chat: None,
+        }
+    }
+
+    #[must_use]
+    pub fn error(message: &impl ToString) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    #[must_use]
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn chat(mut self, chat: ChatId) -> Self {
+        self.chat = Some(chat);
+        self
+    }
+
+    /// Render the event in given format. All interpolated values are escaped
+    /// in [`DebugFormat::Html`].
+    #[must_use]
+    pub fn render(&self, format: DebugFormat) -> String {
+        match format {
+            DebugFormat::Plain => self.message.clone(),
+            DebugFormat::Html => {
+                let mut card = format!("<b>[{}]</b>", self.severity);
+                if let Some(command) = &self.command {
+                    card += &format!(" <code>{}</code>", html::escape(command));
+                }
+                if let Some(chat) = self.chat {
+                    card += &format!(" in <code>{chat}</code>");
+                }
+                card + "\n" + &html::escape(&self.message)
+            }
+        }
+    }
+}
+
+/// Send a debug event rendered in configured `debug_format`, see
+/// [`send_debug`].
+///
+/// # Panics
+///
+/// When debug channel is not initialized
+pub fn send_debug_event(event: &DebugEvent) {
+    send_debug(&event.render(Config::get().debug_format));
+}
+
 macro_rules! catch {
     ($expr:expr) => {
         if let Err(e) = $expr {
@@ -75,3 +222,36 @@ macro_rules! catch {
 }
 
 pub(crate) use catch;
+
+#[test]
+fn test_debug_event_render() {
+    let event = DebugEvent::error(&"Title already in use")
+        .command("Title { title: <b>VIP</b> }")
+        .chat(ChatId(-100));
+
+    assert_eq!(event.render(DebugFormat::Plain), "Title already in use");
+    assert_eq!(
+        event.render(DebugFormat::Html),
+        "<b>[ERROR]</b> <code>Title { title: &lt;b&gt;VIP&lt;/b&gt; }</code> in \
+         <code>-100</code>\nTitle already in use"
+    );
+}
+
+#[test]
+fn test_send_failures() {
+    let mut failures = SendFailures::new(3);
+
+    // Successes reset the count
+    assert!(!failures.record(false));
+    assert!(!failures.record(false));
+    assert!(!failures.record(true));
+    assert!(!failures.given_up());
+
+    // Gives up after repeated failures, warning once
+    assert!(!failures.record(false));
+    assert!(!failures.record(false));
+    assert!(failures.record(false));
+    assert!(failures.given_up());
+    assert!(!failures.record(false));
+    assert!(failures.given_up());
+}
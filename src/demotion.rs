@@ -0,0 +1,148 @@
+use color_eyre::Result;
+use serde::Deserialize;
+use sled::Db;
+use teloxide::{
+    types::{ChatMemberUpdated, UserId},
+    utils::html,
+};
+use tracing::info;
+
+use crate::TitleRecord;
+
+/// What to do with the title record of a member demoted by someone other than
+/// the bot, e.g. a human admin in the Telegram UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DemotionPolicy {
+    /// Keep the record, which is stale until the member sets a new title
+    #[default]
+    Keep,
+    /// Clear the record
+    Clear,
+    /// Clear the record and tell the chat
+    Notify,
+}
+
+/// Clear the record of the member demoted in `update` by someone other than
+/// the bot (`me`), following `policy`. Returns the message to send to the
+/// chat, if any.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn handle_external_demotion(
+    db: &Db,
+    update: &ChatMemberUpdated,
+    me: UserId,
+    policy: DemotionPolicy,
+) -> Result<Option<String>> {
+    let demoted =
+        update.old_chat_member.is_administrator() && !update.new_chat_member.is_administrator();
+    if policy == DemotionPolicy::Keep || !demoted || update.from.id == me {
+        return Ok(None);
+    }
+    let user = &update.new_chat_member.user;
+    let record = match TitleRecord::get_with_id(db, update.chat.id, user.id)? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+    record.remove_from(db)?;
+    info!(chat = ?update.chat.id, user = ?user.id, "Cleared title of externally demoted member");
+
+    Ok((policy == DemotionPolicy::Notify).then(|| {
+        format!(
+            "{} is no longer an admin, title <code>{}</code> is cleared",
+            html::escape(&user.full_name()),
+            html::escape(&record.title)
+        )
+    }))
+}
+
+#[test]
+fn test_handle_external_demotion() {
+    use teloxide::types::ChatId;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = TitleRecord {
+        title: "VIP".to_owned(),
+        chat_id: ChatId(-1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    let update = |by: u64, new_status: &str| -> ChatMemberUpdated {
+        let member = |status: &str| {
+            let user = serde_json::json!({ "id": 2, "is_bot": false, "first_name": "Alice" });
+            if status != "administrator" {
+                return serde_json::json!({ "user": user, "status": status });
+            }
+            serde_json::json!({
+                "user": user,
+                "status": status,
+                "can_be_edited": true,
+                "is_anonymous": false,
+                "can_manage_chat": false,
+                "can_change_info": false,
+                "can_delete_messages": false,
+                "can_manage_video_chats": false,
+                "can_invite_users": true,
+                "can_restrict_members": false,
+                "can_pin_messages": false,
+                "can_promote_members": false
+            })
+        };
+        serde_json::from_value(serde_json::json!({
+            "chat": { "id": -1, "type": "supergroup", "title": "Chat" },
+            "from": { "id": by, "is_bot": false, "first_name": "Admin" },
+            "date": 0,
+            "old_chat_member": member("administrator"),
+            "new_chat_member": member(new_status)
+        }))
+        .unwrap()
+    };
+    let me = UserId(1);
+    let has_record = || {
+        TitleRecord::get_with_id(&db, ChatId(-1), UserId(2))
+            .unwrap()
+            .is_some()
+    };
+    record.insert_into(&db).unwrap();
+
+    // Kept when not configured, not a demotion or demoted by the bot itself
+    let demotion = update(3, "member");
+    assert_eq!(
+        handle_external_demotion(&db, &demotion, me, DemotionPolicy::Keep).unwrap(),
+        None
+    );
+    let promotion = update(3, "administrator");
+    assert_eq!(
+        handle_external_demotion(&db, &promotion, me, DemotionPolicy::Clear).unwrap(),
+        None
+    );
+    let by_bot = update(1, "member");
+    assert_eq!(
+        handle_external_demotion(&db, &by_bot, me, DemotionPolicy::Clear).unwrap(),
+        None
+    );
+    assert!(has_record());
+
+    // Cleared silently
+    assert_eq!(
+        handle_external_demotion(&db, &demotion, me, DemotionPolicy::Clear).unwrap(),
+        None
+    );
+    assert!(!has_record());
+
+    // Cleared with the chat notified
+    record.insert_into(&db).unwrap();
+    let message = handle_external_demotion(&db, &demotion, me, DemotionPolicy::Notify).unwrap();
+    assert_eq!(
+        message.as_deref(),
+        Some("Alice is no longer an admin, title <code>VIP</code> is cleared")
+    );
+    assert!(!has_record());
+
+    // Nothing to clear for untracked members
+    assert_eq!(
+        handle_external_demotion(&db, &demotion, me, DemotionPolicy::Notify).unwrap(),
+        None
+    );
+}
@@ -0,0 +1,62 @@
+use color_eyre::Result;
+
+/// Outcome of a single check of `/diagnose`, with details when it passes.
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Result<String>,
+}
+
+impl Check {
+    #[must_use]
+    pub fn new(name: impl Into<String>, outcome: Result<String>) -> Self {
+        Self {
+            name: name.into(),
+            outcome,
+        }
+    }
+}
+
+/// Render all checks as a report, one line per check, headed by how many of
+/// them failed.
+#[must_use]
+pub fn render_diagnosis(checks: &[Check]) -> String {
+    let failed = checks.iter().filter(|check| check.outcome.is_err()).count();
+    let mut report = if failed == 0 {
+        "<b>Diagnosis</b>: all checks passed".to_owned()
+    } else {
+        format!(
+            "<b>Diagnosis</b>: {failed} of {} checks failed",
+            checks.len()
+        )
+    };
+    for check in checks {
+        let line = match &check.outcome {
+            Ok(detail) => format!("\n[ok] {}: {detail}", check.name),
+            Err(error) => format!("\n[failed] {}: {error}", check.name),
+        };
+        report.push_str(&line);
+    }
+    report
+}
+
+#[test]
+fn test_render_diagnosis() {
+    use color_eyre::eyre::eyre;
+
+    assert_eq!(
+        render_diagnosis(&[Check::new("Database", Ok("readable".to_owned()))]),
+        "<b>Diagnosis</b>: all checks passed\n[ok] Database: readable"
+    );
+
+    // Failures don't stop the other checks from being reported
+    assert_eq!(
+        render_diagnosis(&[
+            Check::new("Bot status", Err(eyre!("I am not an admin"))),
+            Check::new("Database", Ok("readable".to_owned())),
+            Check::new("Privilege", Err(eyre!("missing"))),
+        ]),
+        "<b>Diagnosis</b>: 2 of 3 checks failed\n[failed] Bot status: I am not an admin\n[ok] \
+         Database: readable\n[failed] Privilege: missing"
+    );
+}
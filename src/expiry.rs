@@ -0,0 +1,148 @@
+use std::future::Future;
+
+use color_eyre::Result;
+use sled::Db;
+use teloxide::{
+    prelude::*,
+    types::{ChatId, UserId},
+};
+use tokio::time::interval;
+use tracing::info;
+
+use crate::{send_debug, unix_now, Config, TitleRecord, BOT};
+
+/// Expiry of a title set at `now`, if `title_ttl` is set.
+///
+/// # Panics
+/// When config cannot be parsed
+#[must_use]
+pub fn expiry_from(now: u64) -> Option<u64> {
+    Config::get().title_ttl.map(|ttl| now + ttl.as_secs())
+}
+
+/// Records whose title is expired at `now`.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn list_expired(db: &Db, now: u64) -> Result<Vec<TitleRecord>> {
+    let mut expired = Vec::new();
+    for entry in db.scan_prefix("chat$") {
+        let (key, value) = entry?;
+        let record = TitleRecord::parse_chat_key(&key, &value)?;
+        if record
+            .expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+        {
+            expired.push(record);
+        }
+    }
+    Ok(expired)
+}
+
+/// Demote holders of titles expired at `now` with `demote`, then remove their
+/// records. Returns how many titles were reaped. Records of users failed to be
+/// demoted are kept, so they're tried again on the next sweep.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub async fn reap_expired<Func, Fut>(db: &Db, now: u64, mut demote: Func) -> Result<usize>
+where
+    Func: FnMut(ChatId, UserId) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut reaped = 0;
+    for record in list_expired(db, now)? {
+        if let Err(error) = demote(record.chat_id, record.user_id).await {
+            send_debug(&error);
+            continue;
+        }
+        // Unless the title was set again while demoting
+        if TitleRecord::get_with_id(db, record.chat_id, record.user_id)?.as_ref() == Some(&record) {
+            record.remove_from(db)?;
+            reaped += 1;
+        }
+    }
+    Ok(reaped)
+}
+
+/// Reap expired titles every `title_sweep_interval` if `title_ttl` is set,
+/// never returns.
+///
+/// # Panics
+/// If the bot is not initialized.
+pub async fn run_expiry(db: Db) {
+    let conf = Config::get();
+    if conf.title_ttl.is_none() {
+        return std::future::pending().await;
+    }
+    let bot = BOT.get().unwrap();
+    let mut interval = interval(conf.title_sweep_interval);
+    loop {
+        interval.tick().await;
+        // Promoting with no privileges demotes the member
+        let reaped = reap_expired(&db, unix_now(), |chat_id, user_id| async move {
+            bot.promote_chat_member(chat_id, user_id).send().await?;
+            Ok(())
+        })
+        .await;
+        match reaped {
+            Ok(reaped) => info!(reaped, "Swept expired titles"),
+            Err(error) => send_debug(&error),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_reap_expired() {
+    use std::sync::Mutex;
+
+    use color_eyre::eyre::bail;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |user_id, expires_at| TitleRecord {
+        title: format!("title-{user_id}"),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at,
+    };
+    record(2, Some(100)).insert_into(&db).unwrap();
+    record(3, Some(200)).insert_into(&db).unwrap();
+    record(4, None).insert_into(&db).unwrap();
+
+    let demoted = Mutex::new(Vec::new());
+    let demote = |_, user_id: UserId| {
+        demoted.lock().unwrap().push(user_id.0);
+        async { Ok(()) }
+    };
+
+    // Nothing expired yet
+    assert_eq!(reap_expired(&db, 99, demote).await.unwrap(), 0);
+    assert!(demoted.lock().unwrap().is_empty());
+
+    // Only expired titles are reaped
+    assert_eq!(reap_expired(&db, 150, demote).await.unwrap(), 1);
+    assert_eq!(*demoted.lock().unwrap(), [2]);
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(2)).unwrap(),
+        None
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "title-2").unwrap(),
+        None
+    );
+
+    // Kept when demotion fails
+    let res = reap_expired(&db, 300, |_, _| async { bail!("Bad Request") }).await;
+    assert_eq!(res.unwrap(), 0);
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(3)).unwrap(),
+        Some(record(3, Some(200)))
+    );
+
+    // Titles without expiry never expire
+    assert_eq!(reap_expired(&db, u64::MAX, demote).await.unwrap(), 1);
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(4)).unwrap(),
+        Some(record(4, None))
+    );
+}
@@ -0,0 +1,207 @@
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use humantime_serde::re::humantime;
+use teloxide::{prelude::*, types::ChatId};
+use tokio::time::interval;
+use tracing::warn;
+
+use crate::{Config, BOT};
+
+static COMMANDS: AtomicU64 = AtomicU64::new(0);
+static FAILED_COMMANDS: AtomicU64 = AtomicU64::new(0);
+
+/// Count a handled command for the heartbeat status.
+pub fn count_command(ok: bool) {
+    COMMANDS.fetch_add(1, Ordering::Relaxed);
+    if !ok {
+        FAILED_COMMANDS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Liveness of the bot, shown in the heartbeat message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub uptime: Duration,
+    pub commands: u64,
+    pub failed_commands: u64,
+}
+
+impl Status {
+    #[must_use]
+    pub fn current(started: Instant) -> Self {
+        Self {
+            uptime: started.elapsed(),
+            commands: COMMANDS.load(Ordering::Relaxed),
+            failed_commands: FAILED_COMMANDS.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let uptime = Duration::from_secs(self.uptime.as_secs());
+        write!(
+            f,
+            "Golden Axe <b>Alive</b>\nUptime: {}\nCommands: {} ({} failed)",
+            humantime::format_duration(uptime),
+            self.commands,
+            self.failed_commands
+        )
+    }
+}
+
+/// The status message in the debug chat. Posted on the first beat and edited
+/// in place afterwards, so the chat is not flooded.
+#[derive(Debug, Default)]
+struct Heartbeat {
+    message_id: Option<i32>,
+}
+
+impl Heartbeat {
+    /// Post the text with `post`, which edits the message of given id, or
+    /// sends a new one when there's none and returns its id. A new message is
+    /// sent if editing fails, e.g. when the old one was deleted.
+    ///
+    /// # Errors
+    /// If sending the new message fails.
+    async fn beat<Func, Fut>(&mut self, text: String, post: Func) -> Result<()>
+    where
+        Func: Fn(Option<i32>, String) -> Fut,
+        Fut: Future<Output = Result<i32>>,
+    {
+        if let Some(message_id) = self.message_id {
+            if post(Some(message_id), text.clone()).await.is_ok() {
+                return Ok(());
+            }
+        }
+        self.message_id = Some(post(None, text).await?);
+        Ok(())
+    }
+}
+
+/// Chat and period of the heartbeat, only when both `debug_chat` and
+/// `heartbeat_interval` are set. A zero interval disables it as well.
+fn schedule(conf: &Config) -> Option<(ChatId, Duration)> {
+    let period = conf.heartbeat_interval.filter(|period| !period.is_zero())?;
+    Some((ChatId(conf.debug_chat?), period))
+}
+
+/// Post the status to the debug chat every `heartbeat_interval`. Never
+/// resolves, and does nothing if not scheduled, see [`schedule`].
+///
+/// # Panics
+/// When config cannot be parsed or the bot is not initialized
+pub async fn run_heartbeat() {
+    let Some((chat_id, period)) = schedule(Config::get()) else {
+        return std::future::pending().await;
+    };
+    let bot = BOT.get().unwrap();
+    let started = Instant::now();
+    let mut heartbeat = Heartbeat::default();
+    let mut interval = interval(period);
+    loop {
+        interval.tick().await;
+        let text = Status::current(started).to_string();
+        let res = heartbeat
+            .beat(text, |message_id, text| async move {
+                match message_id {
+                    Some(id) => {
+                        bot.edit_message_text(chat_id, id, text).send().await?;
+                        Ok(id)
+                    }
+                    None => Ok(bot.send_message(chat_id, text).send().await?.id),
+                }
+            })
+            .await;
+        // Not sent to the debug chat, which is what failed
+        if let Err(error) = res {
+            warn!(%error, "Failed to post heartbeat");
+        }
+    }
+}
+
+#[test]
+fn test_status() {
+    let status = Status {
+        uptime: Duration::from_millis(3_723_500),
+        commands: 42,
+        failed_commands: 3,
+    };
+    assert_eq!(
+        status.to_string(),
+        "Golden Axe <b>Alive</b>\nUptime: 1h 2m 3s\nCommands: 42 (3 failed)"
+    );
+}
+
+#[test]
+fn test_schedule() {
+    figment::Jail::expect_with(|j| {
+        j.set_env("GOLDEN_AXE_TOKEN", "token");
+        assert_eq!(schedule(&Config::from_env().unwrap()), None);
+
+        // Nowhere to post to
+        j.set_env("GOLDEN_AXE_HEARTBEAT_INTERVAL", "5m");
+        assert_eq!(schedule(&Config::from_env().unwrap()), None);
+
+        j.set_env("GOLDEN_AXE_DEBUG_CHAT", "123");
+        assert_eq!(
+            schedule(&Config::from_env().unwrap()),
+            Some((ChatId(123), Duration::from_secs(5 * 60)))
+        );
+
+        j.set_env("GOLDEN_AXE_HEARTBEAT_INTERVAL", "0s");
+        assert_eq!(schedule(&Config::from_env().unwrap()), None);
+        Ok(())
+    });
+}
+
+#[tokio::test]
+async fn test_heartbeat() {
+    use std::sync::Mutex;
+
+    use color_eyre::eyre::bail;
+
+    let posted = Mutex::new(Vec::new());
+    let deleted = Mutex::new(false);
+    let post = |message_id: Option<i32>, text: String| {
+        posted.lock().unwrap().push((message_id, text));
+        let deleted = *deleted.lock().unwrap();
+        let next_id = i32::try_from(posted.lock().unwrap().len()).unwrap();
+        async move {
+            match message_id {
+                Some(_) if deleted => bail!("Bad Request: message to edit not found"),
+                Some(id) => Ok(id),
+                None => Ok(next_id),
+            }
+        }
+    };
+    let mut heartbeat = Heartbeat::default();
+
+    // Sent once, then edited in place
+    heartbeat.beat("1".to_owned(), post).await.unwrap();
+    heartbeat.beat("2".to_owned(), post).await.unwrap();
+    heartbeat.beat("3".to_owned(), post).await.unwrap();
+    assert_eq!(
+        *posted.lock().unwrap(),
+        [
+            (None, "1".to_owned()),
+            (Some(1), "2".to_owned()),
+            (Some(1), "3".to_owned())
+        ]
+    );
+
+    // Sent again when the message is gone
+    *deleted.lock().unwrap() = true;
+    heartbeat.beat("4".to_owned(), post).await.unwrap();
+    assert_eq!(heartbeat.message_id, Some(5));
+    assert_eq!(
+        posted.lock().unwrap()[3..],
+        [(Some(1), "4".to_owned()), (None, "4".to_owned())]
+    );
+}
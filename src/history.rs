@@ -0,0 +1,183 @@
+use std::{
+    fmt::{self, Display},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use color_eyre::{eyre::Context, Result};
+use humantime_serde::re::humantime;
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::types::{ChatId, UserId};
+
+use crate::{normalize_title, TitleRecord};
+
+/// A user who held a title, stored with the other holders of the same title as
+/// a JSON list under `history$<chat_id>$<normalized title>`, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub user_id: UserId,
+    /// Unix timestamp in seconds
+    pub since: u64,
+}
+
+/// Record the user of `record` as the latest holder of its title, unless they
+/// already are.
+///
+/// # Errors
+/// If the database returns an error or the history is in bad shape.
+pub fn record_holder(db: &Db, record: &TitleRecord, now: u64) -> Result<()> {
+    let mut entries = title_history(db, record.chat_id, &record.title)?;
+    if entries.last().map(|entry| entry.user_id) == Some(record.user_id) {
+        return Ok(());
+    }
+    entries.push(HistoryEntry {
+        user_id: record.user_id,
+        since: now,
+    });
+    db.insert(
+        make_history_key(record.chat_id, &record.title),
+        serde_json::to_vec(&entries)?,
+    )?;
+    Ok(())
+}
+
+/// Users who held the title in the chat, oldest first. Titles are compared
+/// normalized, see [`normalize_title`].
+///
+/// # Errors
+/// If the database returns an error or the history is in bad shape.
+pub fn title_history(db: &Db, chat_id: ChatId, title: &str) -> Result<Vec<HistoryEntry>> {
+    match db.get(make_history_key(chat_id, title))? {
+        Some(value) => serde_json::from_slice(&value).wrap_err("Bad title history"),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Remove the user from the history of every title in the chat, returns how
+/// many entries were removed.
+///
+/// # Errors
+/// If the database returns an error or the history is in bad shape.
+pub fn forget_holder(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<usize> {
+    let mut removed = 0;
+    for item in db.scan_prefix(format!("history${chat_id}$")) {
+        let (key, value) = item?;
+        let mut entries: Vec<HistoryEntry> =
+            serde_json::from_slice(&value).wrap_err("Bad title history")?;
+        let before = entries.len();
+        entries.retain(|entry| entry.user_id != user_id);
+        if entries.len() == before {
+            continue;
+        }
+        removed += before - entries.len();
+        if entries.is_empty() {
+            db.remove(key)?;
+        } else {
+            db.insert(key, serde_json::to_vec(&entries)?)?;
+        }
+    }
+    Ok(removed)
+}
+
+/// A title the user held, normalized, see [`normalize_title`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HeldTitle {
+    pub title: String,
+    /// Unix timestamp in seconds
+    pub since: u64,
+}
+
+/// Titles the user held in the chat, in the order of the history keys.
+///
+/// # Errors
+/// If the database returns an error or the history is in bad shape.
+pub fn titles_held(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Vec<HeldTitle>> {
+    let prefix = format!("history${chat_id}$");
+    let mut held = Vec::new();
+    for item in db.scan_prefix(&prefix) {
+        let (key, value) = item?;
+        let entries: Vec<HistoryEntry> =
+            serde_json::from_slice(&value).wrap_err("Bad title history")?;
+        let title = String::from_utf8(key[prefix.len()..].to_vec())?;
+        held.extend(
+            entries
+                .into_iter()
+                .filter(|entry| entry.user_id == user_id)
+                .map(|entry| HeldTitle {
+                    title: title.clone(),
+                    since: entry.since,
+                }),
+        );
+    }
+    Ok(held)
+}
+
+fn make_history_key(chat_id: ChatId, title: &str) -> IVec {
+    format!("history${}${}", chat_id, normalize_title(title))
+        .into_bytes()
+        .into()
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(self.since));
+        write!(f, "<code>User({})</code> since {since}", self.user_id)
+    }
+}
+
+#[test]
+fn test_title_history() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    let holders = |title| -> Vec<u64> {
+        title_history(&db, ChatId(1), title)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.user_id.0)
+            .collect()
+    };
+
+    // Unknown titles have no history
+    assert!(title_history(&db, ChatId(1), "VIP").unwrap().is_empty());
+
+    // Holders accumulate in order, repeated sets by the holder are ignored
+    record_holder(&db, &record("VIP", 2), 100).unwrap();
+    record_holder(&db, &record("VIP", 2), 150).unwrap();
+    record_holder(&db, &record("vip ", 3), 200).unwrap();
+    record_holder(&db, &record("VIP", 2), 300).unwrap();
+    assert_eq!(holders("  Vip"), [2, 3, 2]);
+    assert_eq!(title_history(&db, ChatId(1), "VIP").unwrap()[1].since, 200);
+    assert!(title_history(&db, ChatId(2), "VIP").unwrap().is_empty());
+    assert_eq!(
+        title_history(&db, ChatId(1), "VIP").unwrap()[0].to_string(),
+        "<code>User(2)</code> since 1970-01-01T00:01:40Z"
+    );
+
+    // Forgotten users are removed from all histories
+    record_holder(&db, &record("Mod", 2), 400).unwrap();
+    assert_eq!(
+        titles_held(&db, ChatId(1), UserId(2)).unwrap(),
+        [
+            HeldTitle {
+                title: "mod".to_owned(),
+                since: 400
+            },
+            HeldTitle {
+                title: "vip".to_owned(),
+                since: 100
+            },
+            HeldTitle {
+                title: "vip".to_owned(),
+                since: 300
+            },
+        ]
+    );
+    assert_eq!(forget_holder(&db, ChatId(1), UserId(2)).unwrap(), 3);
+    assert_eq!(holders("VIP"), [3]);
+    assert!(holders("Mod").is_empty());
+}
@@ -0,0 +1,607 @@
+use std::fmt::{self, Display};
+
+use color_eyre::{eyre::ensure, Result};
+use sled::{Db, IVec};
+use teloxide::{types::ChatId, utils::html};
+
+use crate::{count_removed, normalize_title, TitleRecord};
+
+/// Maximum number of inconsistencies shown in a report.
+const MAX_SAMPLES: usize = 10;
+
+/// Number of entries shown in each page of [`dump_raw_keys`].
+const RAW_KEYS_PER_PAGE: usize = 30;
+
+/// An inconsistency between the `chat$`, `title$` and `titlenorm$` keyspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A `chat$` key whose title is not mapped back to the same user.
+    DanglingChatKey(TitleRecord),
+    /// A `title$` key whose user does not hold the same title.
+    DanglingTitleKey(TitleRecord),
+    /// A `chat$` key whose title has no `titlenorm$` key pointing to a user
+    /// holding it.
+    MissingNormKey(TitleRecord),
+    /// A `titlenorm$` key whose user holds no title normalized to it. The
+    /// title of the record is the normalized one.
+    StaleNormKey(TitleRecord),
+    /// A key that cannot be parsed.
+    BadKey(IVec),
+}
+
+impl Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DanglingChatKey(record) => write!(
+                f,
+                "Dangling chat key: Chat({}) User({}) -> {}",
+                record.chat_id,
+                record.user_id,
+                html::escape(&record.title)
+            ),
+            Self::DanglingTitleKey(record) => write!(
+                f,
+                "Dangling title key: Chat({}) {} -> User({})",
+                record.chat_id,
+                html::escape(&record.title),
+                record.user_id
+            ),
+            Self::MissingNormKey(record) => write!(
+                f,
+                "Missing normalized key: Chat({}) User({}) -> {}",
+                record.chat_id,
+                record.user_id,
+                html::escape(&record.title)
+            ),
+            Self::StaleNormKey(record) => write!(
+                f,
+                "Stale normalized key: Chat({}) {} -> User({})",
+                record.chat_id,
+                html::escape(&record.title),
+                record.user_id
+            ),
+            Self::BadKey(key) => write!(
+                f,
+                "Bad key: {}",
+                html::escape(&String::from_utf8_lossy(key))
+            ),
+        }
+    }
+}
+
+/// Result of [`verify_db`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Number of keys checked
+    pub checked: usize,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl IntegrityReport {
+    #[must_use]
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+}
+
+impl Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checked {} keys, found {} inconsistencies",
+            self.checked,
+            self.inconsistencies.len()
+        )?;
+        for inconsistency in self.inconsistencies.iter().take(MAX_SAMPLES) {
+            write!(f, "\n{inconsistency}")?;
+        }
+        if self.inconsistencies.len() > MAX_SAMPLES {
+            write!(f, "\n...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Cross-check all `chat$`, `title$` and `titlenorm$` keys, making sure each
+/// chat key has a title key pointing back to the same user and vice versa, and
+/// that each title can be found by its normalized key.
+///
+/// Title keys are looked up exactly, as [`TitleRecord::get_with_title`] falls
+/// back to the normalized key and would hide a missing one. Titles differing
+/// only in case or whitespace, which could both be taken before normalized
+/// keys were introduced, share one normalized key, which is not reported.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn verify_db(db: &Db) -> Result<IntegrityReport> {
+    let mut report = IntegrityReport::default();
+
+    for entry in db.scan_prefix("chat$") {
+        let (key, value) = entry?;
+        report.checked += 1;
+        match TitleRecord::parse_chat_key(&key, &value) {
+            Ok(record) => {
+                let holder = exact_holder(db, record.chat_id, &record.title)?;
+                if holder.map(|holder| holder.user_id) != Some(record.user_id) {
+                    report
+                        .inconsistencies
+                        .push(Inconsistency::DanglingChatKey(record));
+                } else if !has_norm_key(db, &record)? {
+                    report
+                        .inconsistencies
+                        .push(Inconsistency::MissingNormKey(record));
+                }
+            }
+            Err(_) => report.inconsistencies.push(Inconsistency::BadKey(key)),
+        }
+    }
+
+    for entry in db.scan_prefix("title$") {
+        let (key, value) = entry?;
+        report.checked += 1;
+        match TitleRecord::parse_title_key(&key, &value) {
+            Ok(record) => {
+                let owned = TitleRecord::get_with_id(db, record.chat_id, record.user_id)
+                    .ok()
+                    .flatten();
+                if owned.map(|owned| owned.title).as_ref() != Some(&record.title) {
+                    report
+                        .inconsistencies
+                        .push(Inconsistency::DanglingTitleKey(record));
+                }
+            }
+            Err(_) => report.inconsistencies.push(Inconsistency::BadKey(key)),
+        }
+    }
+
+    for entry in db.scan_prefix("titlenorm$") {
+        let (key, value) = entry?;
+        report.checked += 1;
+        match TitleRecord::parse_norm_key(&key, &value) {
+            Ok(record) => {
+                if !holds_normalized(db, &record) {
+                    report
+                        .inconsistencies
+                        .push(Inconsistency::StaleNormKey(record));
+                }
+            }
+            Err(_) => report.inconsistencies.push(Inconsistency::BadKey(key)),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Cross-check the keys of a single title in the chat, like [`verify_db`]
+/// does for the whole database. Title key issues come first so that
+/// [`repair_db`] frees the title before restoring it for the chat key holder,
+/// along with its normalized key.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn verify_title(db: &Db, chat_id: ChatId, title: &str) -> Result<Vec<Inconsistency>> {
+    let mut inconsistencies = Vec::new();
+
+    let title_key = TitleRecord::make_title_key(chat_id, title);
+    if let Some(value) = db.get(&title_key)? {
+        match TitleRecord::parse_title_key(&title_key, &value) {
+            Ok(record) => {
+                let owned = TitleRecord::get_with_id(db, chat_id, record.user_id)
+                    .ok()
+                    .flatten();
+                if owned.map(|owned| owned.title).as_ref() != Some(&record.title) {
+                    inconsistencies.push(Inconsistency::DanglingTitleKey(record));
+                }
+            }
+            Err(_) => inconsistencies.push(Inconsistency::BadKey(title_key)),
+        }
+    }
+
+    for entry in db.scan_prefix(format!("chat${chat_id}$")) {
+        let (key, value) = entry?;
+        // Followed by the expiry if any, see `TitleRecord::chat_value`
+        if !value.starts_with(title.as_bytes()) {
+            continue;
+        }
+        match TitleRecord::parse_chat_key(&key, &value) {
+            Ok(record) if record.title != title => {}
+            Ok(record) => {
+                let holder = exact_holder(db, chat_id, title)?;
+                if holder.map(|holder| holder.user_id) != Some(record.user_id) {
+                    inconsistencies.push(Inconsistency::DanglingChatKey(record));
+                } else if !has_norm_key(db, &record)? {
+                    inconsistencies.push(Inconsistency::MissingNormKey(record));
+                }
+            }
+            Err(_) => inconsistencies.push(Inconsistency::BadKey(key)),
+        }
+    }
+
+    let norm_key = TitleRecord::make_norm_key(chat_id, title);
+    if let Some(value) = db.get(&norm_key)? {
+        match TitleRecord::parse_norm_key(&norm_key, &value) {
+            Ok(record) if !holds_normalized(db, &record) => {
+                inconsistencies.push(Inconsistency::StaleNormKey(record));
+            }
+            Ok(_) => {}
+            Err(_) => inconsistencies.push(Inconsistency::BadKey(norm_key)),
+        }
+    }
+
+    Ok(inconsistencies)
+}
+
+/// Holder of the title by its exact title key, without falling back to the
+/// normalized key like [`TitleRecord::get_with_title`].
+///
+/// # Errors
+/// If the database returns an error.
+fn exact_holder(db: &Db, chat_id: ChatId, title: &str) -> Result<Option<TitleRecord>> {
+    let title_key = TitleRecord::make_title_key(chat_id, title);
+    Ok(db
+        .get(&title_key)?
+        .and_then(|value| TitleRecord::parse_title_key(&title_key, &value).ok()))
+}
+
+/// Whether the user of the record holds a title normalized the same as the
+/// title of the record.
+fn holds_normalized(db: &Db, record: &TitleRecord) -> bool {
+    TitleRecord::get_with_id(db, record.chat_id, record.user_id)
+        .ok()
+        .flatten()
+        .map_or(false, |held| {
+            normalize_title(&held.title) == normalize_title(&record.title)
+        })
+}
+
+/// Whether the normalized key of the title points to a user holding it, not
+/// necessarily the user of the record.
+///
+/// # Errors
+/// If the database returns an error.
+fn has_norm_key(db: &Db, record: &TitleRecord) -> Result<bool> {
+    let norm_key = TitleRecord::make_norm_key(record.chat_id, &record.title);
+    Ok(db
+        .get(&norm_key)?
+        .and_then(|value| TitleRecord::parse_norm_key(&norm_key, &value).ok())
+        .map_or(false, |holder| holds_normalized(db, &holder)))
+}
+
+/// Repair inconsistencies found by [`verify_db`] or [`verify_title`].
+///
+/// - A dangling chat key gets its title key restored if the title is free,
+///   otherwise it's removed since the title belongs to someone else, who gets
+///   the normalized key too.
+/// - Dangling title keys and bad keys are removed, along with the normalized
+///   key of a dangling title key if its user holds no such title.
+/// - A missing normalized key is added, and a stale one removed.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn repair_db(db: &Db, inconsistencies: &[Inconsistency]) -> Result<()> {
+    for inconsistency in inconsistencies {
+        match inconsistency {
+            Inconsistency::DanglingChatKey(record) => {
+                let title_key = TitleRecord::make_title_key(record.chat_id, &record.title);
+                let Some(holder) = db.get(&title_key)? else {
+                    record.insert_into(db)?;
+                    continue;
+                };
+                if record.holds_norm_key(db)? {
+                    db.insert(
+                        TitleRecord::make_norm_key(record.chat_id, &record.title),
+                        holder,
+                    )?;
+                }
+                if db
+                    .remove(TitleRecord::make_chat_key(record.chat_id, record.user_id))?
+                    .is_some()
+                {
+                    count_removed(db, 1)?;
+                }
+            }
+            Inconsistency::DanglingTitleKey(record) => {
+                db.remove(TitleRecord::make_title_key(record.chat_id, &record.title))?;
+                if record.holds_norm_key(db)? && !holds_normalized(db, record) {
+                    db.remove(TitleRecord::make_norm_key(record.chat_id, &record.title))?;
+                }
+            }
+            Inconsistency::MissingNormKey(record) => {
+                if !has_norm_key(db, record)? {
+                    db.insert(
+                        TitleRecord::make_norm_key(record.chat_id, &record.title),
+                        &record.user_id.0.to_be_bytes(),
+                    )?;
+                }
+            }
+            Inconsistency::StaleNormKey(record) => {
+                if record.holds_norm_key(db)? && !holds_normalized(db, record) {
+                    db.remove(TitleRecord::make_norm_key(record.chat_id, &record.title))?;
+                }
+            }
+            Inconsistency::BadKey(key) => {
+                if db.remove(key)?.is_some() && key.starts_with(b"chat$") {
+                    count_removed(db, 1)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Dump raw `chat$` and `title$` entries of the chat, one page (starting from
+/// 1) at a time. Keys and values that are not printable UTF-8 are shown in
+/// hex.
+///
+/// # Errors
+/// If the database returns an error or the page is out of range.
+pub fn dump_raw_keys(db: &Db, chat_id: ChatId, page: usize) -> Result<String> {
+    let mut entries = Vec::new();
+    for prefix in [format!("chat${chat_id}$"), format!("title${chat_id}$")] {
+        for entry in db.scan_prefix(prefix) {
+            entries.push(entry?);
+        }
+    }
+    if entries.is_empty() {
+        return Ok(format!("No keys found for Chat({chat_id})"));
+    }
+
+    let pages = (entries.len() + RAW_KEYS_PER_PAGE - 1) / RAW_KEYS_PER_PAGE;
+    ensure!(
+        (1..=pages).contains(&page),
+        "Page out of range (1 to {pages})"
+    );
+
+    let lines = entries
+        .iter()
+        .skip((page - 1) * RAW_KEYS_PER_PAGE)
+        .take(RAW_KEYS_PER_PAGE)
+        .map(|(key, value)| format!("{} = {}", format_raw(key), format_raw(value)))
+        .collect::<Vec<_>>();
+    Ok(format!(
+        "Raw keys of Chat({chat_id}), page {page}/{pages}\n{}",
+        lines.join("\n")
+    ))
+}
+
+/// Format raw bytes as escaped text, or hex if not printable UTF-8.
+fn format_raw(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.chars().any(char::is_control) => {
+            format!("<code>{}</code>", html::escape(text))
+        }
+        _ => {
+            let hex = bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            format!("<code>0x{hex}</code>")
+        }
+    }
+}
+
+#[test]
+fn test_verify_db() {
+    use teloxide::types::{ChatId, UserId};
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let good = TitleRecord {
+        title: "good".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    good.insert_into(&db).unwrap();
+
+    // Chat key without title key
+    db.insert(TitleRecord::make_chat_key(ChatId(1), UserId(3)), "lost")
+        .unwrap();
+    // Title key without chat key
+    db.insert(
+        TitleRecord::make_title_key(ChatId(1), "orphan"),
+        &4_u64.to_be_bytes(),
+    )
+    .unwrap();
+    // Title without normalized key
+    let legacy = TitleRecord {
+        title: "Legacy".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(5),
+        expires_at: None,
+    };
+    legacy.insert_into(&db).unwrap();
+    db.remove(TitleRecord::make_norm_key(ChatId(1), "Legacy"))
+        .unwrap();
+    // Normalized key without title
+    db.insert(
+        TitleRecord::make_norm_key(ChatId(1), "gone"),
+        &6_u64.to_be_bytes(),
+    )
+    .unwrap();
+    // Chat and normalized keys without the exact title key
+    let unkeyed = TitleRecord {
+        title: "Unkeyed".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(7),
+        expires_at: None,
+    };
+    unkeyed.insert_into(&db).unwrap();
+    db.remove(TitleRecord::make_title_key(ChatId(1), "Unkeyed"))
+        .unwrap();
+
+    let report = verify_db(&db).unwrap();
+    assert_eq!(report.checked, 10);
+    assert_eq!(
+        report.inconsistencies,
+        vec![
+            Inconsistency::DanglingChatKey(TitleRecord {
+                title: "lost".into(),
+                chat_id: ChatId(1),
+                user_id: UserId(3),
+                expires_at: None,
+            }),
+            Inconsistency::MissingNormKey(legacy.clone()),
+            Inconsistency::DanglingChatKey(unkeyed.clone()),
+            Inconsistency::DanglingTitleKey(TitleRecord {
+                title: "orphan".into(),
+                chat_id: ChatId(1),
+                user_id: UserId(4),
+                expires_at: None,
+            }),
+            Inconsistency::StaleNormKey(TitleRecord {
+                title: "gone".into(),
+                chat_id: ChatId(1),
+                user_id: UserId(6),
+                expires_at: None,
+            }),
+        ]
+    );
+    assert!(report
+        .to_string()
+        .starts_with("Checked 10 keys, found 5 inconsistencies"));
+
+    repair_db(&db, &report.inconsistencies).unwrap();
+
+    let report = verify_db(&db).unwrap();
+    assert!(report.is_consistent());
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "lost")
+            .unwrap()
+            .map(|record| record.user_id),
+        Some(UserId(3))
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "orphan").unwrap(),
+        None
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "legacy").unwrap(),
+        Some(legacy)
+    );
+    assert!(db
+        .contains_key(TitleRecord::make_title_key(ChatId(1), "Unkeyed"))
+        .unwrap());
+    assert!(!db
+        .contains_key(TitleRecord::make_norm_key(ChatId(1), "gone"))
+        .unwrap());
+}
+
+#[test]
+fn test_verify_title() {
+    use teloxide::types::UserId;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    TitleRecord {
+        title: "good".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+    assert!(verify_title(&db, ChatId(1), "good").unwrap().is_empty());
+    assert!(verify_title(&db, ChatId(1), "unknown").unwrap().is_empty());
+
+    // User 3 holds the title but the title and normalized keys point to user
+    // 4, who holds nothing
+    db.insert(TitleRecord::make_chat_key(ChatId(1), UserId(3)), "VIP")
+        .unwrap();
+    db.insert(
+        TitleRecord::make_title_key(ChatId(1), "VIP"),
+        &4_u64.to_be_bytes(),
+    )
+    .unwrap();
+    db.insert(
+        TitleRecord::make_norm_key(ChatId(1), "VIP"),
+        &4_u64.to_be_bytes(),
+    )
+    .unwrap();
+    let record = |user_id| TitleRecord {
+        title: "VIP".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    let found = verify_title(&db, ChatId(1), "VIP").unwrap();
+    assert_eq!(
+        found,
+        [
+            Inconsistency::DanglingTitleKey(record(4)),
+            Inconsistency::DanglingChatKey(record(3)),
+            Inconsistency::StaleNormKey(TitleRecord {
+                title: "vip".into(),
+                ..record(4)
+            }),
+        ]
+    );
+    // Other titles are left alone
+    assert!(verify_title(&db, ChatId(1), "good").unwrap().is_empty());
+
+    repair_db(&db, &found).unwrap();
+
+    assert!(verify_title(&db, ChatId(1), "VIP").unwrap().is_empty());
+    assert!(verify_db(&db).unwrap().is_consistent());
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "VIP").unwrap(),
+        Some(record(3))
+    );
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "vip")
+            .unwrap()
+            .map(|record| record.user_id),
+        Some(UserId(3))
+    );
+}
+
+#[test]
+fn test_dump_raw_keys() {
+    use teloxide::types::UserId;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    TitleRecord {
+        title: "a$<b>".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+    // Belongs to another chat sharing the same prefix
+    TitleRecord {
+        title: "other".into(),
+        chat_id: ChatId(12),
+        user_id: UserId(3),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+
+    assert_eq!(
+        dump_raw_keys(&db, ChatId(1), 1).unwrap(),
+        "Raw keys of Chat(1), page 1/1\n<code>chat$1$2</code> = \
+         <code>a$&lt;b&gt;</code>\n<code>title$1$a$&lt;b&gt;</code> = \
+         <code>0x0000000000000002</code>"
+    );
+    assert!(dump_raw_keys(&db, ChatId(1), 2).is_err());
+    assert_eq!(
+        dump_raw_keys(&db, ChatId(5), 1).unwrap(),
+        "No keys found for Chat(5)"
+    );
+
+    for user in 100..=RAW_KEYS_PER_PAGE as u64 + 100 {
+        TitleRecord {
+            title: user.to_string(),
+            chat_id: ChatId(1),
+            user_id: UserId(user),
+            expires_at: None,
+        }
+        .insert_into(&db)
+        .unwrap();
+    }
+    assert!(dump_raw_keys(&db, ChatId(1), 3)
+        .unwrap()
+        .starts_with("Raw keys of Chat(1), page 3/3"));
+}
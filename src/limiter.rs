@@ -0,0 +1,58 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Tracks a cooldown period for each key, e.g. to post a message at most once
+/// per period in each chat.
+#[derive(Debug)]
+pub struct Cooldown<K> {
+    period: Duration,
+    last: Mutex<HashMap<K, Instant>>,
+}
+
+impl<K: Eq + Hash> Cooldown<K> {
+    #[must_use]
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start a new cooldown for the key and return `true`, or return `false`
+    /// if the key is still cooling down.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn try_start(&self, key: K) -> bool {
+        self.try_start_at(key, Instant::now())
+    }
+
+    fn try_start_at(&self, key: K, now: Instant) -> bool {
+        let mut last = self.last.lock().unwrap();
+        match last.get(&key) {
+            Some(&started) if now.saturating_duration_since(started) < self.period => false,
+            _ => {
+                last.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cooldown() {
+    use teloxide::types::ChatId;
+
+    let cooldown = Cooldown::new(Duration::from_secs(60));
+    let now = Instant::now();
+
+    assert!(cooldown.try_start_at(ChatId(1), now));
+    assert!(!cooldown.try_start_at(ChatId(1), now + Duration::from_secs(1)));
+    assert!(!cooldown.try_start_at(ChatId(1), now + Duration::from_secs(59)));
+    assert!(cooldown.try_start_at(ChatId(2), now));
+    assert!(cooldown.try_start_at(ChatId(1), now + Duration::from_secs(60)));
+}
@@ -7,7 +7,42 @@
 #![warn(clippy::nursery)]
 #![warn(clippy::all)]
 
-mod_use![bot, debug_chat, ctx, config, server];
+mod_use![
+    bot,
+    cache,
+    capacity,
+    debounce,
+    debug_chat,
+    demotion,
+    ctx,
+    diagnose,
+    expiry,
+    config,
+    heartbeat,
+    history,
+    integrity,
+    limiter,
+    pending,
+    privilege,
+    protection,
+    query,
+    rank,
+    replica,
+    reservation,
+    resync,
+    roster,
+    schedule,
+    server,
+    settings,
+    signature,
+    snapshot,
+    statsd,
+    style,
+    temp_anon,
+    transfer,
+    trash,
+    webhook
+];
 
 use std::{sync::OnceLock, time::Duration};
 
@@ -58,11 +93,28 @@ async fn main() -> Result<()> {
     BOT.set(bot.clone()).unwrap();
 
     let db = sled::open(&conf.db_path).unwrap();
+    // The stored count may drift when removed outside of `TitleRecord`
+    capacity::recount_titles(&db)?;
+    let backfilled = TitleRecord::backfill_norm_keys(&db)?;
+    if backfilled > 0 {
+        info!(
+            backfilled,
+            "Added normalized keys of titles stored before them"
+        );
+    }
 
     debug_chat::init();
+    webhook::init(db.clone());
+    statsd::init_statsd();
 
     select! {
-        _ = server::run() => {},
+        _ = server::run(db.clone()) => {},
+        _ = trash::run_purge(db.clone()) => {},
+        _ = temp_anon::run_temp_anon(db.clone()) => {},
+        _ = schedule::run_schedules(db.clone()) => {},
+        _ = expiry::run_expiry(db.clone()) => {},
+        _ = replica::run_replica(db.clone()) => {},
+        _ = heartbeat::run_heartbeat() => {},
         _ = bot::run(bot, db) => {},
         _ = tokio::signal::ctrl_c() => {}
     }
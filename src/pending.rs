@@ -0,0 +1,100 @@
+use color_eyre::Result;
+use sled::{Batch, Db, IVec};
+use teloxide::types::{ChatId, UserId};
+
+use crate::{count_removed, TitleRecord};
+
+/// Record that the titles of `user_ids` in the chat are about to be removed,
+/// stored as JSON under `pending$<chat_id>`. Requests to Telegram cannot be
+/// rolled back, so the intent is recorded before them and cleared by
+/// [`finish_removal`] along with the records.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn begin_removal(db: &Db, chat_id: ChatId, user_ids: &[UserId]) -> Result<()> {
+    db.insert(make_key(chat_id), serde_json::to_vec(user_ids)?)?;
+    Ok(())
+}
+
+/// Users of a removal that was begun but never finished in the chat, e.g.
+/// interrupted by a restart.
+///
+/// # Errors
+/// If the database returns an error or the entry is malformed.
+pub fn interrupted_removal(db: &Db, chat_id: ChatId) -> Result<Vec<UserId>> {
+    Ok(match db.get(make_key(chat_id))? {
+        Some(value) => serde_json::from_slice(&value)?,
+        None => Vec::new(),
+    })
+}
+
+/// Remove the titles of `user_ids` in the chat and the pending removal in a
+/// single batch, so either all or none of them are removed. Returns how many
+/// titles were removed.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn finish_removal(db: &Db, chat_id: ChatId, user_ids: &[UserId]) -> Result<usize> {
+    let mut batch = Batch::default();
+    let mut removed = 0;
+    for &user_id in user_ids {
+        if let Some(record) = TitleRecord::get_with_id(db, chat_id, user_id)? {
+            if record.holds_norm_key(db)? {
+                batch.remove(TitleRecord::make_norm_key(chat_id, &record.title));
+            }
+            batch.remove(TitleRecord::make_title_key(chat_id, &record.title));
+            batch.remove(TitleRecord::make_chat_key(chat_id, user_id));
+            removed += 1;
+        }
+    }
+    batch.remove(make_key(chat_id));
+    db.apply_batch(batch)?;
+    count_removed(db, removed)?;
+    Ok(removed)
+}
+
+fn make_key(chat_id: ChatId) -> IVec {
+    format!("pending${}", chat_id).into_bytes().into()
+}
+
+#[test]
+fn test_removal() {
+    use crate::verify_db;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |user_id| TitleRecord {
+        title: format!("Admin {user_id}"),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    for user_id in 1..=3 {
+        record(user_id).insert_into(&db).unwrap();
+    }
+
+    // Interrupted before finishing, nothing is removed and the intent is kept
+    begin_removal(&db, ChatId(1), &[UserId(1), UserId(2)]).unwrap();
+    assert_eq!(db.scan_prefix("chat$").count(), 3);
+    assert!(verify_db(&db).unwrap().is_consistent());
+    assert_eq!(
+        interrupted_removal(&db, ChatId(1)).unwrap(),
+        [UserId(1), UserId(2)]
+    );
+    assert!(interrupted_removal(&db, ChatId(2)).unwrap().is_empty());
+
+    // Finished, all removed at once along with the intent
+    assert_eq!(
+        finish_removal(&db, ChatId(1), &[UserId(1), UserId(2), UserId(4)]).unwrap(),
+        2
+    );
+    assert!(verify_db(&db).unwrap().is_consistent());
+    assert!(interrupted_removal(&db, ChatId(1)).unwrap().is_empty());
+    assert!(TitleRecord::get_with_title(&db, ChatId(1), "Admin 1")
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(3)).unwrap(),
+        Some(record(3))
+    );
+    assert_eq!(db.scan_prefix("chat$").count(), 1);
+}
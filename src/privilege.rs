@@ -0,0 +1,277 @@
+use color_eyre::{eyre::ensure, Result};
+use serde::{Deserialize, Serialize};
+use teloxide::{payloads::PromoteChatMemberSetters, types::ChatMemberKind};
+use tracing::warn;
+
+/// Administrator rights that can be granted when promoting a member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Privilege {
+    ManageChat,
+    ChangeInfo,
+    DeleteMessages,
+    ManageVideoChats,
+    RestrictMembers,
+    InviteUsers,
+    PinMessages,
+    PromoteMembers,
+}
+
+impl Privilege {
+    /// All privileges, in the order of the Bot API.
+    pub const ALL: [Self; 8] = [
+        Self::ManageChat,
+        Self::ChangeInfo,
+        Self::DeleteMessages,
+        Self::ManageVideoChats,
+        Self::RestrictMembers,
+        Self::InviteUsers,
+        Self::PinMessages,
+        Self::PromoteMembers,
+    ];
+
+    /// Privilege with given name, see [`Privilege::as_str`].
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|privilege| privilege.as_str() == name)
+    }
+
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::ManageChat => "manage_chat",
+            Self::ChangeInfo => "change_info",
+            Self::DeleteMessages => "delete_messages",
+            Self::ManageVideoChats => "manage_video_chats",
+            Self::RestrictMembers => "restrict_members",
+            Self::InviteUsers => "invite_users",
+            Self::PinMessages => "pin_messages",
+            Self::PromoteMembers => "promote_members",
+        }
+    }
+
+    /// Whether the member with given kind holds this privilege.
+    #[must_use]
+    pub fn held_by(self, kind: &ChatMemberKind) -> bool {
+        match self {
+            Self::ManageChat => kind.can_manage_chat(),
+            Self::ChangeInfo => kind.can_change_info(),
+            Self::DeleteMessages => kind.can_delete_messages(),
+            Self::ManageVideoChats => kind.can_manage_video_chats(),
+            Self::RestrictMembers => kind.can_restrict_members(),
+            Self::InviteUsers => kind.can_invite_users(),
+            Self::PinMessages => kind.can_pin_messages(),
+            Self::PromoteMembers => kind.can_promote_members(),
+        }
+    }
+
+    /// Grant this privilege on a `promote_chat_member` request.
+    #[must_use]
+    pub fn grant<R: PromoteChatMemberSetters>(self, req: R) -> R {
+        match self {
+            Self::ManageChat => req.can_manage_chat(true),
+            Self::ChangeInfo => req.can_change_info(true),
+            Self::DeleteMessages => req.can_delete_messages(true),
+            Self::ManageVideoChats => req.can_manage_video_chats(true),
+            Self::RestrictMembers => req.can_restrict_members(true),
+            Self::InviteUsers => req.can_invite_users(true),
+            Self::PinMessages => req.can_pin_messages(true),
+            Self::PromoteMembers => req.can_promote_members(true),
+        }
+    }
+}
+
+/// Grant all the privileges on a `promote_chat_member` request.
+#[must_use]
+pub fn grant_all<R: PromoteChatMemberSetters>(req: R, privileges: &[Privilege]) -> R {
+    privileges
+        .iter()
+        .fold(req, |req, privilege| privilege.grant(req))
+}
+
+/// Privileges held by the member with given kind, in the order of
+/// [`Privilege::ALL`].
+#[must_use]
+pub fn held_privileges(kind: &ChatMemberKind) -> Vec<Privilege> {
+    Privilege::ALL
+        .into_iter()
+        .filter(|privilege| privilege.held_by(kind))
+        .collect()
+}
+
+/// Filter out privileges that the bot (with kind `me`) does not hold, since
+/// Telegram refuses to grant rights the promoter lacks. Skipped ones are
+/// warned.
+#[must_use]
+pub fn grantable_privileges(privileges: &[Privilege], me: &ChatMemberKind) -> Vec<Privilege> {
+    privileges
+        .iter()
+        .copied()
+        .filter(|privilege| {
+            let held = privilege.held_by(me);
+            if !held {
+                warn!(
+                    privilege = privilege.as_str(),
+                    "Bot does not hold the privilege, skipped for promotion"
+                );
+            }
+            held
+        })
+        .collect()
+}
+
+/// Privileges the bot needs to manage titles, plus the ones it's configured to
+/// grant on promotion.
+#[must_use]
+pub fn required_privileges(promote_privileges: &[Privilege]) -> Vec<Privilege> {
+    let mut required = vec![Privilege::PromoteMembers, Privilege::InviteUsers];
+    for privilege in promote_privileges {
+        if !required.contains(privilege) {
+            required.push(*privilege);
+        }
+    }
+    required
+}
+
+/// Privileges in `required` that the bot (with kind `me`) does not hold.
+#[must_use]
+pub fn missing_privileges(required: &[Privilege], me: &ChatMemberKind) -> Vec<Privilege> {
+    required
+        .iter()
+        .copied()
+        .filter(|privilege| !privilege.held_by(me))
+        .collect()
+}
+
+/// Ensure the bot (with kind `me`) holds all `required` privileges, naming the
+/// missing ones otherwise.
+///
+/// # Errors
+/// If any privilege is missing.
+pub fn assert_privileges(required: &[Privilege], me: &ChatMemberKind) -> Result<()> {
+    let missing = missing_privileges(required, me);
+    ensure!(
+        missing.is_empty(),
+        "I need these privileges to do this: {}",
+        format_privileges(&missing)
+    );
+    Ok(())
+}
+
+/// Format privileges as a comma separated list of code spans.
+#[must_use]
+pub fn format_privileges(privileges: &[Privilege]) -> String {
+    privileges
+        .iter()
+        .map(|privilege| format!("<code>{}</code>", privilege.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[test]
+fn test_grantable_privileges() {
+    let me: teloxide::types::ChatMember = serde_json::from_str(
+        r#"{
+            "user": { "id": 1, "is_bot": true, "first_name": "Golden Axe" },
+            "status": "administrator",
+            "can_be_edited": false,
+            "is_anonymous": false,
+            "can_manage_chat": true,
+            "can_change_info": false,
+            "can_delete_messages": false,
+            "can_manage_video_chats": false,
+            "can_invite_users": true,
+            "can_restrict_members": false,
+            "can_pin_messages": true,
+            "can_promote_members": true
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        grantable_privileges(&[Privilege::InviteUsers], &me.kind),
+        vec![Privilege::InviteUsers]
+    );
+    assert_eq!(
+        grantable_privileges(
+            &[
+                Privilege::InviteUsers,
+                Privilege::ChangeInfo,
+                Privilege::PinMessages
+            ],
+            &me.kind
+        ),
+        vec![Privilege::InviteUsers, Privilege::PinMessages]
+    );
+}
+
+#[test]
+fn test_missing_privileges() {
+    let me: teloxide::types::ChatMember = serde_json::from_str(
+        r#"{
+            "user": { "id": 1, "is_bot": true, "first_name": "Golden Axe" },
+            "status": "administrator",
+            "can_be_edited": false,
+            "is_anonymous": false,
+            "can_manage_chat": true,
+            "can_change_info": false,
+            "can_delete_messages": false,
+            "can_manage_video_chats": false,
+            "can_invite_users": true,
+            "can_restrict_members": false,
+            "can_pin_messages": false,
+            "can_promote_members": false
+        }"#,
+    )
+    .unwrap();
+
+    let required = required_privileges(&[Privilege::InviteUsers, Privilege::PinMessages]);
+    assert_eq!(
+        required,
+        vec![
+            Privilege::PromoteMembers,
+            Privilege::InviteUsers,
+            Privilege::PinMessages
+        ]
+    );
+    assert_eq!(
+        missing_privileges(&required, &me.kind),
+        vec![Privilege::PromoteMembers, Privilege::PinMessages]
+    );
+
+    // Stopped early with the precise missing privileges
+    assert!(assert_privileges(&[Privilege::InviteUsers], &me.kind).is_ok());
+    assert_eq!(
+        assert_privileges(&required, &me.kind)
+            .unwrap_err()
+            .to_string(),
+        "I need these privileges to do this: <code>promote_members</code>, \
+         <code>pin_messages</code>"
+    );
+}
+
+#[test]
+fn test_grant_all() {
+    use teloxide::{
+        payloads::PromoteChatMember,
+        types::{ChatId, UserId},
+    };
+
+    let req = || PromoteChatMember::new(ChatId(1), UserId(2));
+
+    let with_invite = grant_all(req(), &[Privilege::InviteUsers, Privilege::PinMessages]);
+    assert_eq!(with_invite.can_invite_users, Some(true));
+    assert_eq!(with_invite.can_pin_messages, Some(true));
+
+    let without_invite = grant_all(req(), &[Privilege::PinMessages]);
+    assert_eq!(without_invite.can_invite_users, None);
+    assert_eq!(without_invite.can_pin_messages, Some(true));
+
+    assert_eq!(
+        Privilege::parse("invite_users"),
+        Some(Privilege::InviteUsers)
+    );
+    assert_eq!(Privilege::parse("invite"), None);
+}
@@ -0,0 +1,112 @@
+use color_eyre::{
+    eyre::{ensure, Context},
+    Result,
+};
+use sled::{Db, IVec};
+use teloxide::types::{ChatId, UserId};
+
+use crate::TitleRecord;
+
+/// Mark the title as protected or not. Protected titles can only be removed by
+/// the owner, while holders of other titles can remove their own one.
+///
+/// Stored under `protected$<chat_id>$<title>` with empty value.
+///
+/// # Errors
+/// If the insertion or removal fails.
+pub fn set_protected(db: &Db, chat_id: ChatId, title: &str, protected: bool) -> Result<()> {
+    ensure!(!title.is_empty(), "Title cannot be empty");
+    let key = make_protected_key(chat_id, title);
+    if protected {
+        db.insert(key, &[])?;
+    } else {
+        db.remove(key)?;
+    }
+    Ok(())
+}
+
+/// Whether the title is protected in the chat.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn is_protected(db: &Db, chat_id: ChatId, title: &str) -> Result<bool> {
+    Ok(db.contains_key(make_protected_key(chat_id, title))?)
+}
+
+/// List all protected titles in the chat.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn list_protected(db: &Db, chat_id: ChatId) -> Result<Vec<String>> {
+    let prefix = format!("protected${chat_id}$");
+    db.scan_prefix(&prefix)
+        .map(|x| -> Result<_> {
+            let (key, _) = x.wrap_err("Failed to scan database")?;
+            Ok(String::from_utf8(key[prefix.len()..].to_vec())?)
+        })
+        .try_collect()
+}
+
+/// Ensure the record can be removed by `remover`. The owner can remove any
+/// title, others can only remove their own title if it's not protected.
+///
+/// # Errors
+/// If the removal is not allowed, or the database returns an error.
+pub fn check_removal(db: &Db, record: &TitleRecord, remover: UserId, is_owner: bool) -> Result<()> {
+    if is_owner {
+        return Ok(());
+    }
+    ensure!(
+        record.user_id == remover,
+        "You can only remove your own title"
+    );
+    ensure!(
+        !is_protected(db, record.chat_id, &record.title)?,
+        "Title is protected, only the owner can remove it"
+    );
+    Ok(())
+}
+
+fn make_protected_key(chat_id: ChatId, title: &str) -> IVec {
+    format!("protected${}${}", chat_id, title)
+        .into_bytes()
+        .into()
+}
+
+#[test]
+fn test_check_removal() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let casual = TitleRecord {
+        title: "Lurker".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    let staff = TitleRecord {
+        title: "Staff".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+        expires_at: None,
+    };
+    set_protected(&db, ChatId(1), "Staff", true).unwrap();
+    assert!(is_protected(&db, ChatId(1), "Staff").unwrap());
+    assert!(!is_protected(&db, ChatId(3), "Staff").unwrap());
+
+    // Holder can clear the casual title but not the protected one
+    assert!(check_removal(&db, &casual, UserId(2), false).is_ok());
+    assert_eq!(
+        check_removal(&db, &staff, UserId(2), false)
+            .unwrap_err()
+            .to_string(),
+        "Title is protected, only the owner can remove it"
+    );
+    // Others cannot remove either
+    assert!(check_removal(&db, &casual, UserId(4), false).is_err());
+    // Owner can remove both
+    assert!(check_removal(&db, &casual, UserId(4), true).is_ok());
+    assert!(check_removal(&db, &staff, UserId(4), true).is_ok());
+
+    set_protected(&db, ChatId(1), "Staff", false).unwrap();
+    assert!(check_removal(&db, &staff, UserId(2), false).is_ok());
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use color_eyre::{eyre::bail, Result};
+use teloxide::{
+    types::{ChatId, UserId},
+    utils::html,
+};
+
+use crate::{TitleRecord, MAX_MESSAGE_LEN};
+
+/// Titles longer than this are truncated in `/titles`, in characters.
+const MAX_LISTED_TITLE_LEN: usize = 32;
+
+/// Maximum length of the lines in a page of `/titles`, leaving room for the
+/// header and footer.
+const MAX_PAGE_LEN: usize = MAX_MESSAGE_LEN - 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Title,
+    User,
+}
+
+/// Options of `/titles`, e.g. `/titles sort=title filter=vip page=2`. Records
+/// are kept in scan order unless sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TitleQuery {
+    pub sort: Option<SortBy>,
+    /// Case-insensitive substring the title must contain
+    pub filter: Option<String>,
+    /// Page to show, starting from 1
+    pub page: Option<usize>,
+    /// Whether to show the member status of each holder
+    pub show_status: bool,
+}
+
+impl TitleQuery {
+    /// Parse `key=value` options separated by whitespace.
+    ///
+    /// # Errors
+    /// If any option is unknown or malformed.
+    pub fn parse(args: &str) -> Result<Self> {
+        let mut query = Self::default();
+        for option in args.split_whitespace() {
+            match option.split_once('=') {
+                Some(("sort", "title")) => query.sort = Some(SortBy::Title),
+                Some(("sort", "user")) => query.sort = Some(SortBy::User),
+                Some(("filter", filter)) if !filter.is_empty() => {
+                    query.filter = Some(filter.to_lowercase());
+                }
+                Some(("page", page)) => match page.parse() {
+                    Ok(page) if page > 0 => query.page = Some(page),
+                    _ => bail!("Bad page number `{}`", html::escape(page)),
+                },
+                Some(("show", "status")) => query.show_status = true,
+                _ => bail!(
+                    "Unknown option `{}`, expect sort=title|user, filter=&lt;text&gt;, \
+                     page=&lt;n&gt; or show=status",
+                    html::escape(option)
+                ),
+            }
+        }
+        Ok(query)
+    }
+
+    /// Filter and sort the records.
+    #[must_use]
+    pub fn apply(&self, mut records: Vec<TitleRecord>) -> Vec<TitleRecord> {
+        if let Some(filter) = &self.filter {
+            records.retain(|record| record.title.to_lowercase().contains(filter.as_str()));
+        }
+        match self.sort {
+            Some(SortBy::Title) => records.sort_by(|a, b| a.title.cmp(&b.title)),
+            Some(SortBy::User) => records.sort_by_key(|record| record.user_id.0),
+            None => {}
+        }
+        records
+    }
+}
+
+/// Render the records of the chat as the given page of `/titles`. Long titles
+/// are truncated, and records are split into pages that fit in a message.
+/// Holders found in `statuses` are shown with their status.
+///
+/// # Errors
+/// If the page is out of range.
+pub fn render_titles(
+    chat_id: ChatId,
+    records: &[TitleRecord],
+    statuses: &HashMap<UserId, &str>,
+    page: usize,
+) -> Result<String> {
+    let pages = paginate(records, statuses);
+    Ok(match pages.get(page.saturating_sub(1)) {
+        _ if pages.is_empty() => "No titles found.".to_owned(),
+        Some(lines) if pages.len() == 1 => format!("<code>in Chat({chat_id}):</code>\n{lines}"),
+        Some(lines) => format!(
+            "<code>in Chat({chat_id}), page {page}/{}:</code>\n{lines}\n\nUse /titles \
+             page=&lt;n&gt; to see other pages",
+            pages.len()
+        ),
+        None => bail!("Page out of range (1 to {})", pages.len()),
+    })
+}
+
+/// Append the notice to a listing of `/titles` when only the first `cap`
+/// records were scanned.
+pub fn add_cap_notice(listing: &mut String, cap: usize) {
+    listing.push_str(&format!("\n(showing first {cap} of many)"));
+}
+
+/// Split the records into pages of lines, each no longer than
+/// [`MAX_PAGE_LEN`].
+fn paginate(records: &[TitleRecord], statuses: &HashMap<UserId, &str>) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    let mut len = 0;
+    for record in records {
+        let mut line = format!(
+            "<code>{}: User({})</code>",
+            html::escape(&truncate(&record.title, MAX_LISTED_TITLE_LEN)),
+            record.user_id
+        );
+        if let Some(status) = statuses.get(&record.user_id) {
+            line.push_str(&format!(" ({status})"));
+        }
+        let line_len = line.chars().count();
+        if len > 0 && len + 1 + line_len > MAX_PAGE_LEN {
+            pages.push(std::mem::take(&mut page));
+            len = 0;
+        }
+        if len > 0 {
+            page.push('\n');
+            len += 1;
+        }
+        page.push_str(&line);
+        len += line_len;
+    }
+    if len > 0 {
+        pages.push(page);
+    }
+    pages
+}
+
+/// Truncate the text to at most `max` characters, ending with an ellipsis if
+/// truncated. Never splits a character.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_owned()
+    } else {
+        text.chars()
+            .take(max - 1)
+            .chain(std::iter::once('…'))
+            .collect()
+    }
+}
+
+#[test]
+fn test_title_query() {
+    let record = |user_id, title: &str| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    let records = vec![record(3, "VIP b"), record(1, "Member"), record(2, "vip a")];
+
+    assert_eq!(
+        TitleQuery::parse("").unwrap().apply(records.clone()),
+        records
+    );
+    assert_eq!(
+        TitleQuery::parse("sort=title")
+            .unwrap()
+            .apply(records.clone()),
+        vec![record(1, "Member"), record(3, "VIP b"), record(2, "vip a")]
+    );
+    assert_eq!(
+        TitleQuery::parse("sort=user")
+            .unwrap()
+            .apply(records.clone()),
+        vec![record(1, "Member"), record(2, "vip a"), record(3, "VIP b")]
+    );
+    assert_eq!(
+        TitleQuery::parse("filter=VIP sort=user")
+            .unwrap()
+            .apply(records),
+        vec![record(2, "vip a"), record(3, "VIP b")]
+    );
+    assert!(TitleQuery::parse("sort=age").is_err());
+    assert_eq!(TitleQuery::parse("page=2").unwrap().page, Some(2));
+    assert!(TitleQuery::parse("page=0").is_err());
+    assert!(TitleQuery::parse("show=status").unwrap().show_status);
+}
+
+#[test]
+fn test_render_titles() {
+    let none = HashMap::new();
+    let record = |user_id, title: String| TitleRecord {
+        title,
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+
+    // Truncated on character boundary, then escaped
+    let long = record(1, format!("<{}", "字".repeat(100)));
+    assert_eq!(
+        render_titles(ChatId(1), &[long], &none, 1).unwrap(),
+        format!(
+            "<code>in Chat(1):</code>\n<code>&lt;{}…: User(1)</code>",
+            "字".repeat(MAX_LISTED_TITLE_LEN - 2)
+        )
+    );
+    assert_eq!(
+        render_titles(ChatId(1), &[], &none, 1).unwrap(),
+        "No titles found."
+    );
+
+    // Too many records are split into pages
+    let records = (0..200)
+        .map(|user_id| record(user_id, "a".repeat(MAX_LISTED_TITLE_LEN)))
+        .collect::<Vec<_>>();
+    let pages = paginate(&records, &none);
+    assert!(pages.len() > 1);
+    assert!(pages
+        .iter()
+        .all(|page| page.chars().count() <= MAX_PAGE_LEN));
+    assert_eq!(pages.concat().matches("<code>").count(), 200);
+    assert!(render_titles(ChatId(1), &records, &none, 2)
+        .unwrap()
+        .starts_with(&format!("<code>in Chat(1), page 2/{}:</code>", pages.len())));
+    assert!(render_titles(ChatId(1), &records, &none, pages.len() + 1).is_err());
+
+    // Statuses are shown when known
+    let statuses = HashMap::from([(UserId(1), "left")]);
+    assert_eq!(
+        render_titles(ChatId(1), &records[1..3], &statuses, 1).unwrap(),
+        format!(
+            "<code>in Chat(1):</code>\n<code>{0}: User(1)</code> (left)\n<code>{0}: User(2)</code>",
+            "a".repeat(MAX_LISTED_TITLE_LEN)
+        )
+    );
+}
+
+#[test]
+fn test_cap_notice() {
+    let record = |user_id| TitleRecord {
+        title: "VIP".to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    let records = [record(1), record(2)];
+    let mut show = render_titles(ChatId(1), &records, &HashMap::new(), 1).unwrap();
+    add_cap_notice(&mut show, 2);
+    assert_eq!(
+        show,
+        "<code>in Chat(1):</code>\n<code>VIP: User(1)</code>\n<code>VIP: User(2)</code>\n(showing \
+         first 2 of many)"
+    );
+}
@@ -0,0 +1,103 @@
+use color_eyre::{eyre::Context, Result};
+use sled::{Db, IVec};
+use teloxide::types::ChatId;
+
+/// Placeholder in titles that is filled with a per-chat auto-incremented
+/// number, e.g. `#{rank} Supporter`.
+pub const RANK_PLACEHOLDER: &str = "{rank}";
+
+/// Fill [`RANK_PLACEHOLDER`] in the title with the next rank of the chat,
+/// returning the rank used, if any. Titles without the placeholder are
+/// returned as is.
+///
+/// The counter is not touched, so titles rejected after expansion don't use up
+/// ranks. Call [`commit_rank`] once the title is claimed.
+///
+/// # Errors
+/// When the counter cannot be read or is malformed.
+pub fn expand_rank(db: &Db, chat_id: ChatId, title: &str) -> Result<(String, Option<u64>)> {
+    if !title.contains(RANK_PLACEHOLDER) {
+        return Ok((title.to_owned(), None));
+    }
+    let rank = parse_rank(db.get(make_key(chat_id))?.as_deref())? + 1;
+    Ok((
+        title.replace(RANK_PLACEHOLDER, &rank.to_string()),
+        Some(rank),
+    ))
+}
+
+/// Advance the rank counter of the chat to the rank of a claimed title. The
+/// counter never goes back, e.g. when a concurrent claim committed a higher
+/// rank first.
+///
+/// # Errors
+/// When the counter cannot be updated or is malformed.
+pub fn commit_rank(db: &Db, chat_id: ChatId, rank: u64) -> Result<()> {
+    let key = make_key(chat_id);
+    loop {
+        let old = db.get(&key)?;
+        if parse_rank(old.as_deref())? >= rank {
+            return Ok(());
+        }
+        if db
+            .compare_and_swap(&key, old, Some(rank.to_be_bytes().to_vec()))?
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Parse the stored counter, 0 when there's none yet.
+fn parse_rank(value: Option<&[u8]>) -> Result<u64> {
+    value.map_or(Ok(0), |value| {
+        Ok(u64::from_be_bytes(
+            value.try_into().wrap_err("Bad rank counter")?,
+        ))
+    })
+}
+
+fn make_key(chat_id: ChatId) -> IVec {
+    format!("rank${}", chat_id).into_bytes().into()
+}
+
+#[test]
+fn test_expand_rank() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    assert_eq!(
+        expand_rank(&db, ChatId(1), "Supporter").unwrap(),
+        ("Supporter".to_owned(), None)
+    );
+    assert_eq!(
+        expand_rank(&db, ChatId(1), "#{rank} Supporter").unwrap(),
+        ("#1 Supporter".to_owned(), Some(1))
+    );
+    // Not used up until committed
+    assert_eq!(
+        expand_rank(&db, ChatId(1), "#{rank} Supporter").unwrap(),
+        ("#1 Supporter".to_owned(), Some(1))
+    );
+    commit_rank(&db, ChatId(1), 1).unwrap();
+    assert_eq!(
+        expand_rank(&db, ChatId(1), "#{rank} Supporter").unwrap(),
+        ("#2 Supporter".to_owned(), Some(2))
+    );
+    assert_eq!(
+        expand_rank(&db, ChatId(2), "No.{rank}").unwrap(),
+        ("No.1".to_owned(), Some(1))
+    );
+
+    // Never goes back
+    commit_rank(&db, ChatId(1), 5).unwrap();
+    commit_rank(&db, ChatId(1), 3).unwrap();
+    assert_eq!(
+        expand_rank(&db, ChatId(1), "{rank}").unwrap(),
+        ("6".to_owned(), Some(6))
+    );
+
+    // Corrupt counters are reported instead of starting over
+    db.insert("rank$3", "bad").unwrap();
+    assert!(expand_rank(&db, ChatId(3), "{rank}").is_err());
+    assert!(commit_rank(&db, ChatId(3), 1).is_err());
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+    time::Duration,
+};
+
+use color_eyre::{eyre::Context, Result};
+use sled::Db;
+use teloxide::types::ChatId;
+use tokio::time::interval;
+
+use crate::{send_debug, Config, TitleRecord};
+
+/// Replica of the title keyspace used by listings when `replica_refresh` is
+/// set.
+pub static REPLICA: LazyLock<TitleReplica> = LazyLock::new(TitleReplica::default);
+
+/// In-memory copy of all title records, grouped by chat. Listings are served
+/// from the copy instead of scanning the live database, at the cost of lagging
+/// behind until the next refresh.
+#[derive(Debug, Default)]
+pub struct TitleReplica {
+    chats: RwLock<HashMap<ChatId, Vec<TitleRecord>>>,
+}
+
+impl TitleReplica {
+    /// Replace the copy with all records currently in the database.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn refresh(&self, db: &Db) -> Result<()> {
+        let mut chats = HashMap::<_, Vec<_>>::new();
+        for entry in db.scan_prefix("chat$") {
+            let (key, value) = entry.wrap_err("Failed to scan database")?;
+            let record = TitleRecord::parse_chat_key(&key, &value)?;
+            chats.entry(record.chat_id).or_default().push(record);
+        }
+        *self.chats.write().unwrap() = chats;
+        Ok(())
+    }
+
+    /// Records of the chat as of the last refresh.
+    ///
+    /// # Panics
+    /// If the lock is poisoned
+    #[must_use]
+    pub fn list_in_chat(&self, chat_id: ChatId) -> Vec<TitleRecord> {
+        self.chats
+            .read()
+            .unwrap()
+            .get(&chat_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Refresh the copy every `period`, starting immediately.
+    pub async fn refresh_every(&self, db: &Db, period: Duration) {
+        let mut interval = interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(error) = self.refresh(db) {
+                send_debug(&error);
+            }
+        }
+    }
+}
+
+/// Keep [`REPLICA`] refreshed if enabled, never returns.
+pub async fn run_replica(db: Db) {
+    match Config::get().replica_period() {
+        Some(period) => REPLICA.refresh_every(&db, period).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[tokio::test]
+async fn test_title_replica() {
+    use teloxide::types::UserId;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let record = |chat_id, title: &str| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(chat_id),
+        user_id: UserId(1),
+        expires_at: None,
+    };
+    record(1, "VIP").insert_into(&db).unwrap();
+    record(2, "Staff").insert_into(&db).unwrap();
+
+    let replica = TitleReplica::default();
+    assert!(replica.list_in_chat(ChatId(1)).is_empty());
+    replica.refresh(&db).unwrap();
+    assert_eq!(replica.list_in_chat(ChatId(1)), vec![record(1, "VIP")]);
+    assert_eq!(replica.list_in_chat(ChatId(2)), vec![record(2, "Staff")]);
+
+    // Lags behind the database until refreshed on the interval
+    record(1, "VIP").remove_from(&db).unwrap();
+    record(3, "New").insert_into(&db).unwrap();
+    assert_eq!(replica.list_in_chat(ChatId(1)), vec![record(1, "VIP")]);
+    tokio::select! {
+        _ = replica.refresh_every(&db, Duration::from_millis(10)) => {}
+        _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+    }
+    assert!(replica.list_in_chat(ChatId(1)).is_empty());
+    assert_eq!(replica.list_in_chat(ChatId(3)), vec![record(3, "New")]);
+}
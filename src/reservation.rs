@@ -0,0 +1,284 @@
+use std::fmt::{self, Display};
+
+use color_eyre::{
+    eyre::{ensure, Context, ContextCompat},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::{
+    types::{ChatId, UserId},
+    utils::html,
+};
+
+use crate::TitleRecord;
+
+/// A title reserved for a specific user, stored under
+/// `reserve$<chat_id>$<title>` with the user id as value. Nobody else can set
+/// the title, while the user can claim it with `/title`.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub title: String,
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+}
+
+impl Reservation {
+    /// Insert the reservation into DB, overwriting the existing one.
+    ///
+    /// # Errors
+    /// If the insertion fails.
+    pub fn insert_into(&self, db: &Db) -> Result<()> {
+        db.insert(
+            Self::make_key(self.chat_id, &self.title),
+            &self.user_id.0.to_be_bytes(),
+        )?;
+        Ok(())
+    }
+
+    /// Get the reservation of the title.
+    ///
+    /// # Errors
+    /// When get fails or bad encoding.
+    pub fn get(db: &Db, chat_id: ChatId, title: &str) -> Result<Option<Self>> {
+        let user_id = match db.get(Self::make_key(chat_id, title))? {
+            Some(value) => u64::from_be_bytes((*value).try_into().wrap_err("Bad value")?),
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
+            title: title.to_owned(),
+            chat_id,
+            user_id: UserId(user_id),
+        }))
+    }
+
+    /// List all reservations in the chat.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn list_in_chat(db: &Db, chat_id: ChatId) -> Result<Vec<Self>> {
+        let prefix = format!("reserve${chat_id}$");
+        db.scan_prefix(&prefix)
+            .map(|x| -> Result<_> {
+                let (key, value) = x.wrap_err("Failed to scan database")?;
+                let title = String::from_utf8(key[prefix.len()..].to_vec())?;
+                let user_id = u64::from_be_bytes((*value).try_into().wrap_err("Bad value")?);
+                Ok(Self {
+                    title,
+                    chat_id,
+                    user_id: UserId(user_id),
+                })
+            })
+            .try_collect()
+    }
+
+    /// Ensure the title is either not reserved or reserved for the user.
+    ///
+    /// # Errors
+    /// If the title is reserved for someone else, or get fails.
+    pub fn check(db: &Db, chat_id: ChatId, title: &str, user_id: UserId) -> Result<()> {
+        if let Some(reservation) = Self::get(db, chat_id, title)? {
+            ensure!(
+                reservation.user_id == user_id,
+                "Title is reserved for someone else"
+            );
+        }
+        Ok(())
+    }
+
+    /// Remove the reservation from DB.
+    ///
+    /// # Errors
+    /// If the removal fails.
+    pub fn remove_from(&self, db: &Db) -> Result<()> {
+        db.remove(Self::make_key(self.chat_id, &self.title))?;
+        Ok(())
+    }
+
+    fn make_key(chat_id: ChatId, title: &str) -> IVec {
+        format!("reserve${}${}", chat_id, title).into_bytes().into()
+    }
+}
+
+/// Outcome of reserving many titles at once with [`reserve_all`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BulkReport {
+    pub reserved: Vec<Reservation>,
+    /// Titles skipped, with the reason
+    pub conflicts: Vec<(String, &'static str)>,
+}
+
+impl Display for BulkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reserved {} titles", self.reserved.len())?;
+        if !self.conflicts.is_empty() {
+            write!(f, ", skipped {}:", self.conflicts.len())?;
+            for (title, reason) in &self.conflicts {
+                write!(f, "\n<code>{}</code>: {reason}", html::escape(title))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Insert all reservations, skipping titles that are in use or reserved by
+/// someone else, including earlier ones in the list.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn reserve_all(db: &Db, reservations: Vec<Reservation>) -> Result<BulkReport> {
+    let mut report = BulkReport::default();
+    for reservation in reservations {
+        let Reservation {
+            chat_id,
+            user_id,
+            ref title,
+        } = reservation;
+        let conflict = if let Some(record) = TitleRecord::get_with_title(db, chat_id, title)?
+            && record.user_id != user_id
+        {
+            Some("in use")
+        } else if let Some(existing) = Reservation::get(db, chat_id, title)?
+            && existing.user_id != user_id
+        {
+            Some("reserved for someone else")
+        } else {
+            None
+        };
+        match conflict {
+            Some(reason) => report.conflicts.push((reservation.title, reason)),
+            None => {
+                reservation.insert_into(db)?;
+                report.reserved.push(reservation);
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl Display for Reservation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "<code>{}: User({})</code>",
+            html::escape(&self.title),
+            self.user_id
+        )
+    }
+}
+
+#[test]
+fn test_reservation() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let reservation = Reservation {
+        title: "Staff".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(2),
+    };
+    reservation.insert_into(&db).unwrap();
+
+    assert_eq!(
+        Reservation::get(&db, ChatId(1), "Staff").unwrap(),
+        Some(reservation.clone())
+    );
+    assert!(Reservation::check(&db, ChatId(1), "Staff", UserId(2)).is_ok());
+    assert_eq!(
+        Reservation::check(&db, ChatId(1), "Staff", UserId(3))
+            .unwrap_err()
+            .to_string(),
+        "Title is reserved for someone else"
+    );
+    // Only reserved in chat 1
+    assert!(Reservation::check(&db, ChatId(4), "Staff", UserId(3)).is_ok());
+
+    reservation.remove_from(&db).unwrap();
+    assert!(Reservation::check(&db, ChatId(1), "Staff", UserId(3)).is_ok());
+}
+
+#[test]
+fn test_list_reservations() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let reservation = |chat_id, title: &str, user_id| Reservation {
+        title: title.into(),
+        chat_id: ChatId(chat_id),
+        user_id: UserId(user_id),
+    };
+    for r in [
+        reservation(1, "Staff", 2),
+        reservation(1, "A$<b>", 3),
+        // Another chat sharing the same prefix
+        reservation(10, "Staff", 4),
+    ] {
+        r.insert_into(&db).unwrap();
+    }
+
+    assert_eq!(
+        Reservation::list_in_chat(&db, ChatId(1)).unwrap(),
+        vec![reservation(1, "A$<b>", 3), reservation(1, "Staff", 2)]
+    );
+    assert_eq!(
+        reservation(1, "A$<b>", 3).to_string(),
+        "<code>A$&lt;b&gt;: User(3)</code>"
+    );
+
+    // Revoking one keeps the others
+    reservation(1, "Staff", 2).remove_from(&db).unwrap();
+    assert_eq!(
+        Reservation::list_in_chat(&db, ChatId(1)).unwrap(),
+        vec![reservation(1, "A$<b>", 3)]
+    );
+    assert_eq!(Reservation::list_in_chat(&db, ChatId(10)).unwrap().len(), 1);
+}
+
+#[test]
+fn test_reserve_all() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let reservation = |title: &str, user_id| Reservation {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+    };
+
+    // Clean bulk reserve
+    let report = reserve_all(&db, vec![reservation("A", 2), reservation("B", 3)]).unwrap();
+    assert_eq!(
+        report.reserved,
+        vec![reservation("A", 2), reservation("B", 3)]
+    );
+    assert!(report.conflicts.is_empty());
+    assert_eq!(report.to_string(), "Reserved 2 titles");
+
+    // Conflicts with titles in use and existing reservations are skipped
+    TitleRecord {
+        title: "Taken".into(),
+        chat_id: ChatId(1),
+        user_id: UserId(4),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+    let report = reserve_all(
+        &db,
+        vec![
+            reservation("Taken", 2),
+            reservation("A", 3),
+            reservation("C", 2),
+            reservation("C", 3),
+        ],
+    )
+    .unwrap();
+    assert_eq!(report.reserved, vec![reservation("C", 2)]);
+    assert_eq!(
+        report.to_string(),
+        "Reserved 1 titles, skipped 3:\n<code>Taken</code>: in use\n<code>A</code>: reserved for \
+         someone else\n<code>C</code>: reserved for someone else"
+    );
+    assert_eq!(
+        Reservation::get(&db, ChatId(1), "A").unwrap(),
+        Some(reservation("A", 2))
+    );
+}
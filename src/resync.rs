@@ -0,0 +1,60 @@
+use teloxide::types::ChatMemberKind;
+
+/// Action needed to bring the stored title of a user in line with the live
+/// custom title on Telegram, see [`ResyncAction::decide`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResyncAction {
+    /// Stored and live titles are the same
+    InSync,
+    /// The live title is gone, set the stored title again
+    Reapply(String),
+    /// The live title was changed outside the bot, save it as the record
+    UpdateRecord(String),
+}
+
+impl ResyncAction {
+    /// Decide the action from the stored and live title. The live title wins
+    /// when both are present, since it's what members see.
+    #[must_use]
+    pub fn decide(stored: Option<&str>, live: Option<&str>) -> Self {
+        match (stored, live) {
+            (stored, Some(live)) if stored != Some(live) => Self::UpdateRecord(live.to_owned()),
+            (Some(stored), None) => Self::Reapply(stored.to_owned()),
+            _ => Self::InSync,
+        }
+    }
+}
+
+/// Live custom title of the member, if any.
+#[must_use]
+pub fn live_custom_title(kind: &ChatMemberKind) -> Option<&str> {
+    let title = match kind {
+        ChatMemberKind::Owner(owner) => owner.custom_title.as_deref(),
+        ChatMemberKind::Administrator(admin) => admin.custom_title.as_deref(),
+        _ => None,
+    };
+    title.filter(|title| !title.is_empty())
+}
+
+#[test]
+fn test_resync_action() {
+    assert_eq!(ResyncAction::decide(None, None), ResyncAction::InSync);
+    assert_eq!(
+        ResyncAction::decide(Some("VIP"), Some("VIP")),
+        ResyncAction::InSync
+    );
+    // Re-apply when the title is lost on Telegram side
+    assert_eq!(
+        ResyncAction::decide(Some("VIP"), None),
+        ResyncAction::Reapply("VIP".to_owned())
+    );
+    // Update the record when the title is changed or set outside the bot
+    assert_eq!(
+        ResyncAction::decide(Some("VIP"), Some("Boss")),
+        ResyncAction::UpdateRecord("Boss".to_owned())
+    );
+    assert_eq!(
+        ResyncAction::decide(None, Some("Boss")),
+        ResyncAction::UpdateRecord("Boss".to_owned())
+    );
+}
@@ -0,0 +1,184 @@
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    sync::LazyLock,
+    time::Duration,
+};
+
+use color_eyre::Result;
+use serde::Deserialize;
+use teloxide::{
+    types::{ChatId, UserId},
+    utils::html,
+};
+
+use crate::TtlCache;
+
+/// How long the roster of a chat is cached by [`ROSTER`].
+const ROSTER_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Rosters fetched from `roster_url`, cached for [`ROSTER_TTL`].
+pub static ROSTER: LazyLock<Roster> = LazyLock::new(|| Roster::new(ROSTER_TTL));
+
+/// Entry of the external member roster, listing the titles a member is allowed
+/// to use.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RosterEntry {
+    pub user_id: UserId,
+    #[serde(default)]
+    pub titles: Vec<String>,
+}
+
+/// Rosters of chats, each cached for a while after being fetched.
+#[derive(Debug)]
+pub struct Roster {
+    cache: TtlCache<ChatId, Vec<RosterEntry>>,
+}
+
+impl Roster {
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            cache: TtlCache::new(ttl),
+        }
+    }
+
+    /// Roster of the chat, fetched with `fetch` unless cached.
+    ///
+    /// # Errors
+    /// If fetching fails.
+    pub async fn entries<F, Fut>(&self, chat_id: ChatId, fetch: F) -> Result<Vec<RosterEntry>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<RosterEntry>>>,
+    {
+        if let Some(entries) = self.cache.get(&chat_id) {
+            return Ok(entries);
+        }
+        let entries = fetch().await?;
+        self.cache.insert(chat_id, entries.clone());
+        Ok(entries)
+    }
+}
+
+/// Fetch the roster of the chat with `GET <url>?chat_id=<chat_id>`, which
+/// responds with a JSON array of [`RosterEntry`].
+///
+/// # Errors
+/// If the request fails or the response is malformed.
+pub async fn fetch_roster(url: &str, chat_id: ChatId) -> Result<Vec<RosterEntry>> {
+    Ok(teloxide::net::client_from_env()
+        .get(url)
+        .query(&[("chat_id", chat_id.0)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Outcome of checking the title of a member against the roster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RosterCheck {
+    /// The title is allowed by the roster
+    Match(String),
+    /// The title is not among the allowed ones
+    Mismatch { title: String, allowed: Vec<String> },
+    /// The member has a title but is not listed
+    NotListed(String),
+    /// The member has no title to check
+    NoTitle,
+}
+
+/// Check `title` of the user against the roster `entries`. Titles are
+/// compared as shown, with the prefix and suffix of the chat.
+#[must_use]
+pub fn check_roster(entries: &[RosterEntry], user_id: UserId, title: Option<&str>) -> RosterCheck {
+    let Some(title) = title else {
+        return RosterCheck::NoTitle;
+    };
+    match entries.iter().find(|entry| entry.user_id == user_id) {
+        Some(entry) if entry.titles.iter().any(|allowed| allowed == title) => {
+            RosterCheck::Match(title.to_owned())
+        }
+        Some(entry) => RosterCheck::Mismatch {
+            title: title.to_owned(),
+            allowed: entry.titles.clone(),
+        },
+        None => RosterCheck::NotListed(title.to_owned()),
+    }
+}
+
+impl Display for RosterCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Match(title) => {
+                write!(f, "<code>{}</code> matches the roster", html::escape(title))
+            }
+            Self::Mismatch { title, allowed } if allowed.is_empty() => write!(
+                f,
+                "Mismatch: <code>{}</code> is not allowed, the roster allows no title",
+                html::escape(title)
+            ),
+            Self::Mismatch { title, allowed } => write!(
+                f,
+                "Mismatch: <code>{}</code> is not allowed, the roster allows {}",
+                html::escape(title),
+                allowed
+                    .iter()
+                    .map(|allowed| format!("<code>{}</code>", html::escape(allowed)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::NotListed(title) => write!(
+                f,
+                "Mismatch: <code>{}</code> is used by someone not in the roster",
+                html::escape(title)
+            ),
+            Self::NoTitle => write!(f, "No title to verify"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_roster() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let fetches = AtomicUsize::new(0);
+    let fetch = || async {
+        fetches.fetch_add(1, Ordering::Relaxed);
+        Result::<_>::Ok(serde_json::from_str::<Vec<RosterEntry>>(
+            r#"[{ "user_id": 1, "titles": ["VIP", "Staff"] }, { "user_id": 2 }]"#,
+        )?)
+    };
+    let roster = Roster::new(Duration::from_secs(60));
+
+    let entries = roster.entries(ChatId(-1), fetch).await.unwrap();
+    assert_eq!(
+        check_roster(&entries, UserId(1), Some("Staff")),
+        RosterCheck::Match("Staff".to_owned())
+    );
+    assert_eq!(
+        check_roster(&entries, UserId(1), Some("<Boss>")).to_string(),
+        "Mismatch: <code>&lt;Boss&gt;</code> is not allowed, the roster allows <code>VIP</code>, \
+         <code>Staff</code>"
+    );
+    assert!(matches!(
+        check_roster(&entries, UserId(2), Some("VIP")),
+        RosterCheck::Mismatch { .. }
+    ));
+    assert_eq!(
+        check_roster(&entries, UserId(3), Some("VIP")),
+        RosterCheck::NotListed("VIP".to_owned())
+    );
+    assert_eq!(
+        check_roster(&entries, UserId(3), None),
+        RosterCheck::NoTitle
+    );
+
+    // Cached after the first fetch
+    roster.entries(ChatId(-1), fetch).await.unwrap();
+    assert_eq!(fetches.load(Ordering::Relaxed), 1);
+    roster.entries(ChatId(-2), fetch).await.unwrap();
+    assert_eq!(fetches.load(Ordering::Relaxed), 2);
+}
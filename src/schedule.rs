@@ -0,0 +1,210 @@
+use std::{future::Future, time::Duration};
+
+use color_eyre::{
+    eyre::{ensure, ContextCompat},
+    Result,
+};
+use futures::{stream, StreamExt};
+use humantime_serde::re::humantime;
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::{prelude::*, types::ChatId};
+use tokio::time::interval;
+use tracing::info;
+
+use crate::{
+    finish_removal, send_debug, unix_now, BotType, TitleRecord, BOT, MAX_CONCURRENT_REQUESTS,
+};
+
+/// How often due clears are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Shortest interval between clears, so a typo doesn't clear titles every
+/// minute.
+const MIN_CLEAR_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Recurring clear of all titles in a chat, stored as JSON under
+/// `schedule$<chat_id>` so it survives restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ClearSchedule {
+    /// Interval in seconds
+    every: u64,
+    /// Unix timestamp in seconds of the next clear
+    next_at: u64,
+}
+
+/// Clear all titles of the chat every `every`, starting from `now`,
+/// overwriting the existing schedule.
+///
+/// # Errors
+/// If the interval is too short or the insertion fails.
+pub fn schedule_clear(db: &Db, chat_id: ChatId, every: Duration, now: u64) -> Result<()> {
+    ensure!(
+        every >= MIN_CLEAR_INTERVAL,
+        "Interval too short (min {})",
+        humantime::format_duration(MIN_CLEAR_INTERVAL)
+    );
+    let schedule = ClearSchedule {
+        every: every.as_secs(),
+        next_at: now + every.as_secs(),
+    };
+    db.insert(make_key(chat_id), serde_json::to_vec(&schedule)?)?;
+    Ok(())
+}
+
+/// Cancel the recurring clear of the chat, returns whether there was one.
+///
+/// # Errors
+/// If the removal fails.
+pub fn cancel_clear(db: &Db, chat_id: ChatId) -> Result<bool> {
+    Ok(db.remove(make_key(chat_id))?.is_some())
+}
+
+/// Clear the titles of all chats whose clear is due at `now` with `clear`,
+/// then schedule their next clear an interval from `now`, so clears missed
+/// while the bot was down run once. Returns how many chats were cleared
+/// successfully.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub async fn fire_due_clears<Func, Fut>(db: &Db, now: u64, mut clear: Func) -> Result<usize>
+where
+    Func: FnMut(ChatId) -> Fut,
+    Fut: Future<Output = Result<usize>>,
+{
+    let mut due = Vec::new();
+    for entry in db.scan_prefix("schedule$") {
+        let (key, value) = entry?;
+        let schedule = serde_json::from_slice::<ClearSchedule>(&value)?;
+        if schedule.next_at <= now {
+            due.push((parse_key(&key)?, schedule));
+        }
+    }
+
+    let mut fired = 0;
+    for (chat_id, schedule) in due {
+        match clear(chat_id).await {
+            Ok(cleared) => {
+                info!(chat = ?chat_id, cleared, "Cleared titles on schedule");
+                fired += 1;
+            }
+            Err(error) => send_debug(&error),
+        }
+        let next = ClearSchedule {
+            next_at: now + schedule.every,
+            ..schedule
+        };
+        // Unless cancelled while clearing
+        if db.get(make_key(chat_id))?.is_some() {
+            db.insert(make_key(chat_id), serde_json::to_vec(&next)?)?;
+        }
+    }
+    Ok(fired)
+}
+
+/// Clear the titles of everyone in the chat, returns how many were cleared.
+/// At most [`MAX_CONCURRENT_REQUESTS`] titles are cleared at once, and titles
+/// failed to be cleared on Telegram are kept.
+///
+/// # Errors
+/// If the database returns an error.
+pub async fn clear_chat_titles(bot: &BotType, db: &Db, chat_id: ChatId) -> Result<usize> {
+    let records = TitleRecord::list_in_chat(db, chat_id)?;
+    let results = stream::iter(&records)
+        .map(|record| async move {
+            let res = bot
+                .set_chat_administrator_custom_title(chat_id, record.user_id, "")
+                .send()
+                .await;
+            (record.user_id, res)
+        })
+        .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+    let mut cleared = Vec::with_capacity(records.len());
+    for (user_id, res) in results {
+        match res {
+            Ok(_) => cleared.push(user_id),
+            Err(error) => send_debug(&error),
+        }
+    }
+    finish_removal(db, chat_id, &cleared)
+}
+
+/// Run scheduled clears periodically. Schedules are read from the database on
+/// every check, so they are picked up again after restart. Never returns.
+///
+/// # Panics
+/// If the bot is not initialized.
+pub async fn run_schedules(db: Db) {
+    let bot = BOT.get().unwrap();
+    let mut interval = interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let fired = fire_due_clears(&db, unix_now(), |chat_id| {
+            clear_chat_titles(bot, &db, chat_id)
+        })
+        .await;
+        if let Err(error) = fired {
+            send_debug(&error);
+        }
+    }
+}
+
+fn make_key(chat_id: ChatId) -> IVec {
+    format!("schedule${}", chat_id).into_bytes().into()
+}
+
+fn parse_key(key: &IVec) -> Result<ChatId> {
+    let key = String::from_utf8(key.to_vec())?;
+    let chat_id = key.strip_prefix("schedule$").wrap_err("Bad key")?;
+    Ok(ChatId(chat_id.parse()?))
+}
+
+#[tokio::test]
+async fn test_scheduled_clear() {
+    use std::sync::Mutex;
+
+    let path = std::env::temp_dir().join(format!("golden-axe-schedule-{}", unix_now()));
+    let day = Duration::from_secs(24 * 60 * 60);
+    let cleared = Mutex::new(Vec::new());
+    let clear = |chat_id| {
+        cleared.lock().unwrap().push(chat_id);
+        async { Ok(1) }
+    };
+
+    {
+        let db = sled::open(&path).unwrap();
+        assert!(schedule_clear(&db, ChatId(1), Duration::from_secs(60), 0).is_err());
+        schedule_clear(&db, ChatId(1), day, 1000).unwrap();
+        schedule_clear(&db, ChatId(2), day * 7, 1000).unwrap();
+    }
+
+    // Persisted across restarts
+    let db = sled::open(&path).unwrap();
+
+    // Not due yet
+    assert_eq!(fire_due_clears(&db, 1000 + 86399, clear).await.unwrap(), 0);
+    assert!(cleared.lock().unwrap().is_empty());
+
+    // Triggered at the interval, then rescheduled
+    assert_eq!(fire_due_clears(&db, 1000 + 86400, clear).await.unwrap(), 1);
+    assert_eq!(*cleared.lock().unwrap(), [ChatId(1)]);
+    assert_eq!(fire_due_clears(&db, 1000 + 86401, clear).await.unwrap(), 0);
+    assert_eq!(
+        fire_due_clears(&db, 1000 + 2 * 86400, clear).await.unwrap(),
+        1
+    );
+
+    // Nothing cleared once cancelled
+    assert!(cancel_clear(&db, ChatId(1)).unwrap());
+    assert!(!cancel_clear(&db, ChatId(1)).unwrap());
+    assert_eq!(
+        fire_due_clears(&db, 1000 + 3 * 86400, clear).await.unwrap(),
+        0
+    );
+    assert_eq!(cleared.lock().unwrap().len(), 2);
+
+    drop(db);
+    std::fs::remove_dir_all(path).unwrap();
+}
@@ -1,34 +1,264 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, future::Future};
 
 use color_eyre::{eyre::Context, Result};
 use hyper::{
+    header::AUTHORIZATION,
     service::{make_service_fn, service_fn},
-    Body, Response, Server,
+    Body, Method, Request, Response, Server,
 };
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, UserId},
+    utils::command::BotCommands,
+};
+use tokio::try_join;
 use tracing::info;
 
+use crate::{
+    apply_title, assert_title_editable, emit_event, send_debug, Command, Config, TitleRecord,
+    TransferBundle, WebhookEvent, BOT, BOT_INFO,
+};
+
+/// Body of `POST /title`, used by integrations (e.g. a points system) to set
+/// titles programmatically.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TitleRequest {
+    pub chat_id: i64,
+    pub user_id: u64,
+    pub title: String,
+}
+
+/// Entry of `GET /commands.json`, describing a command for external UIs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandInfo {
+    pub name: String,
+    pub description: String,
+    pub arguments: Vec<ArgumentInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArgumentInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// Describe all commands with their arguments.
+#[must_use]
+pub fn command_list() -> Vec<CommandInfo> {
+    Command::bot_commands()
+        .into_iter()
+        .map(|command| CommandInfo {
+            arguments: Command::arguments_of(&command.command)
+                .iter()
+                .map(|name| ArgumentInfo {
+                    name: (*name).to_owned(),
+                    kind: "string".to_owned(),
+                })
+                .collect(),
+            name: command.command.trim_start_matches('/').to_owned(),
+            description: command.description,
+        })
+        .collect()
+}
+
+fn commands_json() -> Result<Response<Body>, Infallible> {
+    Result::<_, Infallible>::Ok(
+        Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&command_list()).unwrap()))
+            .unwrap(),
+    )
+}
+
 fn no_content() -> Result<Response<Body>, Infallible> {
     Result::<_, Infallible>::Ok(Response::builder().status(204).body(Body::empty()).unwrap())
 }
 
 fn not_found() -> Result<Response<Body>, Infallible> {
+    text(404, "Not Found")
+}
+
+fn text(status: u16, text: impl Into<Body>) -> Result<Response<Body>, Infallible> {
     Result::<_, Infallible>::Ok(
         Response::builder()
-            .status(404)
+            .status(status)
             .header("Content-Type", "text/plain")
-            .body(Body::from("Not Found"))
+            .body(text.into())
+            .unwrap(),
+    )
+}
+
+/// Whether the request carries `Authorization: Bearer <token>`. Always false
+/// when no token is configured.
+fn is_authorized(req: &Request<Body>, token: Option<&str>) -> bool {
+    let header = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    match (token, header) {
+        (Some(token), Some(header)) => header.strip_prefix("Bearer ") == Some(token),
+        _ => false,
+    }
+}
+
+/// Handle `POST /title`, passing the authenticated request to `apply`.
+async fn set_title<Func, Fut>(
+    req: Request<Body>,
+    token: Option<&str>,
+    apply: Func,
+) -> Result<Response<Body>, Infallible>
+where
+    Func: FnOnce(TitleRequest) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if !is_authorized(&req, token) {
+        return text(401, "Unauthorized");
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(error) => return text(400, error.to_string()),
+    };
+    let request = match serde_json::from_slice::<TitleRequest>(&body) {
+        Ok(request) => request,
+        Err(error) => return text(400, error.to_string()),
+    };
+    match apply(request).await {
+        Ok(()) => no_content(),
+        Err(error) => text(422, error.to_string()),
+    }
+}
+
+/// Chat id given as `?chat_id=<id>` in the query of the request.
+fn query_chat_id(req: &Request<Body>) -> Option<ChatId> {
+    req.uri()
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("chat_id="))?
+        .parse()
+        .ok()
+        .map(ChatId)
+}
+
+/// Handle `GET /transfer?chat_id=<id>`, exporting the state of the chat for
+/// another instance to take over.
+fn export_transfer(
+    req: &Request<Body>,
+    token: Option<&str>,
+    db: &Db,
+) -> Result<Response<Body>, Infallible> {
+    if !is_authorized(req, token) {
+        return text(401, "Unauthorized");
+    }
+    let chat_id = match query_chat_id(req) {
+        Some(chat_id) => chat_id,
+        None => return text(400, "Expect ?chat_id=<id>"),
+    };
+    let bundle = match TransferBundle::export(db, chat_id) {
+        Ok(bundle) => bundle,
+        Err(error) => return text(500, error.to_string()),
+    };
+    Result::<_, Infallible>::Ok(
+        Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&bundle).unwrap()))
             .unwrap(),
     )
 }
 
-pub async fn run() -> Result<()> {
-    let make_service = make_service_fn(|_| async {
-        Ok::<_, Infallible>(service_fn(|req| async move {
-            match req.uri().path() {
-                "/health" => no_content(),
-                _ => not_found(),
+/// Handle `POST /transfer`, importing the state of a chat exported by another
+/// instance. Live titles of the replaced records are cleared with `clear`.
+async fn import_transfer<Func, Fut>(
+    req: Request<Body>,
+    token: Option<&str>,
+    db: &Db,
+    clear: Func,
+) -> Result<Response<Body>, Infallible>
+where
+    Func: Fn(TitleRecord) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    if !is_authorized(&req, token) {
+        return text(401, "Unauthorized");
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(error) => return text(400, error.to_string()),
+    };
+    let bundle = match serde_json::from_slice::<TransferBundle>(&body) {
+        Ok(bundle) => bundle,
+        Err(error) => return text(400, error.to_string()),
+    };
+    match bundle.import(db) {
+        Ok(replaced) => {
+            info!(chat = ?bundle.chat_id, replaced = replaced.len(), "Imported transferred state");
+            for record in replaced {
+                clear(record).await;
             }
-        }))
+            no_content()
+        }
+        Err(error) => text(422, error.to_string()),
+    }
+}
+
+pub async fn run(db: Db) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let db = db.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let db = db.clone();
+                async move {
+                    match (req.method(), req.uri().path()) {
+                        (_, "/health") => no_content(),
+                        (&Method::GET, "/commands.json") => commands_json(),
+                        (&Method::POST, "/title") => {
+                            let token = Config::get().api_token.as_deref();
+                            set_title(req, token, |request| async move {
+                                let (chat_id, user_id) =
+                                    (ChatId(request.chat_id), UserId(request.user_id));
+                                let bot = BOT.get().unwrap();
+                                let me = BOT_INFO.get().expect("Bot info not initialized").0;
+                                let (me, member) = try_join!(
+                                    bot.get_chat_member(chat_id, me),
+                                    bot.get_chat_member(chat_id, user_id)
+                                )?;
+                                assert_title_editable(&me.kind, &member.kind)?;
+                                apply_title(bot, &db, chat_id, user_id, request.title).await?;
+                                let event = WebhookEvent::new("api_title", chat_id, user_id);
+                                emit_event(event.with_title_from(&db)?);
+                                Ok(())
+                            })
+                            .await
+                        }
+                        (&Method::GET, "/transfer") => {
+                            export_transfer(&req, Config::get().transfer_token.as_deref(), &db)
+                        }
+                        (&Method::POST, "/transfer") => {
+                            let token = Config::get().transfer_token.as_deref();
+                            import_transfer(req, token, &db, |record| async move {
+                                let bot = BOT.get().unwrap();
+                                if let Err(error) = bot
+                                    .set_chat_administrator_custom_title(
+                                        record.chat_id,
+                                        record.user_id,
+                                        "",
+                                    )
+                                    .await
+                                {
+                                    send_debug(&error);
+                                }
+                            })
+                            .await
+                        }
+                        _ => not_found(),
+                    }
+                }
+            }))
+        }
     });
     info!("Server running");
     Server::bind(&"0.0.0.0:8080".parse().unwrap())
@@ -36,3 +266,121 @@ pub async fn run() -> Result<()> {
         .await
         .wrap_err("")
 }
+
+#[tokio::test]
+async fn test_set_title() {
+    let request = || {
+        Request::post("/title")
+            .header(AUTHORIZATION, "Bearer secret")
+            .body(Body::from(
+                r#"{ "chat_id": -1, "user_id": 2, "title": "Level 10" }"#,
+            ))
+            .unwrap()
+    };
+
+    let mut applied = None;
+    let res = set_title(request(), Some("secret"), |request| {
+        applied = Some(request);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+    assert_eq!(res.status(), 204);
+    assert_eq!(
+        applied,
+        Some(TitleRequest {
+            chat_id: -1,
+            user_id: 2,
+            title: "Level 10".to_owned(),
+        })
+    );
+
+    // Wrong token, or no token configured at all
+    for token in [Some("other"), None] {
+        let res = set_title(request(), token, |_| async {
+            panic!("Unauthenticated request should not be applied")
+        })
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 401);
+    }
+}
+
+#[tokio::test]
+async fn test_transfer() {
+    use std::sync::Mutex;
+
+    let primary = sled::Config::new().temporary(true).open().unwrap();
+    let backup = sled::Config::new().temporary(true).open().unwrap();
+    let record = |user_id, title: &str| TitleRecord {
+        title: title.to_owned(),
+        chat_id: ChatId(-1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    record(2, "VIP").insert_into(&primary).unwrap();
+    record(3, "Stale").insert_into(&backup).unwrap();
+
+    let export = |uri: &str, auth: &str| {
+        let req = Request::get(uri)
+            .header(AUTHORIZATION, auth)
+            .body(Body::empty())
+            .unwrap();
+        export_transfer(&req, Some("handoff"), &primary).unwrap()
+    };
+    assert_eq!(export("/transfer?chat_id=-1", "Bearer other").status(), 401);
+    assert_eq!(export("/transfer", "Bearer handoff").status(), 400);
+    let res = export("/transfer?chat_id=-1", "Bearer handoff");
+    assert_eq!(res.status(), 200);
+    let bundle = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+    let cleared = Mutex::new(Vec::new());
+    let req = Request::post("/transfer")
+        .header(AUTHORIZATION, "Bearer handoff")
+        .body(Body::from(bundle))
+        .unwrap();
+    let res = import_transfer(req, Some("handoff"), &backup, |record| {
+        cleared.lock().unwrap().push(record);
+        async {}
+    })
+    .await
+    .unwrap();
+    assert_eq!(res.status(), 204);
+    assert_eq!(
+        TransferBundle::export(&backup, ChatId(-1)).unwrap(),
+        TransferBundle::export(&primary, ChatId(-1)).unwrap()
+    );
+    // The replaced title is cleared on Telegram side too
+    assert_eq!(*cleared.lock().unwrap(), [record(3, "Stale")]);
+}
+
+#[tokio::test]
+async fn test_commands_json() {
+    let res = commands_json().unwrap();
+    assert_eq!(res.status(), 200);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let commands: Vec<CommandInfo> = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(commands.len(), Command::bot_commands().len());
+    assert!(commands
+        .iter()
+        .all(|command| !command.description.is_empty()));
+    assert_eq!(
+        commands.iter().find(|command| command.name == "title"),
+        Some(&CommandInfo {
+            name: "title".to_owned(),
+            description: "Change my title.".to_owned(),
+            arguments: vec![ArgumentInfo {
+                name: "title".to_owned(),
+                kind: "string".to_owned(),
+            }],
+        })
+    );
+    assert_eq!(
+        commands
+            .iter()
+            .find(|command| command.name == "anonymous")
+            .map(|command| command.arguments.len()),
+        Some(0)
+    );
+}
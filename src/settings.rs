@@ -0,0 +1,748 @@
+use std::time::Duration;
+
+use color_eyre::{
+    eyre::{bail, ensure, Context},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::{types::ChatId, utils::html};
+
+use crate::{format_privileges, normalize_title, title_len, Privilege, TitleRecord, MAX_TITLE_LEN};
+
+/// Message replied when a command is done, unless overridden by the chat.
+pub const DEFAULT_DONE_MESSAGE: &str = "Done! Wait for a while to take effect.";
+
+/// Maximum length of a Telegram text message, in characters.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Named character categories usable in the `allowed_chars` setting.
+const CHAR_CATEGORIES: [(&str, fn(char) -> bool); 5] = [
+    ("ascii_letters", |c| c.is_ascii_alphabetic()),
+    ("letters", char::is_alphabetic),
+    ("digits", char::is_numeric),
+    ("space", |c| c == ' '),
+    ("punctuation", |c| c.is_ascii_punctuation()),
+];
+
+/// Per-chat settings, configured by the owner of the chat with `/set`.
+///
+/// Stored in DB as JSON under `settings$<chat_id>`. Unset fields fall back to
+/// their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Message replied when a command is done.
+    pub done_message: Option<String>,
+    /// Characters allowed in titles, as comma separated items. Each item is
+    /// either a name in [`CHAR_CATEGORIES`] or a set of literal characters,
+    /// e.g. `ascii_letters,space,⭐🔥`. Any character is allowed when unset.
+    pub allowed_chars: Option<String>,
+    /// Prepended to titles set with `/title`.
+    pub title_prefix: Option<String>,
+    /// Appended to titles set with `/title`.
+    pub title_suffix: Option<String>,
+    /// Unix timestamp in seconds until which auto-deletion is paused, set with
+    /// `/verbose` instead of `/set`.
+    pub verbose_until: Option<u64>,
+    /// Maximum number of titles in the chat, set with `/quota` instead of
+    /// `/set`. Unlimited when unset.
+    pub quota: Option<usize>,
+    /// Whether `/nuke` must be given a reason, which is logged to debug.
+    pub nuke_requires_reason: bool,
+    /// Privileges granted when promoting members for titles, set as comma
+    /// separated names, e.g. `invite_users,pin_messages`. Falls back to
+    /// `promote_privileges` of the config when unset.
+    pub promote_privileges: Option<Vec<Privilege>>,
+    /// Words not allowed in titles, set as comma separated words, e.g.
+    /// `spam,scam`, or edited one by one with `/blocked`. Stored normalized,
+    /// see [`ChatSettings::check_words`].
+    pub blocked_words: Option<Vec<String>>,
+}
+
+/// Titles to update when re-applying prefix and suffix, see
+/// [`ChatSettings::plan_reapply`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReapplyPlan {
+    /// Records and their new titles
+    pub updates: Vec<(TitleRecord, String)>,
+    /// Records that would exceed the length limit with prefix and suffix
+    pub too_long: Vec<TitleRecord>,
+}
+
+impl ChatSettings {
+    /// Load settings of the chat, or the default if never set.
+    ///
+    /// # Errors
+    /// When get fails or bad encoding.
+    pub fn load(db: &Db, chat_id: ChatId) -> Result<Self> {
+        match db.get(Self::make_key(chat_id))? {
+            Some(value) => serde_json::from_slice(&value).wrap_err("Bad settings"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Save settings of the chat.
+    ///
+    /// # Errors
+    /// When the insertion fails.
+    pub fn save(&self, db: &Db, chat_id: ChatId) -> Result<()> {
+        db.insert(Self::make_key(chat_id), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Update the setting named `key` with `value`. Empty value resets the
+    /// setting to default.
+    ///
+    /// # Errors
+    /// When the key is unknown or the value is invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        match key {
+            "done_message" => self.done_message = parse_message(value)?,
+            "allowed_chars" => self.allowed_chars = parse_allowed_chars(value)?,
+            "title_prefix" => self.title_prefix = parse_affix(value)?,
+            "title_suffix" => self.title_suffix = parse_affix(value)?,
+            "nuke_requires_reason" => self.nuke_requires_reason = parse_bool(value)?,
+            "promote_privileges" => self.promote_privileges = parse_privileges(value)?,
+            "blocked_words" => self.blocked_words = parse_blocked_words(value)?,
+            _ => bail!("Unknown setting `{}`", html::escape(key)),
+        }
+        Ok(())
+    }
+
+    /// Privileges granted when promoting members, or `default` if unset.
+    #[must_use]
+    pub fn promote_privileges<'a>(&'a self, default: &'a [Privilege]) -> &'a [Privilege] {
+        self.promote_privileges.as_deref().unwrap_or(default)
+    }
+
+    /// Message replied when a command is done, not escaped.
+    #[must_use]
+    pub fn done_message(&self) -> &str {
+        self.done_message.as_deref().unwrap_or(DEFAULT_DONE_MESSAGE)
+    }
+
+    /// Ensure every character of the title is allowed in the chat.
+    ///
+    /// # Errors
+    /// Naming the first character that is not allowed.
+    pub fn check_title(&self, title: &str) -> Result<()> {
+        if let Some(allowed) = &self.allowed_chars
+            && let Some(c) = title.chars().find(|&c| !is_char_allowed(allowed, c))
+        {
+            bail!(
+                "Character `{}` is not allowed in titles of this chat",
+                html::escape(&c.to_string())
+            );
+        }
+        Ok(())
+    }
+
+    /// Ensure the title contains none of the blocked words of the chat. Case
+    /// and whitespace are ignored, so `S p A m` is caught as `spam`.
+    ///
+    /// # Errors
+    /// If a blocked word is found, without repeating the word.
+    pub fn check_words(&self, title: &str) -> Result<()> {
+        if let Some(words) = &self.blocked_words {
+            let title = compact(&normalize_title(title));
+            ensure!(
+                !words.iter().any(|word| title.contains(word.as_str())),
+                "Title contains a word not allowed in this chat"
+            );
+        }
+        Ok(())
+    }
+
+    /// Add a blocked word, see [`ChatSettings::check_words`].
+    ///
+    /// # Errors
+    /// If the word is empty or already blocked.
+    pub fn add_blocked_word(&mut self, word: &str) -> Result<()> {
+        let word = compact(&normalize_title(word));
+        ensure!(!word.is_empty(), "format: /blocked add &lt;word&gt;");
+        let words = self.blocked_words.get_or_insert_with(Vec::new);
+        ensure!(
+            !words.contains(&word),
+            "<code>{}</code> is already blocked",
+            html::escape(&word)
+        );
+        words.push(word);
+        Ok(())
+    }
+
+    /// Remove a blocked word, see [`ChatSettings::check_words`].
+    ///
+    /// # Errors
+    /// If the word is not blocked.
+    pub fn remove_blocked_word(&mut self, word: &str) -> Result<()> {
+        let word = compact(&normalize_title(word));
+        let words = self.blocked_words.get_or_insert_with(Vec::new);
+        let before = words.len();
+        words.retain(|blocked| *blocked != word);
+        let removed = words.len() < before;
+        if words.is_empty() {
+            self.blocked_words = None;
+        }
+        ensure!(
+            removed,
+            "<code>{}</code> is not blocked",
+            html::escape(&word)
+        );
+        Ok(())
+    }
+
+    /// List blocked words of the chat.
+    #[must_use]
+    pub fn describe_blocked_words(&self) -> String {
+        match self.blocked_words.as_deref() {
+            None | Some([]) => "No blocked words.".to_owned(),
+            Some(words) => {
+                let words = words
+                    .iter()
+                    .map(|word| format!("<code>{}</code>", html::escape(word)))
+                    .collect::<Vec<_>>();
+                format!("Blocked words:\n{}", words.join("\n"))
+            }
+        }
+    }
+
+    /// Pause auto-deletion for `duration` from `now`, or resume it right away
+    /// if `duration` is zero.
+    pub fn pause_deletion(&mut self, now: u64, duration: Duration) {
+        self.verbose_until = (!duration.is_zero()).then(|| now + duration.as_secs());
+    }
+
+    /// Whether auto-deletion is paused at `now`.
+    #[must_use]
+    pub fn is_verbose(&self, now: u64) -> bool {
+        self.verbose_until.map_or(false, |until| now < until)
+    }
+
+    /// Set the quota from the argument of `/quota`: a positive number, or
+    /// `off` to disable.
+    ///
+    /// # Errors
+    /// If the value is neither.
+    pub fn set_quota(&mut self, value: &str) -> Result<()> {
+        self.quota = match value.trim() {
+            "off" => None,
+            value => match value.parse() {
+                Ok(quota) if quota > 0 => Some(quota),
+                _ => bail!(
+                    "Expect a positive number or `off`, got `{}`",
+                    html::escape(value)
+                ),
+            },
+        };
+        Ok(())
+    }
+
+    /// Describe the quota, given `used` titles in the chat.
+    #[must_use]
+    pub fn describe_quota(&self, used: usize) -> String {
+        match self.quota {
+            Some(quota) => format!("Quota: {used}/{quota} titles used"),
+            None => format!("No quota, {used} titles used"),
+        }
+    }
+
+    /// Describe the rules titles of the chat must follow, merging the settings
+    /// of the chat with global ones, given `used` titles and `reserved`
+    /// reservations in the chat, and the global `blacklist`.
+    #[must_use]
+    pub fn describe_limits(
+        &self,
+        used: usize,
+        reserved: usize,
+        blacklist: &[String],
+        reject_chat_title: bool,
+    ) -> String {
+        let affix_len = [&self.title_prefix, &self.title_suffix]
+            .into_iter()
+            .flatten()
+            .map(|affix| title_len(affix))
+            .sum::<usize>();
+        let length = if affix_len == 0 {
+            format!("Max title length: {MAX_TITLE_LEN} characters")
+        } else {
+            format!(
+                "Max title length: {} characters, as prefix and suffix take {affix_len} of \
+                 {MAX_TITLE_LEN}",
+                MAX_TITLE_LEN.saturating_sub(affix_len)
+            )
+        };
+        let chars = match &self.allowed_chars {
+            Some(allowed) => format!("Allowed characters: <code>{}</code>", html::escape(allowed)),
+            None => "Allowed characters: any".to_owned(),
+        };
+        let chat_title = if reject_chat_title {
+            "The chat title cannot be used as a title"
+        } else {
+            "The chat title can be used as a title"
+        };
+        [
+            "<b>Limits of this chat</b>".to_owned(),
+            length,
+            self.describe_quota(used),
+            chars,
+            format!(
+                "Blocked words: {}",
+                format_words(self.blocked_words.as_deref().unwrap_or_default())
+            ),
+            format!("Titles not allowed: {}", format_words(blacklist)),
+            format!("Reserved titles: {reserved}"),
+            chat_title.to_owned(),
+        ]
+        .join("\n")
+    }
+
+    /// Ensure one more title fits in the quota, given `used` titles in the
+    /// chat.
+    ///
+    /// # Errors
+    /// If the quota is reached.
+    pub fn check_quota(&self, used: usize) -> Result<()> {
+        if let Some(quota) = self.quota {
+            ensure!(
+                used < quota,
+                "This chat has reached its quota of {quota} titles"
+            );
+        }
+        Ok(())
+    }
+
+    /// Ensure `/nuke` is given a reason if the chat requires one.
+    ///
+    /// # Errors
+    /// If a reason is required but empty.
+    pub fn check_nuke_reason(&self, reason: &str) -> Result<()> {
+        ensure!(
+            !self.nuke_requires_reason || !reason.trim().is_empty(),
+            "A reason is required in this chat: /nuke &lt;reason&gt;"
+        );
+        Ok(())
+    }
+
+    /// Apply the prefix and suffix to the title, unless it already has them.
+    #[must_use]
+    pub fn decorate_title(&self, title: &str) -> String {
+        let mut title = title.to_owned();
+        if let Some(prefix) = &self.title_prefix
+            && !title.starts_with(prefix.as_str())
+        {
+            title.insert_str(0, prefix);
+        }
+        if let Some(suffix) = &self.title_suffix
+            && !title.ends_with(suffix.as_str())
+        {
+            title.push_str(suffix);
+        }
+        title
+    }
+
+    /// Plan re-applying the prefix and suffix to existing records. Records
+    /// that already have them are left out, and ones that would no longer fit
+    /// are reported in [`ReapplyPlan::too_long`].
+    #[must_use]
+    pub fn plan_reapply(&self, records: Vec<TitleRecord>) -> ReapplyPlan {
+        let mut plan = ReapplyPlan::default();
+        for record in records {
+            let title = self.decorate_title(&record.title);
+            if title == record.title {
+                continue;
+            }
+            if title_len(&title) > MAX_TITLE_LEN {
+                plan.too_long.push(record);
+            } else {
+                plan.updates.push((record, title));
+            }
+        }
+        plan
+    }
+
+    fn make_key(chat_id: ChatId) -> IVec {
+        format!("settings${}", chat_id).into_bytes().into()
+    }
+}
+
+/// Parse a message setting, making sure it still fits in a message after
+/// being escaped.
+fn parse_message(value: &str) -> Result<Option<String>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    ensure!(
+        html::escape(value).chars().count() <= MAX_MESSAGE_LEN,
+        "Message too long (max {MAX_MESSAGE_LEN} characters)"
+    );
+    Ok(Some(value.to_owned()))
+}
+
+/// Parse the `allowed_chars` setting, see [`ChatSettings::allowed_chars`].
+fn parse_allowed_chars(value: &str) -> Result<Option<String>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    ensure!(
+        value.split(',').all(|item| !item.trim().is_empty()),
+        "Allowed characters cannot contain empty items"
+    );
+    Ok(Some(value.to_owned()))
+}
+
+/// Parse a title prefix or suffix, leaving room for at least one character
+/// of the title itself.
+fn parse_affix(value: &str) -> Result<Option<String>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    ensure!(
+        title_len(value) < MAX_TITLE_LEN,
+        "Prefix or suffix too long (max {} characters)",
+        MAX_TITLE_LEN - 1
+    );
+    Ok(Some(value.to_owned()))
+}
+
+/// Parse comma separated blocked words, empty meaning unset. Words are stored
+/// the way titles are compared against them in [`ChatSettings::check_words`].
+fn parse_blocked_words(value: &str) -> Result<Option<Vec<String>>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let mut words = Vec::new();
+    for word in value.split(',').map(|word| compact(&normalize_title(word))) {
+        ensure!(!word.is_empty(), "Blocked words cannot contain empty items");
+        if !words.contains(&word) {
+            words.push(word);
+        }
+    }
+    Ok(Some(words))
+}
+
+/// Words as comma separated code, or `none`.
+fn format_words(words: &[String]) -> String {
+    if words.is_empty() {
+        return "none".to_owned();
+    }
+    words
+        .iter()
+        .map(|word| format!("<code>{}</code>", html::escape(word)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Remove all whitespace of a normalized title.
+fn compact(title: &str) -> String {
+    title.split_whitespace().collect()
+}
+
+/// Parse comma separated privilege names, empty meaning unset. At least one
+/// privilege is needed, since promoting with none demotes the member instead.
+fn parse_privileges(value: &str) -> Result<Option<Vec<Privilege>>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let mut privileges = Vec::new();
+    for name in value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let privilege = Privilege::parse(name).wrap_err_with(|| {
+            format!(
+                "Unknown privilege `{}`, expect some of {}",
+                html::escape(name),
+                format_privileges(&Privilege::ALL)
+            )
+        })?;
+        if !privileges.contains(&privilege) {
+            privileges.push(privilege);
+        }
+    }
+    ensure!(
+        !privileges.is_empty(),
+        "At least one privilege is needed, promoting with none would demote"
+    );
+    Ok(Some(privileges))
+}
+
+/// Parse a boolean setting, empty meaning `false`.
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" | "" => Ok(false),
+        _ => bail!("Expect true or false, got `{}`", html::escape(value)),
+    }
+}
+
+fn is_char_allowed(allowed: &str, c: char) -> bool {
+    allowed.split(',').map(str::trim).any(|item| {
+        CHAR_CATEGORIES
+            .iter()
+            .find(|(name, _)| *name == item)
+            .map_or_else(|| item.contains(c), |(_, matches)| matches(c))
+    })
+}
+
+#[test]
+fn test_done_message() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let settings = ChatSettings::load(&db, ChatId(1)).unwrap();
+    assert_eq!(settings.done_message(), DEFAULT_DONE_MESSAGE);
+
+    let mut settings = settings;
+    settings.set("done_message", " <b>Yay</b> ").unwrap();
+    settings.save(&db, ChatId(1)).unwrap();
+
+    let settings = ChatSettings::load(&db, ChatId(1)).unwrap();
+    assert_eq!(settings.done_message(), "<b>Yay</b>");
+    assert_eq!(
+        ChatSettings::load(&db, ChatId(2)).unwrap().done_message(),
+        DEFAULT_DONE_MESSAGE
+    );
+
+    let mut settings = settings;
+    settings.set("done_message", "").unwrap();
+    assert_eq!(settings.done_message(), DEFAULT_DONE_MESSAGE);
+
+    assert!(settings.set("done_message", &"&".repeat(1000)).is_err());
+    assert!(settings.set("no_such_setting", "").is_err());
+}
+
+#[test]
+fn test_allowed_chars() {
+    let mut settings = ChatSettings::default();
+    // Permissive by default
+    assert!(settings.check_title("Anything <3 ⭐").is_ok());
+
+    settings
+        .set("allowed_chars", "ascii_letters, space, ⭐🔥")
+        .unwrap();
+    assert!(settings.check_title("Star ⭐").is_ok());
+    assert_eq!(
+        settings.check_title("Star <3").unwrap_err().to_string(),
+        "Character `&lt;` is not allowed in titles of this chat"
+    );
+    assert_eq!(
+        settings.check_title("Café").unwrap_err().to_string(),
+        "Character `é` is not allowed in titles of this chat"
+    );
+
+    assert!(settings.set("allowed_chars", "digits,,space").is_err());
+    settings.set("allowed_chars", "").unwrap();
+    assert!(settings.check_title("Café <3").is_ok());
+}
+
+#[test]
+fn test_blocked_words() {
+    let mut settings = ChatSettings::default();
+    assert!(settings.check_words("Spammer").is_ok());
+
+    settings.set("blocked_words", "spam, Bad Word").unwrap();
+    assert_eq!(
+        settings.blocked_words,
+        Some(vec!["spam".to_owned(), "badword".to_owned()])
+    );
+
+    // Blocked, also with spacing and casing changed
+    assert_eq!(
+        settings.check_words("Spammer").unwrap_err().to_string(),
+        "Title contains a word not allowed in this chat"
+    );
+    assert!(settings.check_words("S p A m").is_err());
+    assert!(settings.check_words("BADWORD").is_err());
+
+    // Clean titles pass
+    assert!(settings.check_words("Moderator").is_ok());
+
+    assert!(settings.set("blocked_words", "spam,,scam").is_err());
+    settings.set("blocked_words", "").unwrap();
+    assert!(settings.check_words("Spammer").is_ok());
+}
+
+#[test]
+fn test_edit_blocked_words() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let mut settings = ChatSettings::default();
+    assert_eq!(settings.describe_blocked_words(), "No blocked words.");
+
+    // Added words block titles, stored like the ones set with /set
+    settings.set("blocked_words", "spam").unwrap();
+    settings.add_blocked_word(" Bad  Word ").unwrap();
+    settings.add_blocked_word("<scam>").unwrap();
+    assert!(settings.add_blocked_word("badword").is_err());
+    assert!(settings.add_blocked_word(" ").is_err());
+    settings.save(&db, ChatId(1)).unwrap();
+
+    let mut settings = ChatSettings::load(&db, ChatId(1)).unwrap();
+    assert!(settings.check_words("Bad Wordsmith").is_err());
+    assert!(settings.check_words("Spammer").is_err());
+    assert_eq!(
+        settings.describe_blocked_words(),
+        "Blocked words:\n<code>spam</code>\n<code>badword</code>\n<code>&lt;scam&gt;</code>"
+    );
+
+    // Allowed again once removed
+    settings.remove_blocked_word("Bad Word").unwrap();
+    assert!(settings.remove_blocked_word("Bad Word").is_err());
+    assert!(settings.check_words("Bad Wordsmith").is_ok());
+    settings.remove_blocked_word("spam").unwrap();
+    settings.remove_blocked_word("<scam>").unwrap();
+    assert_eq!(settings.blocked_words, None);
+}
+
+#[test]
+fn test_plan_reapply() {
+    use teloxide::types::UserId;
+
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+
+    let mut settings = ChatSettings::default();
+    settings.set("title_prefix", "⭐").unwrap();
+    assert_eq!(settings.decorate_title("Alice"), "⭐Alice");
+    assert_eq!(settings.decorate_title("⭐Alice"), "⭐Alice");
+
+    let plan = settings.plan_reapply(vec![
+        record("Alice", 1),
+        record("Sixteen chars!!!", 2),
+        record("⭐Bob", 3),
+    ]);
+    assert_eq!(
+        plan.updates,
+        vec![(record("Alice", 1), "⭐Alice".to_owned())]
+    );
+    assert_eq!(plan.too_long, vec![record("Sixteen chars!!!", 2)]);
+
+    assert!(settings
+        .set("title_suffix", &"!".repeat(MAX_TITLE_LEN))
+        .is_err());
+}
+
+#[test]
+fn test_verbose() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    let mut settings = ChatSettings::default();
+    assert!(!settings.is_verbose(1000));
+
+    settings.pause_deletion(1000, Duration::from_secs(60));
+    settings.save(&db, ChatId(1)).unwrap();
+
+    // Persisted, and reverts after expiry
+    let mut settings = ChatSettings::load(&db, ChatId(1)).unwrap();
+    assert!(settings.is_verbose(1000));
+    assert!(settings.is_verbose(1059));
+    assert!(!settings.is_verbose(1060));
+    assert!(settings.set("verbose_until", "0").is_err());
+
+    settings.pause_deletion(1000, Duration::ZERO);
+    assert!(!settings.is_verbose(1000));
+}
+
+#[test]
+fn test_quota() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    // Showing
+    let mut settings = ChatSettings::default();
+    assert_eq!(settings.describe_quota(3), "No quota, 3 titles used");
+    assert!(settings.check_quota(100).is_ok());
+
+    // Setting, persisted
+    settings.set_quota("2").unwrap();
+    settings.save(&db, ChatId(1)).unwrap();
+    let mut settings = ChatSettings::load(&db, ChatId(1)).unwrap();
+    assert_eq!(settings.describe_quota(1), "Quota: 1/2 titles used");
+    assert!(settings.check_quota(1).is_ok());
+    assert_eq!(
+        settings.check_quota(2).unwrap_err().to_string(),
+        "This chat has reached its quota of 2 titles"
+    );
+    for bad in ["0", "-1", "many", ""] {
+        assert!(settings.set_quota(bad).is_err());
+    }
+    assert_eq!(settings.quota, Some(2));
+    assert!(settings.set("quota", "5").is_err());
+
+    // Disabling
+    settings.set_quota("off").unwrap();
+    assert_eq!(settings.quota, None);
+    assert!(settings.check_quota(2).is_ok());
+}
+
+#[test]
+fn test_nuke_reason() {
+    let mut settings = ChatSettings::default();
+    assert!(settings.check_nuke_reason("").is_ok());
+
+    settings.set("nuke_requires_reason", "true").unwrap();
+    assert!(settings.check_nuke_reason("spam wave").is_ok());
+    assert_eq!(
+        settings.check_nuke_reason("  ").unwrap_err().to_string(),
+        "A reason is required in this chat: /nuke &lt;reason&gt;"
+    );
+    assert!(settings.set("nuke_requires_reason", "yes").is_err());
+
+    settings.set("nuke_requires_reason", "").unwrap();
+    assert!(settings.check_nuke_reason("").is_ok());
+}
+
+#[test]
+fn test_promote_privileges() {
+    let default = [Privilege::InviteUsers];
+    let mut settings = ChatSettings::default();
+    assert_eq!(settings.promote_privileges(&default), default);
+
+    // Without the invite privilege
+    settings
+        .set("promote_privileges", "pin_messages, manage_chat")
+        .unwrap();
+    assert_eq!(
+        settings.promote_privileges(&default),
+        [Privilege::PinMessages, Privilege::ManageChat]
+    );
+
+    // Invalid combinations are refused, keeping the previous value
+    assert!(settings.set("promote_privileges", "invite").is_err());
+    assert!(settings.set("promote_privileges", " , ").is_err());
+    assert_eq!(settings.promote_privileges(&default).len(), 2);
+
+    settings.set("promote_privileges", "").unwrap();
+    assert_eq!(settings.promote_privileges(&default), default);
+}
+
+#[test]
+fn test_describe_limits() {
+    // Global defaults
+    let mut settings = ChatSettings::default();
+    assert_eq!(
+        settings.describe_limits(0, 0, &[], false),
+        "<b>Limits of this chat</b>\nMax title length: 16 characters\nNo quota, 0 titles \
+         used\nAllowed characters: any\nBlocked words: none\nTitles not allowed: none\nReserved \
+         titles: 0\nThe chat title can be used as a title"
+    );
+
+    // Overridden by the chat
+    settings.set("title_prefix", "[F]").unwrap();
+    settings.set("allowed_chars", "letters,<>").unwrap();
+    settings.set("blocked_words", "spam,<b>").unwrap();
+    settings.set_quota("5").unwrap();
+    let blacklist = ["Owner".to_owned(), "Admin".to_owned()];
+    assert_eq!(
+        settings.describe_limits(2, 1, &blacklist, true),
+        "<b>Limits of this chat</b>\nMax title length: 13 characters, as prefix and suffix take 3 \
+         of 16\nQuota: 2/5 titles used\nAllowed characters: \
+         <code>letters,&lt;&gt;</code>\nBlocked words: <code>spam</code>, \
+         <code>&lt;b&gt;</code>\nTitles not allowed: <code>Owner</code>, \
+         <code>Admin</code>\nReserved titles: 1\nThe chat title cannot be used as a title"
+    );
+}
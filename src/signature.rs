@@ -0,0 +1,124 @@
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::types::{ChatId, UserId};
+
+/// A previous title of an anonymous admin, stored as JSON under
+/// `oldsig$<chat_id>$<title>`. Messages sent before the title changed are
+/// signed with it, so it still resolves to the admin until it expires.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct OldSignature {
+    user_id: UserId,
+    /// Unix timestamp in seconds
+    expires_at: u64,
+}
+
+/// Keep resolving the previous title `title` of the user until `expires_at`.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn remember_signature(
+    db: &Db,
+    chat_id: ChatId,
+    title: &str,
+    user_id: UserId,
+    expires_at: u64,
+) -> Result<()> {
+    let entry = OldSignature {
+        user_id,
+        expires_at,
+    };
+    db.insert(make_key(chat_id, title), serde_json::to_vec(&entry)?)?;
+    Ok(())
+}
+
+/// Find the user who signed with the previous title `sig`, unless expired at
+/// `now`. Expired entries are removed.
+///
+/// # Errors
+/// If the database returns an error or the entry is malformed.
+pub fn resolve_signature(db: &Db, chat_id: ChatId, sig: &str, now: u64) -> Result<Option<UserId>> {
+    let key = make_key(chat_id, sig);
+    let entry = match db.get(&key)? {
+        Some(value) => serde_json::from_slice::<OldSignature>(&value)?,
+        None => return Ok(None),
+    };
+    if now >= entry.expires_at {
+        db.remove(key)?;
+        return Ok(None);
+    }
+    Ok(Some(entry.user_id))
+}
+
+/// Previous titles of the user in the chat that still resolve to them,
+/// including expired ones not yet cleaned up.
+///
+/// # Errors
+/// If the database returns an error or an entry is malformed.
+pub fn signatures_of(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Vec<String>> {
+    let prefix = format!("oldsig${chat_id}$");
+    let mut titles = Vec::new();
+    for item in db.scan_prefix(&prefix) {
+        let (key, value) = item?;
+        if serde_json::from_slice::<OldSignature>(&value)?.user_id == user_id {
+            titles.push(String::from_utf8(key[prefix.len()..].to_vec())?);
+        }
+    }
+    Ok(titles)
+}
+
+/// Stop resolving previous titles of the user in the chat, returns how many
+/// were removed.
+///
+/// # Errors
+/// If the database returns an error or an entry is malformed.
+pub fn forget_signatures(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<usize> {
+    let titles = signatures_of(db, chat_id, user_id)?;
+    for title in &titles {
+        db.remove(make_key(chat_id, title))?;
+    }
+    Ok(titles.len())
+}
+
+fn make_key(chat_id: ChatId, title: &str) -> IVec {
+    format!("oldsig${}${}", chat_id, title).into_bytes().into()
+}
+
+#[test]
+fn test_resolve_signature() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    remember_signature(&db, ChatId(1), "Old", UserId(2), 1000).unwrap();
+
+    // Resolves during the grace period, only in the chat
+    assert_eq!(
+        resolve_signature(&db, ChatId(1), "Old", 999).unwrap(),
+        Some(UserId(2))
+    );
+    assert_eq!(resolve_signature(&db, ChatId(3), "Old", 999).unwrap(), None);
+    assert_eq!(
+        resolve_signature(&db, ChatId(1), "Other", 999).unwrap(),
+        None
+    );
+
+    // Expires after, and is cleaned up
+    assert_eq!(
+        resolve_signature(&db, ChatId(1), "Old", 1000).unwrap(),
+        None
+    );
+    assert!(db.is_empty());
+
+    // Forgotten users no longer resolve
+    remember_signature(&db, ChatId(1), "Mine", UserId(2), 1000).unwrap();
+    remember_signature(&db, ChatId(1), "Theirs", UserId(3), 1000).unwrap();
+    assert_eq!(signatures_of(&db, ChatId(1), UserId(2)).unwrap(), ["Mine"]);
+    assert_eq!(forget_signatures(&db, ChatId(1), UserId(2)).unwrap(), 1);
+    assert_eq!(
+        resolve_signature(&db, ChatId(1), "Mine", 999).unwrap(),
+        None
+    );
+    assert_eq!(
+        resolve_signature(&db, ChatId(1), "Theirs", 999).unwrap(),
+        Some(UserId(3))
+    );
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use color_eyre::{
+    eyre::{bail, ensure, Context},
+    Result,
+};
+use sled::{Db, IVec};
+use teloxide::types::ChatId;
+
+use crate::TitleRecord;
+
+/// Save the records as snapshot `name` of the chat, overwriting the existing
+/// one. Snapshots are stored as JSON under `snapshot$<chat_id>$<name>`.
+///
+/// # Errors
+/// If the name is invalid or the insertion fails.
+pub fn save_snapshot(db: &Db, chat_id: ChatId, name: &str, records: &[TitleRecord]) -> Result<()> {
+    ensure!(
+        !name.is_empty() && !name.contains(char::is_whitespace),
+        "Snapshot name cannot be empty or contain spaces"
+    );
+    db.insert(
+        make_snapshot_key(chat_id, name),
+        serde_json::to_vec(records)?,
+    )?;
+    Ok(())
+}
+
+/// Load snapshot `name` of the chat.
+///
+/// # Errors
+/// If the snapshot does not exist or is in bad shape.
+pub fn load_snapshot(db: &Db, chat_id: ChatId, name: &str) -> Result<Vec<TitleRecord>> {
+    match db.get(make_snapshot_key(chat_id, name))? {
+        Some(value) => serde_json::from_slice(&value).wrap_err("Bad snapshot"),
+        None => bail!("No such snapshot"),
+    }
+}
+
+fn make_snapshot_key(chat_id: ChatId, name: &str) -> IVec {
+    format!("snapshot${}${}", chat_id, name).into_bytes().into()
+}
+
+/// Difference between two sets of title records, keyed by user.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TitleDiff {
+    pub added: Vec<TitleRecord>,
+    pub removed: Vec<TitleRecord>,
+    /// Pairs of (old, new) records of users whose title changed
+    pub changed: Vec<(TitleRecord, TitleRecord)>,
+}
+
+impl TitleDiff {
+    /// Compute the difference from `old` to `new`.
+    pub fn between(old: &[TitleRecord], new: &[TitleRecord]) -> Self {
+        let old_by_user = old
+            .iter()
+            .map(|record| (record.user_id, record))
+            .collect::<HashMap<_, _>>();
+        let new_by_user = new
+            .iter()
+            .map(|record| (record.user_id, record))
+            .collect::<HashMap<_, _>>();
+
+        let mut diff = Self::default();
+        for record in new {
+            match old_by_user.get(&record.user_id) {
+                None => diff.added.push(record.clone()),
+                Some(old) if old.title != record.title => {
+                    diff.changed.push(((*old).clone(), record.clone()));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .iter()
+            .filter(|record| !new_by_user.contains_key(&record.user_id))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Display for TitleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("No changes.");
+        }
+        let mut lines = Vec::new();
+        lines.extend(self.added.iter().map(|record| format!("+ {record}")));
+        lines.extend(self.removed.iter().map(|record| format!("- {record}")));
+        lines.extend(
+            self.changed
+                .iter()
+                .map(|(old, new)| format!("~ {old} → {new}")),
+        );
+        f.write_str(&lines.join("\n"))
+    }
+}
+
+#[test]
+fn test_snapshot_diff() {
+    use teloxide::types::UserId;
+
+    let record = |user_id, title: &str| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let before = vec![record(1, "kept"), record(2, "old"), record(3, "gone")];
+    save_snapshot(&db, ChatId(1), "weekly", &before).unwrap();
+    assert!(save_snapshot(&db, ChatId(1), "bad name", &before).is_err());
+    assert!(load_snapshot(&db, ChatId(1), "missing").is_err());
+
+    let saved = load_snapshot(&db, ChatId(1), "weekly").unwrap();
+    assert_eq!(saved, before);
+
+    let after = vec![record(1, "kept"), record(2, "new"), record(4, "fresh")];
+    let diff = TitleDiff::between(&saved, &after);
+    assert_eq!(
+        diff,
+        TitleDiff {
+            added: vec![record(4, "fresh")],
+            removed: vec![record(3, "gone")],
+            changed: vec![(record(2, "old"), record(2, "new"))],
+        }
+    );
+    assert!(TitleDiff::between(&after, &after).is_empty());
+}
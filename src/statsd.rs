@@ -0,0 +1,66 @@
+use std::{net::UdpSocket, sync::OnceLock, time::Duration};
+
+use tracing::{info, warn};
+
+use crate::Config;
+
+/// Prefix of all metric names.
+const METRIC_PREFIX: &str = "golden_axe";
+
+static STATSD: OnceLock<Option<UdpSocket>> = OnceLock::new();
+
+/// Connect to the StatsD server at `statsd` if configured. Metrics are
+/// dropped otherwise.
+pub fn init_statsd() {
+    STATSD.get_or_init(|| {
+        let addr = Config::get().statsd.as_deref()?;
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(addr).map(|()| socket))
+            .map_err(|error| warn!(%error, addr, "Failed to connect to StatsD"))
+            .ok()?;
+        info!(addr, "StatsD sink initialized");
+        Some(socket)
+    });
+}
+
+/// Push metrics of a handled command: a counter of runs, a counter of errors
+/// if failed, and a timer of how long it took.
+pub fn record_command(command: &str, ok: bool, elapsed: Duration) {
+    let Some(Some(socket)) = STATSD.get() else {
+        return;
+    };
+    if let Err(error) = socket.send(command_lines(command, ok, elapsed).join("\n").as_bytes()) {
+        warn!(%error, "Failed to send metrics to StatsD");
+    }
+}
+
+/// StatsD lines of a handled command, e.g.
+/// `golden_axe.command.title.count:1|c`.
+fn command_lines(command: &str, ok: bool, elapsed: Duration) -> Vec<String> {
+    let name = format!("{METRIC_PREFIX}.command.{}", command.to_lowercase());
+    let mut lines = vec![format!("{name}.count:1|c")];
+    if !ok {
+        lines.push(format!("{name}.error:1|c"));
+    }
+    lines.push(format!("{name}.time:{}|ms", elapsed.as_millis()));
+    lines
+}
+
+#[test]
+fn test_command_lines() {
+    assert_eq!(
+        command_lines("Title", true, Duration::from_millis(1500)),
+        [
+            "golden_axe.command.title.count:1|c",
+            "golden_axe.command.title.time:1500|ms"
+        ]
+    );
+    assert_eq!(
+        command_lines("RemoveTitle", false, Duration::from_millis(20)),
+        [
+            "golden_axe.command.removetitle.count:1|c",
+            "golden_axe.command.removetitle.error:1|c",
+            "golden_axe.command.removetitle.time:20|ms"
+        ]
+    );
+}
@@ -0,0 +1,172 @@
+use color_eyre::{
+    eyre::{bail, ensure, Context},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::{
+    types::{ChatId, UserId},
+    utils::html,
+};
+
+/// Maximum length of a badge name, in characters.
+const MAX_BADGE_LEN: usize = 32;
+
+/// Presentation of a title for external UIs, e.g. a web view of the titles.
+/// Doesn't affect the title on Telegram.
+///
+/// Stored as JSON under `style$<chat_id>$<user_id>`, and exported with
+/// `/mydata`, `/exporttitles` and transfers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TitleStyle {
+    /// Color in hex, e.g. `#ff8800`
+    pub color: Option<String>,
+    /// Name of a badge, e.g. `gold`
+    pub badge: Option<String>,
+}
+
+impl TitleStyle {
+    /// Parse `key=value` options of `/style` separated by whitespace, e.g.
+    /// `color=#ff8800 badge=gold`. Unspecified fields are left unchanged.
+    ///
+    /// # Errors
+    /// If any option is unknown or malformed.
+    pub fn update(&mut self, args: &str) -> Result<()> {
+        for option in args.split_whitespace() {
+            match option.split_once('=') {
+                Some(("color", "")) => self.color = None,
+                Some(("color", color)) => {
+                    ensure!(
+                        is_hex_color(color),
+                        "Bad color `{}`, expect hex like #ff8800",
+                        html::escape(color)
+                    );
+                    self.color = Some(color.to_lowercase());
+                }
+                Some(("badge", "")) => self.badge = None,
+                Some(("badge", badge)) => {
+                    ensure!(
+                        badge.chars().count() <= MAX_BADGE_LEN
+                            && badge
+                                .chars()
+                                .all(|c| c.is_alphanumeric() || c == '-' || c == '_'),
+                        "Bad badge `{}`, expect letters, digits, - or _ (max {MAX_BADGE_LEN} \
+                         characters)",
+                        html::escape(badge)
+                    );
+                    self.badge = Some(badge.to_owned());
+                }
+                _ => bail!(
+                    "Unknown option `{}`, expect color=&lt;hex&gt; or badge=&lt;name&gt;, empty \
+                     to unset",
+                    html::escape(option)
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.color.is_none() && self.badge.is_none()
+    }
+
+    /// Load the style of the user in the chat, if any.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn load(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Option<Self>> {
+        Ok(match db.get(make_key(chat_id, user_id))? {
+            Some(value) => Some(serde_json::from_slice(&value)?),
+            None => None,
+        })
+    }
+
+    /// List styles of all users in the chat.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn list_in_chat(db: &Db, chat_id: ChatId) -> Result<Vec<(UserId, Self)>> {
+        let prefix = format!("style${chat_id}$");
+        db.scan_prefix(&prefix)
+            .map(|x| -> Result<_> {
+                let (key, value) = x.wrap_err("Failed to scan database")?;
+                let user_id = std::str::from_utf8(&key[prefix.len()..])?.parse()?;
+                Ok((UserId(user_id), serde_json::from_slice(&value)?))
+            })
+            .try_collect()
+    }
+
+    /// Remove the style of the user in the chat.
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn remove(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<()> {
+        db.remove(make_key(chat_id, user_id))?;
+        Ok(())
+    }
+
+    /// Save the style of the user in the chat, removing it if empty.
+    ///
+    /// # Errors
+    /// If the database returns an error.
+    pub fn save(&self, db: &Db, chat_id: ChatId, user_id: UserId) -> Result<()> {
+        if self.is_empty() {
+            Self::remove(db, chat_id, user_id)?;
+        } else {
+            db.insert(make_key(chat_id, user_id), serde_json::to_vec(self)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the color is in hex like `#ff8800`.
+fn is_hex_color(color: &str) -> bool {
+    color.strip_prefix('#').map_or(false, |hex| {
+        hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+fn make_key(chat_id: ChatId, user_id: UserId) -> IVec {
+    format!("style${}${}", chat_id, user_id).into_bytes().into()
+}
+
+#[test]
+fn test_title_style() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+
+    // Setting
+    let mut style = TitleStyle::default();
+    style.update("color=#FF8800 badge=gold").unwrap();
+    assert_eq!(
+        style,
+        TitleStyle {
+            color: Some("#ff8800".to_owned()),
+            badge: Some("gold".to_owned()),
+        }
+    );
+    for bad in [
+        "color=ff8800",
+        "color=#ff88",
+        "color=#gg8800",
+        "badge=<b>",
+        "size=2",
+    ] {
+        assert!(style.clone().update(bad).is_err());
+    }
+
+    // Storing
+    assert_eq!(TitleStyle::load(&db, ChatId(1), UserId(2)).unwrap(), None);
+    style.save(&db, ChatId(1), UserId(2)).unwrap();
+    assert_eq!(
+        TitleStyle::load(&db, ChatId(1), UserId(2)).unwrap(),
+        Some(style.clone())
+    );
+
+    // Unsetting, removed once empty
+    style.update("color=").unwrap();
+    assert_eq!(style.badge.as_deref(), Some("gold"));
+    style.update("badge=").unwrap();
+    style.save(&db, ChatId(1), UserId(2)).unwrap();
+    assert!(db.is_empty());
+}
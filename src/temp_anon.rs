@@ -0,0 +1,197 @@
+use std::{future::Future, time::Duration};
+
+use color_eyre::{
+    eyre::{ensure, ContextCompat},
+    Result,
+};
+use sled::{Db, IVec};
+use teloxide::{
+    prelude::*,
+    types::{ChatId, UserId},
+};
+use tokio::time::interval;
+use tracing::info;
+
+use crate::{grant_all, grantable_privileges, send_debug, unix_now, ChatSettings, Config, BOT};
+
+/// How often due reverts are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Schedule the anonymous admin to be reverted at `at` (unix timestamp in
+/// seconds), overwriting the existing schedule. Stored under
+/// `tempanon$<chat_id>$<user_id>` so it survives restarts.
+///
+/// # Errors
+/// If the insertion fails.
+pub fn schedule_revert(db: &Db, chat_id: ChatId, user_id: UserId, at: u64) -> Result<()> {
+    db.insert(make_key(chat_id, user_id), &at.to_be_bytes())?;
+    Ok(())
+}
+
+/// Cancel the scheduled revert, e.g. when reverted manually.
+///
+/// # Errors
+/// If the removal fails.
+pub fn cancel_revert(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<()> {
+    db.remove(make_key(chat_id, user_id))?;
+    Ok(())
+}
+
+/// When the anonymous admin is scheduled to be reverted, if at all.
+///
+/// # Errors
+/// If the database returns an error or the value is malformed.
+pub fn scheduled_revert(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Option<u64>> {
+    db.get(make_key(chat_id, user_id))?
+        .map(|value| {
+            Ok(u64::from_be_bytes(
+                (*value).try_into().wrap_err("Bad value")?,
+            ))
+        })
+        .transpose()
+}
+
+/// List members whose revert is due at `now`.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn due_reverts(db: &Db, now: u64) -> Result<Vec<(ChatId, UserId)>> {
+    let mut due = Vec::new();
+    for entry in db.scan_prefix("tempanon$") {
+        let (key, value) = entry?;
+        let at = u64::from_be_bytes((*value).try_into().wrap_err("Bad value")?);
+        if at <= now {
+            due.push(parse_key(&key)?);
+        }
+    }
+    Ok(due)
+}
+
+/// Revert all due members with `revert`, removing their schedule whether it
+/// succeeds or not, so a failing revert is not retried forever. Returns how
+/// many were reverted successfully.
+///
+/// # Errors
+/// If the database returns an error.
+pub async fn fire_due<Func, Fut>(db: &Db, now: u64, mut revert: Func) -> Result<usize>
+where
+    Func: FnMut(ChatId, UserId) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut reverted = 0;
+    for (chat_id, user_id) in due_reverts(db, now)? {
+        match revert(chat_id, user_id).await {
+            Ok(()) => reverted += 1,
+            Err(error) => send_debug(&error),
+        }
+        cancel_revert(db, chat_id, user_id)?;
+    }
+    Ok(reverted)
+}
+
+/// Revert due temporary anonymous admins periodically. Schedules missed while
+/// the bot was down are fired right after start. Never returns.
+///
+/// # Panics
+/// If the bot is not initialized.
+pub async fn run_temp_anon(db: Db) {
+    let bot = BOT.get().unwrap();
+    let mut interval = interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let fired = fire_due(&db, unix_now(), |chat_id, user_id| {
+            let settings = ChatSettings::load(&db, chat_id);
+            async move {
+                let settings = settings?;
+                let me = bot.get_chat_member(chat_id, bot.get_me().await?.id).await?;
+                let privileges = grantable_privileges(
+                    settings.promote_privileges(&Config::get().promote_privileges),
+                    &me.kind,
+                );
+                ensure!(
+                    !privileges.is_empty(),
+                    "Bot holds none of the privileges to grant, not reverting {user_id} in \
+                     {chat_id}"
+                );
+                grant_all(bot.promote_chat_member(chat_id, user_id), &privileges).await?;
+                Ok(())
+            }
+        })
+        .await;
+        match fired {
+            Ok(0) => {}
+            Ok(reverted) => info!(reverted, "Reverted temporary anonymous admins"),
+            Err(error) => send_debug(&error),
+        }
+    }
+}
+
+fn make_key(chat_id: ChatId, user_id: UserId) -> IVec {
+    format!("tempanon${}${}", chat_id, user_id)
+        .into_bytes()
+        .into()
+}
+
+fn parse_key(key: &IVec) -> Result<(ChatId, UserId)> {
+    let key = String::from_utf8(key.to_vec())?;
+    let mut iter = key.split('$');
+    ensure!(iter.next() == Some("tempanon"), "Bad key");
+    let chat_id = iter
+        .next()
+        .wrap_err("bad key")?
+        .parse::<i64>()
+        .map(ChatId)?;
+    let user_id = iter
+        .next()
+        .wrap_err("bad key")?
+        .parse::<u64>()
+        .map(UserId)?;
+    Ok((chat_id, user_id))
+}
+
+#[test]
+fn test_schedule_revert() {
+    let path = std::env::temp_dir().join(format!("golden-axe-tempanon-{}", unix_now()));
+
+    {
+        let db = sled::open(&path).unwrap();
+        schedule_revert(&db, ChatId(-1), UserId(2), 1000).unwrap();
+        schedule_revert(&db, ChatId(-1), UserId(3), 2000).unwrap();
+        assert_eq!(due_reverts(&db, 999).unwrap(), vec![]);
+        assert_eq!(
+            due_reverts(&db, 1000).unwrap(),
+            vec![(ChatId(-1), UserId(2))]
+        );
+    }
+
+    // Schedules survive a restart
+    let db = sled::open(&path).unwrap();
+    assert_eq!(due_reverts(&db, 2000).unwrap().len(), 2);
+
+    cancel_revert(&db, ChatId(-1), UserId(3)).unwrap();
+    assert_eq!(due_reverts(&db, 2000).unwrap().len(), 1);
+
+    drop(db);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_fire_due() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    schedule_revert(&db, ChatId(-1), UserId(2), 1000).unwrap();
+    schedule_revert(&db, ChatId(-1), UserId(3), 2000).unwrap();
+
+    let mut reverted = Vec::new();
+    let fired = fire_due(&db, 1500, |chat_id, user_id| {
+        reverted.push((chat_id, user_id));
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+    assert_eq!(fired, 1);
+    assert_eq!(reverted, vec![(ChatId(-1), UserId(2))]);
+
+    // Fired ones are not fired again
+    assert_eq!(due_reverts(&db, 1500).unwrap(), vec![]);
+    assert_eq!(due_reverts(&db, 2000).unwrap().len(), 1);
+}
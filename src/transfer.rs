@@ -0,0 +1,131 @@
+use color_eyre::{eyre::ensure, Result};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use teloxide::types::{ChatId, UserId};
+
+use crate::{list_protected, set_protected, ChatSettings, Reservation, TitleRecord, TitleStyle};
+
+/// State of a chat handed off to another instance of the bot, e.g. a backup
+/// taking over title management. Exported with `GET /transfer?chat_id=<id>`
+/// and imported with `POST /transfer`, both authorized by the transfer token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferBundle {
+    pub chat_id: ChatId,
+    pub records: Vec<TitleRecord>,
+    pub settings: ChatSettings,
+    pub reservations: Vec<Reservation>,
+    pub protected: Vec<String>,
+    /// Styles of users in the chat, missing in bundles of older instances
+    #[serde(default)]
+    pub styles: Vec<(UserId, TitleStyle)>,
+}
+
+impl TransferBundle {
+    /// Collect the state of the chat.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn export(db: &Db, chat_id: ChatId) -> Result<Self> {
+        Ok(Self {
+            chat_id,
+            records: TitleRecord::list_in_chat(db, chat_id)?,
+            settings: ChatSettings::load(db, chat_id)?,
+            reservations: Reservation::list_in_chat(db, chat_id)?,
+            protected: list_protected(db, chat_id)?,
+            styles: TitleStyle::list_in_chat(db, chat_id)?,
+        })
+    }
+
+    /// Restore the state of the chat. Records of the chat are replaced, while
+    /// reservations, protected titles and styles are added to the existing
+    /// ones, replacing the styles of the same users.
+    /// Returns the replaced records of users without a title in the bundle,
+    /// whose live titles are left to the caller to clear.
+    ///
+    /// # Errors
+    /// If any entry belongs to another chat, or the database returns an error.
+    pub fn import(&self, db: &Db) -> Result<Vec<TitleRecord>> {
+        ensure!(
+            self.records.iter().all(|x| x.chat_id == self.chat_id)
+                && self.reservations.iter().all(|x| x.chat_id == self.chat_id),
+            "Bundle contains entries of other chats"
+        );
+        let mut replaced = TitleRecord::list_in_chat(db, self.chat_id)?;
+        replaced.retain(|old| !self.records.iter().any(|new| new.user_id == old.user_id));
+        TitleRecord::remove_all_in_chat(db, self.chat_id)?;
+        for record in &self.records {
+            record.insert_into(db)?;
+        }
+        self.settings.save(db, self.chat_id)?;
+        for reservation in &self.reservations {
+            reservation.insert_into(db)?;
+        }
+        for title in &self.protected {
+            set_protected(db, self.chat_id, title, true)?;
+        }
+        for (user_id, style) in &self.styles {
+            style.save(db, self.chat_id, *user_id)?;
+        }
+        Ok(replaced)
+    }
+}
+
+#[test]
+fn test_transfer_bundle() {
+    let primary = sled::Config::new().temporary(true).open().unwrap();
+    let backup = sled::Config::new().temporary(true).open().unwrap();
+
+    let record = |chat_id, user_id, title: &str| TitleRecord {
+        title: title.to_owned(),
+        chat_id: ChatId(chat_id),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+    record(1, 2, "VIP").insert_into(&primary).unwrap();
+    record(1, 3, "Staff").insert_into(&primary).unwrap();
+    record(4, 2, "Other chat").insert_into(&primary).unwrap();
+    let mut settings = ChatSettings::default();
+    settings.set("title_prefix", "⭐").unwrap();
+    settings.save(&primary, ChatId(1)).unwrap();
+    let reservation = Reservation {
+        title: "Mod".to_owned(),
+        chat_id: ChatId(1),
+        user_id: UserId(5),
+    };
+    reservation.insert_into(&primary).unwrap();
+    set_protected(&primary, ChatId(1), "Staff", true).unwrap();
+    let style = TitleStyle {
+        color: None,
+        badge: Some("gold".to_owned()),
+    };
+    style.save(&primary, ChatId(1), UserId(2)).unwrap();
+    style.save(&primary, ChatId(4), UserId(2)).unwrap();
+
+    // Complete state of the chat, and nothing of others
+    let bundle = TransferBundle::export(&primary, ChatId(1)).unwrap();
+    assert_eq!(bundle.records.len(), 2);
+    assert!(bundle.records.contains(&record(1, 2, "VIP")));
+    assert_eq!(bundle.settings, settings);
+    assert_eq!(bundle.reservations, [reservation]);
+    assert_eq!(bundle.protected, ["Staff"]);
+    assert_eq!(bundle.styles, [(UserId(2), style)]);
+
+    // Restored by the backup after a round trip, replacing stale records
+    record(1, 6, "Stale").insert_into(&backup).unwrap();
+    record(1, 2, "Old VIP").insert_into(&backup).unwrap();
+    let json = serde_json::to_vec(&bundle).unwrap();
+    let replaced = serde_json::from_slice::<TransferBundle>(&json)
+        .unwrap()
+        .import(&backup)
+        .unwrap();
+    assert_eq!(replaced, [record(1, 6, "Stale")]);
+    assert_eq!(TransferBundle::export(&backup, ChatId(1)).unwrap(), bundle);
+    assert_eq!(
+        TitleRecord::get_with_id(&backup, ChatId(1), UserId(6)).unwrap(),
+        None
+    );
+
+    let mut foreign = bundle;
+    foreign.records.push(record(4, 2, "Other chat"));
+    assert!(foreign.import(&backup).is_err());
+}
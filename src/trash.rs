@@ -0,0 +1,203 @@
+use std::time::{Duration, SystemTime};
+
+use color_eyre::{
+    eyre::{Context, ContextCompat},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use sled::{Db, IVec};
+use teloxide::types::{ChatId, UserId};
+use tokio::time::interval;
+use tracing::info;
+
+use crate::{send_debug, Config, TitleRecord};
+
+/// How often expired records are purged from trash.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A removed record kept in trash, stored as JSON under
+/// `trash$<chat_id>$<title>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub record: TitleRecord,
+    /// Unix timestamp in seconds
+    pub removed_at: u64,
+}
+
+/// Move the record into trash, so it can be restored with [`restore_record`]
+/// within the retention period.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn trash_record(db: &Db, record: &TitleRecord, now: u64) -> Result<()> {
+    let entry = TrashEntry {
+        record: record.clone(),
+        removed_at: now,
+    };
+    db.insert(
+        make_trash_key(record.chat_id, &record.title),
+        serde_json::to_vec(&entry)?,
+    )?;
+    record.remove_from(db)
+}
+
+/// Take the record of the title out of trash and claim the title again.
+///
+/// # Errors
+/// If the title is not in trash or has expired, the title is in use, or the
+/// database returns an error.
+pub fn restore_record(
+    db: &Db,
+    chat_id: ChatId,
+    title: &str,
+    now: u64,
+    retention: Duration,
+) -> Result<TitleRecord> {
+    let key = make_trash_key(chat_id, title);
+    let entry = db
+        .get(&key)?
+        .map(|value| serde_json::from_slice::<TrashEntry>(&value))
+        .transpose()
+        .wrap_err("Bad trash entry")?
+        .filter(|entry| !is_expired(entry, now, retention))
+        .wrap_err("No such title in trash")?;
+    entry.record.claim(db)?;
+    db.remove(key)?;
+    Ok(entry.record)
+}
+
+/// Remove entries older than the retention period from trash, returns how
+/// many were purged.
+///
+/// # Errors
+/// If the database returns an error.
+pub fn purge_trash(db: &Db, now: u64, retention: Duration) -> Result<usize> {
+    let mut purged = 0;
+    for entry in db.scan_prefix("trash$") {
+        let (key, value) = entry?;
+        // Entries in bad shape cannot be restored anyway
+        let expired = serde_json::from_slice::<TrashEntry>(&value)
+            .map_or(true, |entry| is_expired(&entry, now, retention));
+        if expired {
+            db.remove(key)?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Records of the user in trash of the chat, including expired ones not yet
+/// purged.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn trashed_of(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<Vec<TrashEntry>> {
+    let mut entries = Vec::new();
+    for item in db.scan_prefix(format!("trash${chat_id}$")) {
+        let (_, value) = item?;
+        let entry: TrashEntry = serde_json::from_slice(&value).wrap_err("Bad trash entry")?;
+        if entry.record.user_id == user_id {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Remove records of the user from trash of the chat, so they cannot be
+/// restored. Returns how many were removed.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn forget_trashed(db: &Db, chat_id: ChatId, user_id: UserId) -> Result<usize> {
+    let entries = trashed_of(db, chat_id, user_id)?;
+    for entry in &entries {
+        db.remove(make_trash_key(chat_id, &entry.record.title))?;
+    }
+    Ok(entries.len())
+}
+
+/// Purge expired trash periodically when `soft_delete` is enabled. Never
+/// returns.
+pub async fn run_purge(db: Db) {
+    let conf = Config::get();
+    if !conf.soft_delete {
+        return std::future::pending().await;
+    }
+    let mut interval = interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match purge_trash(&db, unix_now(), conf.trash_retention) {
+            Ok(0) => {}
+            Ok(purged) => info!(purged, "Purged expired trash"),
+            Err(error) => send_debug(&error),
+        }
+    }
+}
+
+/// Current unix timestamp in seconds.
+#[must_use]
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Wrong system time config")
+        .as_secs()
+}
+
+fn is_expired(entry: &TrashEntry, now: u64, retention: Duration) -> bool {
+    now.saturating_sub(entry.removed_at) > retention.as_secs()
+}
+
+fn make_trash_key(chat_id: ChatId, title: &str) -> IVec {
+    format!("trash${}${}", chat_id, title).into_bytes().into()
+}
+
+#[test]
+fn test_trash() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let retention = Duration::from_secs(100);
+    let record = |title: &str, user_id| TitleRecord {
+        title: title.into(),
+        chat_id: ChatId(1),
+        user_id: UserId(user_id),
+        expires_at: None,
+    };
+
+    // Soft delete and restore
+    record("VIP", 2).insert_into(&db).unwrap();
+    trash_record(&db, &record("VIP", 2), 1000).unwrap();
+    assert_eq!(
+        TitleRecord::get_with_title(&db, ChatId(1), "VIP").unwrap(),
+        None
+    );
+    assert_eq!(
+        restore_record(&db, ChatId(1), "VIP", 1050, retention).unwrap(),
+        record("VIP", 2)
+    );
+    assert_eq!(
+        TitleRecord::get_with_id(&db, ChatId(1), UserId(2)).unwrap(),
+        Some(record("VIP", 2))
+    );
+    assert!(restore_record(&db, ChatId(1), "VIP", 1050, retention).is_err());
+
+    // Cannot restore after expiry, and purged
+    trash_record(&db, &record("VIP", 2), 1000).unwrap();
+    trash_record(&db, &record("Fresh", 3), 1090).unwrap();
+    assert_eq!(
+        restore_record(&db, ChatId(1), "VIP", 1101, retention)
+            .unwrap_err()
+            .to_string(),
+        "No such title in trash"
+    );
+    assert_eq!(purge_trash(&db, 1101, retention).unwrap(), 1);
+    assert!(restore_record(&db, ChatId(1), "Fresh", 1101, retention).is_ok());
+    assert_eq!(purge_trash(&db, 1101, retention).unwrap(), 0);
+
+    // Forgotten users lose their trash, others keep theirs
+    trash_record(&db, &record("Gone", 3), 1200).unwrap();
+    trash_record(&db, &record("Kept", 4), 1200).unwrap();
+    assert_eq!(trashed_of(&db, ChatId(1), UserId(3)).unwrap().len(), 1);
+    assert_eq!(forget_trashed(&db, ChatId(1), UserId(3)).unwrap(), 1);
+    assert!(trashed_of(&db, ChatId(1), UserId(3)).unwrap().is_empty());
+    assert!(restore_record(&db, ChatId(1), "Gone", 1200, retention).is_err());
+    assert_eq!(trashed_of(&db, ChatId(1), UserId(4)).unwrap().len(), 1);
+}
@@ -0,0 +1,258 @@
+use std::{future::Future, sync::OnceLock, time::Duration};
+
+use color_eyre::{eyre::Context, Result};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sled::Db;
+use teloxide::types::{ChatId, UserId};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{info, warn};
+
+use crate::{catch, retry, send_debug, unix_now, Config, TitleRecord};
+
+/// Header carrying the signature of the body, see [`sign`].
+pub const SIGNATURE_HEADER: &str = "X-Golden-Axe-Signature";
+
+/// Delay before the first retry of a delivery, doubled on each retry.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+static WEBHOOK_CHANNEL: OnceLock<Option<UnboundedSender<WebhookEvent>>> = OnceLock::new();
+
+/// A mutating action done by the bot, posted as JSON to `webhook_url`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// Name of the command, e.g. `title`, or `api_title` for `POST /title`
+    pub action: String,
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    /// Title of the user once the action is done, none if they hold none
+    pub title: Option<String>,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+impl WebhookEvent {
+    #[must_use]
+    pub fn new(action: impl Into<String>, chat_id: ChatId, user_id: UserId) -> Self {
+        Self {
+            action: action.into(),
+            chat_id,
+            user_id,
+            title: None,
+            timestamp: unix_now(),
+        }
+    }
+
+    /// Fill in the title the user holds now, to be called once the action is
+    /// done.
+    ///
+    /// # Errors
+    /// If the database returns an error or the data is not in good shape.
+    pub fn with_title_from(mut self, db: &Db) -> Result<Self> {
+        self.title =
+            TitleRecord::get_with_id(db, self.chat_id, self.user_id)?.map(|record| record.title);
+        Ok(self)
+    }
+}
+
+/// An event that could not be delivered, stored as JSON under
+/// `deadletter$<id>` for the operator to inspect or replay.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub event: WebhookEvent,
+    pub error: String,
+}
+
+/// Start posting events to `webhook_url` if configured. Refused without
+/// `webhook_secret`, as receivers could not tell events apart from forged
+/// ones.
+///
+/// # Panics
+/// When config cannot be parsed
+pub fn init(db: Db) {
+    WEBHOOK_CHANNEL.get_or_init(|| {
+        let conf = Config::get();
+        let url = conf.webhook_url.clone()?;
+        let Some(secret) = conf.webhook_secret.clone() else {
+            warn!("Webhook URL is set without a secret, no events will be posted");
+            send_debug(
+                &"Webhook URL is set without <code>webhook_secret</code>, no events will be posted",
+            );
+            return None;
+        };
+        let attempts = conf.webhook_attempts;
+        let (tx, mut rx) = unbounded_channel::<WebhookEvent>();
+
+        tokio::spawn(async move {
+            let client = teloxide::net::client_from_env();
+            while let Some(event) = rx.recv().await {
+                let post = |body: Vec<u8>, signature: String| {
+                    let req = client
+                        .post(&url)
+                        .header("Content-Type", "application/json")
+                        .header(SIGNATURE_HEADER, signature)
+                        .body(body);
+                    async move {
+                        req.send().await?.error_for_status()?;
+                        Result::<()>::Ok(())
+                    }
+                };
+                catch!(dispatch(&db, &event, &secret, attempts, RETRY_DELAY, post).await);
+            }
+        });
+
+        info!("Webhook worker initialized");
+
+        Some(tx)
+    });
+}
+
+/// Queue the event to be posted to `webhook_url`. Does nothing when no webhook
+/// is configured.
+pub fn emit_event(event: WebhookEvent) {
+    if let Some(Some(tx)) = WEBHOOK_CHANNEL.get() {
+        // Only closed when the worker panicked, which is already reported
+        drop(tx.send(event));
+    }
+}
+
+/// Signature of the body with the secret, as `sha256=<hex of HMAC-SHA256>`.
+#[must_use]
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, body);
+    let hex = tag
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("sha256={hex}")
+}
+
+/// Post the signed event with `post`, at most `attempts` times. The event is
+/// dead-lettered if all attempts fail.
+///
+/// # Errors
+/// If all attempts fail, or the database returns an error.
+async fn dispatch<Func, Fut>(
+    db: &Db,
+    event: &WebhookEvent,
+    secret: &str,
+    attempts: usize,
+    delay: Duration,
+    post: Func,
+) -> Result<()>
+where
+    Func: Fn(Vec<u8>, String) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let body = serde_json::to_vec(event)?;
+    let signature = sign(secret, &body);
+    if let Err(error) = retry(attempts, delay, || post(body.clone(), signature.clone())).await {
+        let letter = DeadLetter {
+            event: event.clone(),
+            error: error.to_string(),
+        };
+        db.insert(
+            format!("deadletter${:020}", db.generate_id()?),
+            serde_json::to_vec(&letter)?,
+        )?;
+        return Err(error).wrap_err_with(|| {
+            format!(
+                "Webhook event `{}` dead-lettered after {attempts} attempts",
+                event.action
+            )
+        });
+    }
+    Ok(())
+}
+
+/// Events that could not be delivered, oldest first.
+///
+/// # Errors
+/// If the database returns an error or the data is not in good shape.
+pub fn list_dead_letters(db: &Db) -> Result<Vec<DeadLetter>> {
+    db.scan_prefix("deadletter$")
+        .map(|item| -> Result<_> {
+            let (_, value) = item?;
+            serde_json::from_slice(&value).wrap_err("Bad dead letter")
+        })
+        .try_collect()
+}
+
+#[test]
+fn test_sign() {
+    // Test case 2 of RFC 4231
+    assert_eq!(
+        sign("Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn test_event_title() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let event = WebhookEvent::new("title", ChatId(-1), UserId(2));
+
+    assert_eq!(event.clone().with_title_from(&db).unwrap().title, None);
+    TitleRecord {
+        title: "VIP".to_owned(),
+        chat_id: ChatId(-1),
+        user_id: UserId(2),
+        expires_at: None,
+    }
+    .insert_into(&db)
+    .unwrap();
+    let event = event.with_title_from(&db).unwrap();
+    assert_eq!(event.title.as_deref(), Some("VIP"));
+    assert_eq!(serde_json::to_value(&event).unwrap()["title"], "VIP");
+}
+
+#[tokio::test]
+async fn test_dispatch() {
+    use std::sync::Mutex;
+
+    use color_eyre::eyre::bail;
+
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let event = WebhookEvent::new("title", ChatId(-1), UserId(2));
+
+    // Posted once with a valid signature
+    let posted = Mutex::new(Vec::new());
+    dispatch(
+        &db,
+        &event,
+        "secret",
+        3,
+        Duration::ZERO,
+        |body, signature| {
+            posted.lock().unwrap().push((body, signature));
+            async { Ok(()) }
+        },
+    )
+    .await
+    .unwrap();
+    let posted = posted.into_inner().unwrap();
+    assert_eq!(posted.len(), 1);
+    let (body, signature) = &posted[0];
+    assert_eq!(serde_json::from_slice::<WebhookEvent>(body).unwrap(), event);
+    assert_eq!(*signature, sign("secret", body));
+    assert!(list_dead_letters(&db).unwrap().is_empty());
+
+    // Retried, then dead-lettered
+    let attempts = Mutex::new(0);
+    let res = dispatch(&db, &event, "secret", 3, Duration::ZERO, |_, _| {
+        *attempts.lock().unwrap() += 1;
+        async { bail!("503 Service Unavailable") }
+    })
+    .await;
+    assert!(res.is_err());
+    assert_eq!(attempts.into_inner().unwrap(), 3);
+    assert_eq!(
+        list_dead_letters(&db).unwrap(),
+        [DeadLetter {
+            event,
+            error: "503 Service Unavailable".to_owned(),
+        }]
+    );
+}